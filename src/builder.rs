@@ -0,0 +1,377 @@
+//! Opening a datastore with options.
+//!
+//! See [`Datastore::builder`] for details.
+use crate::{Datastore, Error, cache, limits::Limits, manifest::Manifest, settings::Settings};
+use std::{
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+/// Path of the ignore file, relative to the root.
+pub const IGNORE_PATH: &str = ".datastoreignore";
+
+/// Ignore rules applied before any others unless [disabled](Builder::default_ignores).
+///
+/// These skip hidden files and directories, and the swap and backup files left by common editors.
+pub const DEFAULT_IGNORES: &str = ".*\n*~\n*.swp\n*.swo\n*.swx\n\\#*#\n";
+
+/// Options for opening a [`Datastore`], created by [`Datastore::builder`].
+#[derive(Debug, Clone)]
+pub struct Builder {
+    /// The filesystem root of the datastore.
+    root: PathBuf,
+    /// Ignore patterns added by the caller, applied after every other rule.
+    ignore: Vec<String>,
+    /// Whether [`DEFAULT_IGNORES`] apply.
+    default_ignores: bool,
+    /// Whether file access is confined to the root.
+    sandbox: bool,
+    /// Limits on the data files that are parsed.
+    limits: Limits,
+}
+
+impl Builder {
+    /// Ignore files and directories matching `pattern`, which uses gitignore syntax.
+    ///
+    /// Patterns are applied after those from the [manifest](crate::manifest) and
+    /// [ignore file](IGNORE_PATH), so a pattern starting with `!` can re-include paths they
+    /// ignore.
+    #[must_use]
+    pub fn ignore(mut self, pattern: &str) -> Self {
+        self.ignore.push(pattern.to_owned());
+        self
+    }
+
+    /// Set whether [`DEFAULT_IGNORES`] apply. On by default.
+    #[must_use]
+    pub fn default_ignores(mut self, default_ignores: bool) -> Self {
+        self.default_ignores = default_ignores;
+        self
+    }
+
+    /// Set whether file access is confined to the root. Off by default.
+    ///
+    /// Paths passed to [`Datastore::get_with_path`] and friends are joined to the root as given,
+    /// so an absolute path or one with `..` components can read any file, and so can a symlink
+    /// under the root, whether reached by path or by keypath. When sandboxed, such paths are
+    /// refused with [`Error::OutsideRoot`] instead: every file the datastore reads or writes, and
+    /// every directory it lists, must be a relative path without `..` components that, after
+    /// resolving symlinks, is inside the [canonical](std::fs::canonicalize) root. This covers
+    /// listing, walking, indexing and watching as well as reads and writes, and the manifest,
+    /// ignore file and [index cache](cache) read when opening. Use this when paths or keypaths
+    /// come from untrusted input.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use yaml_datastore::{Datastore, Error};
+    ///
+    /// let datastore = Datastore::builder("tests/data").sandbox(true).open();
+    /// let result = datastore.get_with_path::<_, String>("../../Cargo.toml");
+    /// assert!(matches!(result, Err(Error::OutsideRoot(_))));
+    /// ```
+    #[must_use]
+    pub fn sandbox(mut self, sandbox: bool) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Set the limits on each data file the datastore parses. There are none by default.
+    ///
+    /// The manifest, the [index cache](cache) and schema files are held to the same limits, and
+    /// the [ignore file](IGNORE_PATH) to the limit on size, so opening a datastore is bounded too.
+    ///
+    /// A file exceeding them is refused with [`Error::LimitExceeded`] wherever it would be read.
+    /// Like files that can't be parsed, it's indexed as empty and reported by
+    /// [`lint()`](Datastore::lint).
+    #[must_use]
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Open the datastore.
+    ///
+    /// See [`Datastore::open`] for what is read when opening. A missing or unreadable
+    /// [ignore file](IGNORE_PATH) is treated as empty, and an unusable manifest as missing, with
+    /// the error kept in [`Datastore::open_errors`].
+    #[must_use]
+    pub fn open(self) -> Datastore {
+        let mut errors = Vec::new();
+        let mut datastore = self.unconfigured();
+        let manifest = datastore.read_manifest().unwrap_or_else(|e| {
+            errors.push(e);
+            None
+        });
+        let ignore_file = datastore.read_ignore_file().unwrap_or_else(|e| {
+            errors.push(e);
+            None
+        });
+        self.configure(&mut datastore, manifest.as_ref(), ignore_file.as_deref());
+        if let Err(e) = datastore.refresh_cached_indexes() {
+            errors.push(e);
+        }
+        if let Some(manifest) = manifest {
+            errors.extend(datastore.apply_manifest(&manifest));
+        }
+        datastore.open_errors = errors;
+        datastore
+    }
+
+    /// Open the datastore, checking that its root and configuration are usable.
+    ///
+    /// See [`Datastore::try_open`] for details.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Datastore::try_open`].
+    pub fn try_open(mut self) -> Result<Datastore, Error> {
+        self.root = std::fs::canonicalize(&self.root)?;
+        // Listing the root fails if it isn't a directory or can't be read.
+        std::fs::read_dir(&self.root)?;
+        let mut datastore = self.unconfigured();
+        let manifest = datastore.read_manifest()?;
+        let ignore_file = datastore.read_ignore_file()?;
+        self.configure(&mut datastore, manifest.as_ref(), ignore_file.as_deref());
+        datastore.refresh_cached_indexes()?;
+        if let Some(manifest) = manifest
+            && let Some(error) = datastore.apply_manifest(&manifest).into_iter().next()
+        {
+            return Err(error);
+        }
+        Ok(datastore)
+    }
+
+    /// Create the datastore with only the options that govern reading files, so that its
+    /// configuration files are read within the same [sandbox](Self::sandbox) and
+    /// [limits](Self::limits) as its data files.
+    pub(crate) fn unconfigured(&self) -> Datastore {
+        Datastore {
+            root: self.root.clone(),
+            indexes: RwLock::default(),
+            schemas: RwLock::default(),
+            settings: Settings {
+                sandbox: self.sandbox,
+                limits: self.limits,
+                ..Settings::default()
+            },
+            open_errors: Vec::new(),
+        }
+    }
+
+    /// Apply the configuration to `datastore`, and load its [index cache](cache) without
+    /// refreshing it.
+    fn configure(
+        &self,
+        datastore: &mut Datastore,
+        manifest: Option<&Manifest>,
+        ignore_file: Option<&str>,
+    ) {
+        let settings = &mut datastore.settings;
+        if self.default_ignores {
+            settings.ignore.add_lines(DEFAULT_IGNORES);
+        }
+        if let Some(manifest) = manifest {
+            settings.extensions.clone_from(&manifest.extensions);
+            settings.resolution = manifest.resolution;
+            settings.ignore.add_lines(&manifest.ignore.join("\n"));
+        }
+        if let Some(contents) = ignore_file {
+            settings.ignore.add_lines(contents);
+        }
+        settings.ignore.add_lines(&self.ignore.join("\n"));
+        datastore.indexes = RwLock::new(cache::load(datastore).unwrap_or_default());
+    }
+}
+
+impl Datastore {
+    /// Read the [ignore file](IGNORE_PATH), returning `None` if there isn't one.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors from [`read_sized_file()`](Self::read_sized_file) other than a missing
+    /// file.
+    fn read_ignore_file(&self) -> Result<Option<String>, Error> {
+        match self.read_sized_file(Path::new(IGNORE_PATH)) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(Error::IOError(e)) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Bring indexes loaded from the [index cache](cache) up to date with the files on disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`refresh_indexes()`](Self::refresh_indexes).
+    fn refresh_cached_indexes(&self) -> Result<(), Error> {
+        if self.read_indexes().indexes.is_empty() {
+            return Ok(());
+        }
+        self.refresh_indexes()
+    }
+
+    /// Start building a handle to the datastore at the given path, to open it with options.
+    ///
+    /// Files and directories under the root can be excluded from the datastore with gitignore-style
+    /// patterns, which [`get()`](Datastore::get), [`list()`](Datastore::list),
+    /// [`walk()`](Datastore::walk), [`lint()`](Datastore::lint), indexing and every other
+    /// operation respect. Rules are applied in this order, with the last matching rule deciding:
+    ///
+    /// 1. [`DEFAULT_IGNORES`], unless [disabled](Builder::default_ignores).
+    /// 2. The `ignore` patterns in the [manifest](crate::manifest).
+    /// 3. The lines of the [ignore file](IGNORE_PATH) in the root.
+    /// 4. Patterns passed to [`Builder::ignore`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use yaml_datastore::Datastore;
+    ///
+    /// let datastore = Datastore::builder("tests/data").ignore("no_tags.yaml").open();
+    /// assert!(datastore.get::<String>("no_tags.name").is_err());
+    /// assert_eq!(datastore.get::<String>("complete.name").unwrap(), "Complete");
+    /// ```
+    pub fn builder<P: Into<PathBuf>>(path: P) -> Builder {
+        Builder {
+            root: path.into(),
+            ignore: Vec::new(),
+            default_ignores: true,
+            sandbox: false,
+            limits: Limits::default(),
+        }
+    }
+
+    /// Open a handle to the datastore at the given path, checking that it can be used.
+    ///
+    /// Unlike [`open()`](Self::open), which never fails and so leaves a mistyped root to surface
+    /// later as [`Error::KeyNotFound`], this checks that the root exists and is a readable
+    /// directory, and stores it [canonicalized](std::fs::canonicalize). The [manifest] and
+    /// [ignore file](IGNORE_PATH) are loaded if present, and any problem with them is an error
+    /// rather than being ignored. Use [`builder()`](Self::builder) and [`Builder::try_open`] to
+    /// open with options.
+    ///
+    /// [manifest]: crate::manifest
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use yaml_datastore::{Datastore, Error};
+    ///
+    /// let datastore = Datastore::try_open("tests/data").unwrap();
+    /// assert!(datastore.get::<bool>("complete.nested.value").unwrap());
+    /// assert!(matches!(Datastore::try_open("tests/dta"), Err(Error::IOError(_))));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IOError`] if the root doesn't exist, isn't a directory or can't be read,
+    /// if the manifest or ignore file exists but can't be read, or if indexes loaded from the
+    /// [index cache](crate::cache) can't be refreshed.
+    ///
+    /// Returns [`Error::DataParseError`] or [`Error::InvalidManifest`] if the manifest is invalid,
+    /// and any error from [`add_schema_file()`](Self::add_schema_file) or
+    /// [`create_index()`](Self::create_index) for the schemas and indexes it declares.
+    pub fn try_open<P: Into<PathBuf>>(path: P) -> Result<Datastore, Error> {
+        Self::builder(path).try_open()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn write(dir: &Path, path: &str, contents: &str) {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn ignore_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            IGNORE_PATH,
+            "# scratch data\nscratch/\n*.tmp.yaml\n",
+        );
+        write(dir.path(), "a.yaml", "x: 1");
+        write(dir.path(), "scratch/b.yaml", "x: 1");
+        write(dir.path(), "c.tmp.yaml", "x: 1");
+        write(dir.path(), "d.yaml", "x: 1");
+        write(dir.path(), "ci/out.yaml", "x: 1");
+        write(dir.path(), ".cache/e.yaml", "x: 1");
+        write(dir.path(), "a.yaml.swp", "x: 1");
+
+        let datastore = Datastore::builder(dir.path())
+            .ignore("ci/")
+            .ignore("d.yaml")
+            .open();
+        let names: Vec<String> = datastore
+            .list("")
+            .unwrap()
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(names, ["a"]);
+        assert!(datastore.get::<i64>("scratch.b.x").is_err());
+        assert!(datastore.get::<i64>("d.x").is_err());
+        let walked: Vec<_> = datastore.walk().include_hidden(true).collect();
+        assert_eq!(walked.len(), 1);
+        assert!(datastore.lint().unwrap().is_empty());
+
+        let datastore = Datastore::builder(dir.path()).ignore("!d.yaml").open();
+        assert_eq!(datastore.get::<i64>("d.x").unwrap(), 1);
+        assert_eq!(datastore.get::<i64>("ci.out.x").unwrap(), 1);
+    }
+
+    #[test]
+    fn try_open() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a/b.yaml", "x: 1");
+        let datastore = Datastore::try_open(dir.path().join("a/../a")).unwrap();
+        assert_eq!(datastore.root, dir.path().canonicalize().unwrap().join("a"));
+        assert_eq!(datastore.get::<i64>("b.x").unwrap(), 1);
+
+        let missing = Datastore::try_open(dir.path().join("missing"));
+        assert!(
+            matches!(missing, Err(Error::IOError(e)) if e.kind() == std::io::ErrorKind::NotFound)
+        );
+        assert!(matches!(
+            Datastore::try_open(dir.path().join("a/b.yaml")),
+            Err(Error::IOError(_))
+        ));
+
+        write(dir.path(), "a/.yaml-datastore.yaml", "extensions: []");
+        assert!(matches!(
+            Datastore::try_open(dir.path().join("a")),
+            Err(Error::InvalidManifest(_))
+        ));
+        write(
+            dir.path(),
+            "a/.yaml-datastore.yaml",
+            "schemas: {b: missing.json}",
+        );
+        assert!(matches!(
+            Datastore::try_open(dir.path().join("a")),
+            Err(Error::IOError(_))
+        ));
+        // The lazy constructor carries on, keeping the error.
+        let datastore = Datastore::open(dir.path().join("a"));
+        assert_eq!(datastore.get::<i64>("b.x").unwrap(), 1);
+        assert!(matches!(datastore.open_errors(), [Error::IOError(_)]));
+
+        write(
+            dir.path(),
+            "a/.yaml-datastore.yaml",
+            "extensions: [yml]\nunknown: 1",
+        );
+        let datastore = Datastore::open(dir.path().join("a"));
+        assert_eq!(datastore.get::<i64>("b.x").unwrap(), 1);
+        assert!(matches!(
+            datastore.open_errors(),
+            [Error::DataParseError(_)]
+        ));
+    }
+}
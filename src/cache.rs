@@ -0,0 +1,153 @@
+//! Persistent on-disk cache of the datastore's indexes.
+//!
+//! Building an index requires parsing every file in the datastore, which can be slow for large
+//! datastores. To avoid doing so on every start, indexes can be saved with
+//! [`Datastore::save_index_cache`](crate::Datastore::save_index_cache) to a sidecar file at
+//! [`CACHE_PATH`] under the datastore root. When a datastore is [opened](crate::Datastore::open),
+//! that file is loaded if present, and only the files whose [stamp](FileStamp) has changed since
+//! the cache was written are reparsed.
+//!
+//! The cache is strictly an optimization: if it is missing, corrupt, beyond the datastore's
+//! [limits](crate::limits), or written by an incompatible version of this crate, it is ignored and
+//! indexes are built from scratch as usual.
+use crate::{Datastore, Error, index::Indexes};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::Write,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+/// Directory under the datastore root holding sidecar files. It is never treated as data.
+pub const SIDECAR_DIR: &str = ".yaml-datastore";
+
+/// Path of the index cache relative to the datastore root.
+pub const CACHE_PATH: &str = ".yaml-datastore/index";
+
+/// Version of the cache format. Caches with a different version are ignored.
+const CACHE_VERSION: u32 = 1;
+
+/// Files modified this recently when stamped can't be trusted by metadata alone.
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+/// Metadata used to decide whether a file needs to be reparsed.
+///
+/// The modification time and length are checked first since they're cheap. Only if either differs
+/// is the file read and its content hash compared, so a file that was touched but not changed is
+/// still not reparsed.
+///
+/// Filesystem timestamps can be coarse, so a file rewritten with the same length shortly after
+/// being stamped may keep its modification time. Stamps of such recently modified files are marked
+/// racy, and always have their contents compared.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FileStamp {
+    /// Last modification time of the file.
+    modified: SystemTime,
+    /// Length of the file in bytes.
+    len: u64,
+    /// Hash of the file's contents.
+    hash: u64,
+    /// Whether the file was modified too recently for its metadata to be trusted.
+    racy: bool,
+}
+
+impl FileStamp {
+    /// Create a stamp for a file from its metadata and contents.
+    pub(crate) fn new(metadata: &std::fs::Metadata, contents: &[u8]) -> std::io::Result<Self> {
+        let modified = metadata.modified()?;
+        let racy = SystemTime::now()
+            .duration_since(modified)
+            .map_or(true, |age| age < RACY_WINDOW);
+        Ok(Self {
+            modified,
+            len: metadata.len(),
+            hash: content_hash(contents),
+            racy,
+        })
+    }
+
+    /// Return whether the file's metadata still matches this stamp.
+    pub(crate) fn matches_metadata(&self, metadata: &std::fs::Metadata) -> bool {
+        !self.racy
+            && metadata.len() == self.len
+            && metadata.modified().is_ok_and(|m| m == self.modified)
+    }
+
+    /// Return whether the file's contents still match this stamp.
+    pub(crate) fn matches_contents(&self, contents: &[u8]) -> bool {
+        content_hash(contents) == self.hash
+    }
+}
+
+/// Hash file contents with 64-bit FNV-1a.
+///
+/// The standard library's hashers aren't guaranteed to be stable between releases, which would
+/// silently invalidate caches, so a fixed algorithm is used instead.
+fn content_hash(contents: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+    contents.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+    })
+}
+
+/// On-disk format of the cache, as read.
+#[derive(Deserialize)]
+struct CacheFile {
+    /// Format version, compared against [`CACHE_VERSION`].
+    version: u32,
+    /// The cached indexes.
+    indexes: Indexes,
+}
+
+/// On-disk format of the cache, as written.
+#[derive(Serialize)]
+struct CacheFileRef<'a> {
+    /// Format version, always [`CACHE_VERSION`].
+    version: u32,
+    /// The indexes to cache.
+    indexes: &'a Indexes,
+}
+
+/// Load the index cache for `datastore`, if there is a usable one.
+pub(crate) fn load(datastore: &Datastore) -> Option<Indexes> {
+    let contents = datastore.read_data_file(Path::new(CACHE_PATH)).ok()?;
+    let cache: CacheFile = serde_yaml::from_str(&contents).ok()?;
+    (cache.version == CACHE_VERSION).then_some(cache.indexes)
+}
+
+/// Save `indexes` as the index cache for `datastore`.
+///
+/// The cache is written to a temporary file and then renamed into place, so a crash while saving
+/// never leaves a truncated cache behind.
+pub(crate) fn save(datastore: &Datastore, indexes: &Indexes) -> Result<(), Error> {
+    let contents = serde_yaml::to_string(&CacheFileRef {
+        version: CACHE_VERSION,
+        indexes,
+    })?;
+    std::fs::create_dir_all(datastore.confine(Path::new(SIDECAR_DIR))?)?;
+    let path = datastore.confine(Path::new(CACHE_PATH))?;
+    let temporary = datastore.confine(&Path::new(CACHE_PATH).with_extension("tmp"))?;
+    let mut file = std::fs::File::create(&temporary)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&temporary, &path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_is_stable() {
+        assert_eq!(content_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(content_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn missing_cache_is_ignored() {
+        let datastore = Datastore::open(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data"));
+        assert!(load(&datastore).is_none());
+    }
+}
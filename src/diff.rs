@@ -0,0 +1,176 @@
+//! Comparing two datastores keypath by keypath.
+//!
+//! See [`diff()`] for details.
+use crate::{Datastore, Error, keypath::KeyPath};
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+
+/// How the value at a keypath differs between two datastores.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum Change {
+    /// The keypath only exists in the new datastore.
+    Added {
+        /// The value in the new datastore.
+        new: Value,
+    },
+    /// The keypath only exists in the old datastore.
+    Removed {
+        /// The value in the old datastore.
+        old: Value,
+    },
+    /// The keypath exists in both datastores with different values.
+    Changed {
+        /// The value in the old datastore.
+        old: Value,
+        /// The value in the new datastore.
+        new: Value,
+    },
+}
+
+/// A single keypath that differs between two datastores, as returned by [`diff()`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Difference {
+    /// The keypath that differs.
+    pub keypath: KeyPath,
+    /// How it differs.
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+/// Flatten `datastore`, keying it by parsed keypaths so entries sort component by component.
+fn flatten_keypaths(datastore: &Datastore) -> Result<BTreeMap<KeyPath, Value>, Error> {
+    datastore
+        .flatten()?
+        .into_iter()
+        .map(|(keypath, value)| Ok((KeyPath::try_from(keypath)?, value)))
+        .collect()
+}
+
+/// Compare two datastores, returning every leaf keypath whose value differs.
+///
+/// Both datastores are [flattened](Datastore::flatten) first, so the comparison is independent of
+/// how data is split across files: moving the key `b` of `a.yaml` into `a/b.yaml` produces no
+/// differences. Leaves are compared as `flatten` produces them, so a changed sequence element is
+/// reported by its index, and a mapping replaced by a scalar is reported as each of its leaves
+/// being removed and the scalar added.
+///
+/// Differences are returned sorted by keypath.
+///
+/// # Examples
+///
+/// ```no_run
+/// use yaml_datastore::{Datastore, diff};
+///
+/// let old = Datastore::open("release-1");
+/// let new = Datastore::open("release-2");
+/// for difference in diff(&old, &new).unwrap() {
+///     println!("{}: {:?}", difference.keypath, difference.change);
+/// }
+/// ```
+///
+/// # Errors
+///
+/// Returns [`Error::Walk`] for the first file or directory in either datastore that cannot be
+/// read or parsed.
+pub fn diff(old: &Datastore, new: &Datastore) -> Result<Vec<Difference>, Error> {
+    let mut old = flatten_keypaths(old)?;
+    let mut differences = Vec::new();
+    for (keypath, new) in flatten_keypaths(new)? {
+        let change = match old.remove(&keypath) {
+            None => Change::Added { new },
+            Some(old) if old != new => Change::Changed { old, new },
+            Some(_) => continue,
+        };
+        differences.push(Difference { keypath, change });
+    }
+    differences.extend(old.into_iter().map(|(keypath, old)| Difference {
+        keypath,
+        change: Change::Removed { old },
+    }));
+    differences.sort_by(|a, b| a.keypath.cmp(&b.keypath));
+    Ok(differences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn write(dir: &Path, path: &str, contents: &str) {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn diff_ignores_layout() {
+        let old = tempfile::tempdir().unwrap();
+        let new = tempfile::tempdir().unwrap();
+        write(old.path(), "a.yaml", "b: {x: 1, y: 2}\nc: true");
+        write(new.path(), "a.yaml", "c: true");
+        write(new.path(), "a/b.yaml", "x: 1\ny: 2");
+        let differences = diff(&Datastore::open(old.path()), &Datastore::open(new.path())).unwrap();
+        assert!(differences.is_empty());
+    }
+
+    #[test]
+    fn diff_changes() {
+        let old = tempfile::tempdir().unwrap();
+        let new = tempfile::tempdir().unwrap();
+        write(old.path(), "a.yaml", "same: 1\nchanged: 1\nremoved: 1");
+        write(new.path(), "a.yaml", "same: 1\nchanged: 2\nadded: 1");
+        let differences = diff(&Datastore::open(old.path()), &Datastore::open(new.path())).unwrap();
+        let expected = vec![
+            Difference {
+                keypath: KeyPath::try_from("a.added").unwrap(),
+                change: Change::Added {
+                    new: Value::from(1),
+                },
+            },
+            Difference {
+                keypath: KeyPath::try_from("a.changed").unwrap(),
+                change: Change::Changed {
+                    old: Value::from(1),
+                    new: Value::from(2),
+                },
+            },
+            Difference {
+                keypath: KeyPath::try_from("a.removed").unwrap(),
+                change: Change::Removed {
+                    old: Value::from(1),
+                },
+            },
+        ];
+        assert_eq!(differences, expected);
+
+        let json = serde_json::to_string(&differences[1]).unwrap();
+        assert_eq!(
+            json,
+            r#"{"keypath":"a.changed","change":"changed","old":1,"new":2}"#
+        );
+    }
+
+    #[test]
+    fn diff_partially_shadowed_file() {
+        // `a/b.yaml` shadows `a.b.c`, but `a.b.d` still resolves through `a.yaml`.
+        let old = tempfile::tempdir().unwrap();
+        let new = tempfile::tempdir().unwrap();
+        write(old.path(), "a.yaml", "b: {c: 1, d: 2}");
+        write(old.path(), "a/b.yaml", "c: 3");
+        write(new.path(), "a.yaml", "b: {c: 1, d: 4}");
+        write(new.path(), "a/b.yaml", "c: 3");
+        let differences = diff(&Datastore::open(old.path()), &Datastore::open(new.path())).unwrap();
+        assert_eq!(
+            differences,
+            vec![Difference {
+                keypath: KeyPath::try_from("a.b.d").unwrap(),
+                change: Change::Changed {
+                    old: Value::from(2),
+                    new: Value::from(4),
+                },
+            }]
+        );
+    }
+}
@@ -0,0 +1,454 @@
+//! Converting between a datastore and a single YAML or JSON document.
+//!
+//! See [`Datastore::export`] and [`Datastore::import`] for details.
+use crate::{
+    Datastore, Error,
+    cache::SIDECAR_DIR,
+    keypath::{KeyPath, KeyPathParseError, is_valid_component},
+    transaction::Transaction,
+    value_at,
+    walk::{WalkError, collect_leaves},
+};
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// Document format for [`Datastore::export`] and [`Datastore::import`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Format {
+    /// A YAML document.
+    Yaml,
+    /// A pretty-printed JSON document.
+    Json,
+}
+
+impl Format {
+    /// Serialize `value` to `writer` in this format.
+    pub(crate) fn write<W: Write>(self, writer: W, value: &Value) -> Result<(), Error> {
+        match self {
+            Self::Yaml => serde_yaml::to_writer(writer, value)?,
+            Self::Json => serde_json::to_writer_pretty(writer, value)?,
+        }
+        Ok(())
+    }
+
+    /// Deserialize a value from `reader` in this format.
+    pub(crate) fn read<R: Read>(self, reader: R) -> Result<Value, Error> {
+        Ok(match self {
+            Self::Yaml => serde_yaml::from_reader(reader)?,
+            Self::Json => serde_json::from_reader(reader)?,
+        })
+    }
+}
+
+/// Plan the files and directories needed to store `mapping` in the directory `dir`.
+///
+/// Keys of `mapping` become directories while `depth` is greater than 1, and files with the given
+/// extension otherwise.
+fn plan_split(
+    dir: &Path,
+    mapping: Mapping,
+    depth: usize,
+    extension: &str,
+    directories: &mut Vec<PathBuf>,
+    files: &mut Vec<(PathBuf, Value)>,
+) -> Result<(), Error> {
+    for (key, value) in mapping {
+        let name = key
+            .as_str()
+            .filter(|k| is_valid_component(k))
+            .ok_or(KeyPathParseError::InvalidKeyPath)?;
+        let path = dir.join(name);
+        match value {
+            Value::Mapping(mapping) if depth > 1 => {
+                directories.push(path.clone());
+                plan_split(&path, mapping, depth - 1, extension, directories, files)?;
+            }
+            value => files.push((path.with_extension(extension), value)),
+        }
+    }
+    Ok(())
+}
+
+/// Merge `overlay` into `base`, with values from `overlay` taking precedence.
+///
+/// Mappings are merged key by key, recursively. Any other value in `overlay` replaces the value in
+/// `base` outright.
+pub(crate) fn deep_merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => deep_merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// [Merge](deep_merge) `overlay` into `base`, or set `base` to it if there is nothing to merge into.
+pub(crate) fn merge_into(base: &mut Option<Value>, overlay: Value) {
+    match base {
+        Some(base) => deep_merge(base, overlay),
+        None => *base = Some(overlay),
+    }
+}
+
+/// Everything in a directory that contributes to a single name.
+#[derive(Default)]
+struct Sources {
+    /// Data files named by this name, in increasing order of precedence.
+    files: Vec<PathBuf>,
+    /// A subdirectory with this name.
+    directory: Option<PathBuf>,
+}
+
+impl Datastore {
+    /// Read and parse the data file at `path`, naming the file in any error.
+    fn read_value_in_walk(&self, path: &Path) -> Result<Value, Error> {
+        self.read_value(path).map_err(|error| {
+            WalkError {
+                path: path.to_owned(),
+                error,
+            }
+            .into()
+        })
+    }
+
+    /// Build a mapping from the contents of the directory at `dir`, relative to the root.
+    ///
+    /// Each data file becomes a key named by its stem, and each subdirectory a nested mapping.
+    /// Where a file and directory share a name, or a name has both extensions, their contents are
+    /// [merged](deep_merge) so that every keypath has the value [`Datastore::get`] would give it.
+    pub(crate) fn materialize_directory(&self, dir: &Path) -> Result<Value, Error> {
+        let read_error = |error: Error| WalkError {
+            path: dir.to_owned(),
+            error,
+        };
+        let mut sources: BTreeMap<String, Sources> = BTreeMap::new();
+        for entry in self.read_directory(dir).map_err(read_error)? {
+            let path = dir.join(entry.file_name());
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            if path == Path::new(SIDECAR_DIR) {
+                continue;
+            }
+            let is_dir = entry
+                .file_type()
+                .map_err(|e| read_error(e.into()))?
+                .is_dir();
+            if self.is_ignored(&path, is_dir) {
+                continue;
+            }
+            if is_dir {
+                if is_valid_component(name) {
+                    sources.entry(name.to_owned()).or_default().directory = Some(path);
+                }
+            } else if let Some(stem) = path.file_stem().and_then(|s| s.to_str())
+                && is_valid_component(stem)
+                && self.has_data_extension(&path)
+            {
+                sources.entry(stem.to_owned()).or_default().files.push(path);
+            }
+        }
+
+        let mut mapping = Mapping::new();
+        for (name, mut sources) in sources {
+            // Extensions earlier in the list take precedence, so they are merged last.
+            sources.files.sort_by_key(|path| {
+                std::cmp::Reverse(
+                    self.extensions()
+                        .iter()
+                        .position(|e| path.extension().is_some_and(|p| p == e.as_str())),
+                )
+            });
+            let mut value = None;
+            for file in &sources.files {
+                merge_into(&mut value, self.read_value_in_walk(file)?);
+            }
+            let directory = match &sources.directory {
+                Some(directory) => Some(self.materialize_directory(directory)?),
+                None => None,
+            };
+            let value = self.merge_by_depth(value, directory);
+            mapping.insert(name.into(), value.unwrap_or_default());
+        }
+        Ok(Value::Mapping(mapping))
+    }
+
+    /// Build the complete value at `keypath`, or at the root if it is `None`.
+    ///
+    /// Unlike [`Datastore::get_value`], which stops at the first file that resolves the keypath,
+    /// this merges every file and directory that contributes to it, as described in
+    /// [`Datastore::export`].
+    pub(crate) fn materialize(&self, keypath: Option<&KeyPath>) -> Result<Value, Error> {
+        let Some(keypath) = keypath else {
+            return self.materialize_directory(Path::new(""));
+        };
+
+        let mut value = None;
+        // Candidates are visited from lowest to highest precedence.
+        for (path, keys) in self.candidates(keypath).rev() {
+            if self.is_file(&path)
+                && let Some(contents) = value_at(self.read_value_in_walk(&path)?, &keys)
+            {
+                merge_into(&mut value, contents);
+            }
+        }
+        let directory: PathBuf = keypath.components().iter().collect();
+        if self.is_dir(&directory) && !self.is_ignored(&directory, true) {
+            value = self.merge_by_depth(value, Some(self.materialize_directory(&directory)?));
+        }
+        value.ok_or(Error::KeyNotFound)
+    }
+
+    /// Collect every leaf of the complete value at `keypath`, or at the root if it is `None`,
+    /// along with the keypath addressing it.
+    ///
+    /// These are the leaves of the value [`materialize()`](Self::materialize) builds. A keypath
+    /// with nothing at it has no leaves.
+    pub(crate) fn materialize_leaves(
+        &self,
+        keypath: Option<&KeyPath>,
+    ) -> Result<BTreeMap<KeyPath, Value>, Error> {
+        let mut leaves = VecDeque::new();
+        match keypath {
+            Some(keypath) => match self.materialize(Some(keypath)) {
+                Ok(value) => collect_leaves(keypath.clone(), value, &mut leaves),
+                Err(Error::KeyNotFound) => {}
+                Err(e) => return Err(e),
+            },
+            None => {
+                if let Value::Mapping(mapping) = self.materialize(None)? {
+                    for (key, value) in mapping {
+                        if let Some(key) = key.as_str()
+                            && let Ok(keypath) = KeyPath::try_from(key)
+                        {
+                            collect_leaves(keypath, value, &mut leaves);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(leaves.into_iter().collect())
+    }
+
+    /// Export the entire datastore as a single document.
+    ///
+    /// Directories become mappings keyed by entry name, and files their contents keyed by stem.
+    /// Where files and directories contribute to the same keypath, mappings are merged key by key,
+    /// and anything else is replaced outright by whichever [`get()`](Datastore::get) tries first.
+    /// So every leaf of the document has the value `get()` gives it, and the leaves are exactly
+    /// those of [`flatten()`](Datastore::flatten). A mapping, though, holds the keys of every file
+    /// contributing to it, whereas `get()` returns the mapping of the first file only: with
+    /// `a.yaml` containing `b: {c: 1}` and `a/b.yaml` containing `d: 2`, the document has
+    /// `a.b: {c: 1, d: 2}`, while `get("a.b")` is `{d: 2}`. [Ignored](Datastore::builder) files and
+    /// directories, which include hidden ones by default, are skipped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use yaml_datastore::{Datastore, document::Format};
+    ///
+    /// let datastore = Datastore::open("tests/data");
+    /// let mut output = Vec::new();
+    /// datastore.export_keypath("complete.nested", &mut output, Format::Json).unwrap();
+    /// assert_eq!(String::from_utf8(output).unwrap(), "{\n  \"value\": true\n}");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Walk`] naming the first file or directory that cannot be read or parsed.
+    ///
+    /// Returns [`Error::DataParseError`] or [`Error::JsonError`] if the document can't be written,
+    /// for example because JSON doesn't allow non-string keys.
+    pub fn export<W: Write>(&self, writer: W, format: Format) -> Result<(), Error> {
+        format.write(writer, &self.materialize(None)?)
+    }
+
+    /// Export the value at a keypath as a single document.
+    ///
+    /// This is the same as [`export()`](Datastore::export), but rooted at `keypath`. Every file and
+    /// directory contributing to the keypath is included, not just the first one that resolves it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::KeyPathError`] if `keypath` is invalid.
+    ///
+    /// Returns [`Error::KeyNotFound`] if nothing exists at `keypath`.
+    ///
+    /// Otherwise, returns the same errors as [`export()`](Datastore::export).
+    pub fn export_keypath<W: Write>(
+        &self,
+        keypath: &str,
+        writer: W,
+        format: Format,
+    ) -> Result<(), Error> {
+        let keypath = KeyPath::try_from(keypath)?;
+        format.write(writer, &self.materialize(Some(&keypath))?)
+    }
+}
+
+impl Datastore {
+    /// Import a single document, splitting it into files and directories under the root.
+    ///
+    /// This is the inverse of [`export()`](Datastore::export). The document's root must be a
+    /// mapping. Its keys are split into files and directories according to `depth`:
+    ///
+    /// * With a depth of 1, each top-level key becomes a file, so `{a: {b: {c: 1}}}` is written to
+    ///   `a.yaml` containing `{b: {c: 1}}`.
+    /// * With a depth of 2, each top-level key becomes a directory and each second-level key a
+    ///   file, so the same document is written to `a/b.yaml` containing `{c: 1}`.
+    /// * Deeper splits continue in the same way. A depth of 0 is treated as 1.
+    ///
+    /// Values that aren't mappings always become files, whatever the depth. Either way, every
+    /// keypath resolves to the same value it has in the document. Files that already exist are
+    /// overwritten, and other files are left untouched.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::DataParseError`] or [`Error::JsonError`] if the document can't be parsed.
+    ///
+    /// Returns [`Error::NotAMapping`] if the document's root is not a mapping.
+    ///
+    /// Returns [`Error::KeyPathError`] if a key that would become a file or directory name is not a
+    /// valid keypath component. Nothing is written in either case.
+    ///
+    /// Returns [`Error::IOError`] if a file or directory can't be written, in which case any files
+    /// and directories already written are restored.
+    ///
+    /// Returns [`Error::SchemaViolation`] as described in
+    /// [`enforce_schemas()`](Datastore::enforce_schemas).
+    pub fn import<R: Read>(&self, reader: R, format: Format, depth: usize) -> Result<(), Error> {
+        let Value::Mapping(mapping) = format.read(reader)? else {
+            return Err(Error::NotAMapping);
+        };
+        let mut directories = Vec::new();
+        let mut files = Vec::new();
+        plan_split(
+            Path::new(""),
+            mapping,
+            depth.max(1),
+            self.write_extension(),
+            &mut directories,
+            &mut files,
+        )?;
+        let mut transaction = Transaction::new(self);
+        for directory in directories {
+            transaction.create_directory(&directory)?;
+        }
+        for (path, value) in files {
+            transaction.write(&path, &value)?;
+        }
+        transaction.commit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, path: &str, contents: &str) {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn export_merges_files_and_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.yaml", "b: {c: 1, d: 2}\ne: 3");
+        write(dir.path(), "a.yml", "e: 4\nf: 5");
+        write(dir.path(), "a/b.yaml", "c: 6");
+        write(dir.path(), ".hidden/x.yaml", "x: 1");
+
+        let datastore = Datastore::open(dir.path());
+        let mut output = Vec::new();
+        datastore.export(&mut output, Format::Yaml).unwrap();
+        let exported: Value = serde_yaml::from_slice(&output).unwrap();
+        let expected: Value = serde_yaml::from_str("a: {e: 3, f: 5, b: {c: 6, d: 2}}").unwrap();
+        assert_eq!(exported, expected);
+
+        // Every leaf has the value `get()` gives it, and they're the leaves `flatten()` gives.
+        let flat = datastore.flatten().unwrap();
+        assert_eq!(flat.len(), 4);
+        for (keypath, value) in &flat {
+            assert_eq!(&datastore.get_value(keypath).unwrap(), value, "{keypath}");
+        }
+        // A merged mapping has more keys than the file `get()` resolves it to.
+        let b: Value = serde_yaml::from_str("c: 6").unwrap();
+        assert_eq!(datastore.get_value("a.b").unwrap(), b);
+    }
+
+    #[test]
+    fn export_keypath_json() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.yaml", "b: {c: 1}");
+        write(dir.path(), "a/b/d.yaml", "[1, 2]");
+
+        let mut output = Vec::new();
+        Datastore::open(dir.path())
+            .export_keypath("a.b", &mut output, Format::Json)
+            .unwrap();
+        let exported: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(exported, serde_json::json!({"c": 1, "d": [1, 2]}));
+    }
+
+    #[test]
+    fn export_reports_bad_file() {
+        let datastore = Datastore::open(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data"));
+        let result = datastore.export(std::io::sink(), Format::Yaml).unwrap_err();
+        let Error::Walk(error) = result else {
+            panic!("expected a walk error, got {result:?}");
+        };
+        assert_eq!(error.path, Path::new("duplicate.yaml"));
+    }
+
+    #[test]
+    fn import_split_depths() {
+        let document = "a: {b: {c: 1}, d: [1, 2]}\ne: 3\nempty: {}";
+        let cases = [
+            (1, vec!["a.yaml", "e.yaml", "empty.yaml"]),
+            (2, vec!["a/b.yaml", "a/d.yaml", "e.yaml"]),
+            (3, vec!["a/b/c.yaml", "a/d.yaml", "e.yaml"]),
+        ];
+        for (depth, expected_files) in cases {
+            let dir = tempfile::tempdir().unwrap();
+            let datastore = Datastore::open(dir.path());
+            datastore
+                .import(document.as_bytes(), Format::Yaml, depth)
+                .unwrap();
+            for file in expected_files {
+                assert!(dir.path().join(file).is_file(), "{file} at depth {depth}");
+            }
+            assert_eq!(datastore.get::<u64>("a.b.c").unwrap(), 1);
+
+            let mut output = Vec::new();
+            datastore.export(&mut output, Format::Yaml).unwrap();
+            let exported: Value = serde_yaml::from_slice(&output).unwrap();
+            let original: Value = serde_yaml::from_str(document).unwrap();
+            assert_eq!(exported, original);
+        }
+    }
+
+    #[test]
+    fn import_rejects_invalid_documents() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = Datastore::open(dir.path());
+        let result = datastore.import("[1, 2]".as_bytes(), Format::Json, 1);
+        assert!(matches!(result.unwrap_err(), Error::NotAMapping));
+        let result = datastore.import(r#"{"a.b": 1}"#.as_bytes(), Format::Json, 1);
+        assert!(matches!(result.unwrap_err(), Error::KeyPathError(_)));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
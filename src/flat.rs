@@ -0,0 +1,325 @@
+//! Flattening a datastore into a map of keypaths to leaf values, and rebuilding one from it.
+//!
+//! A flat map is independent of how data is split across files, which makes it convenient for
+//! diffing, searching, or feeding into key-value systems. See [`Datastore::flatten`] and
+//! [`Datastore::import_flat`].
+use crate::{Datastore, Error, keypath::KeyPath, set_value_at, transaction::Transaction};
+use serde_yaml::Value;
+use std::{
+    collections::{BTreeMap, btree_map::Entry},
+    path::{Path, PathBuf},
+};
+
+/// Policy deciding which file each keypath is written to when importing data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Layout {
+    /// Split every keypath at a fixed depth.
+    ///
+    /// The first `n` components name the file, and the rest are keys within it. With a depth of
+    /// 1, `a.b.c` is written to the key `b.c` of `a.yaml`, and with a depth of 2 to the key `c` of
+    /// `a/b.yaml`. Keypaths with `n` or fewer components become a whole file. A depth of 0 is
+    /// treated as 1.
+    Depth(usize),
+
+    /// Write every keypath into the file it already resolves through, if there is one.
+    ///
+    /// Candidate files are considered in the same order as [`Datastore::get`]. Keypaths that don't
+    /// resolve through any existing file are split as with [`Layout::Depth`] at the given depth.
+    Existing(usize),
+}
+
+impl Layout {
+    /// Split `keypath` at `depth` into a file path relative to the root, with the given
+    /// extension, and keys within the file.
+    pub(crate) fn split_at_depth(
+        keypath: &KeyPath,
+        depth: usize,
+        extension: &str,
+    ) -> (PathBuf, Vec<String>) {
+        let components = keypath.components();
+        let depth = depth.clamp(1, components.len());
+        let path = components[..depth]
+            .iter()
+            .collect::<PathBuf>()
+            .with_extension(extension);
+        let keys = components[depth..].iter().map(|&c| c.to_owned()).collect();
+        (path, keys)
+    }
+}
+
+impl Datastore {
+    /// Decide which file `keypath` is written to under `layout`, and the keys within that file.
+    ///
+    /// `pending` holds files that are about to be written, which count as existing.
+    pub(crate) fn place(
+        &self,
+        keypath: &KeyPath,
+        layout: Layout,
+        pending: &BTreeMap<PathBuf, Value>,
+    ) -> (PathBuf, Vec<String>) {
+        match layout {
+            Layout::Depth(depth) => Layout::split_at_depth(keypath, depth, self.write_extension()),
+            Layout::Existing(depth) => self
+                .candidates(keypath)
+                .find(|(path, _)| pending.contains_key(path) || self.is_file(path))
+                .map_or_else(
+                    || Layout::split_at_depth(keypath, depth, self.write_extension()),
+                    |(path, keys)| (path, keys.into_iter().map(str::to_owned).collect()),
+                ),
+        }
+    }
+
+    /// Read the data file at `path` so it can be modified, treating a missing file as `null`.
+    pub(crate) fn read_value_for_update(&self, path: &Path) -> Result<Value, Error> {
+        match self.read_value(path) {
+            Err(Error::IOError(e)) if e.kind() == std::io::ErrorKind::NotFound => Ok(Value::Null),
+            result => result,
+        }
+    }
+
+    /// Flatten the entire datastore into a map from keypath to leaf value.
+    ///
+    /// The map holds the leaves of [`export()`](Datastore::export), with sequence elements keyed
+    /// by their index, so `complete.yaml` containing `nested: {value: true}` produces the entry
+    /// `complete.nested.value: true`. Leaves are scalars, empty mappings and sequences, and tagged
+    /// values.
+    ///
+    /// Where files overlap, their mappings are merged key by key, so every leaf keeps the value
+    /// [`get()`](Datastore::get) gives it. For example, with `a.yaml` containing
+    /// `b: {c: 1, d: 2}` and `a/b.yaml` containing `c: 3`, the map holds `a.b.c: 3` and
+    /// `a.b.d: 2`. Anything other than a mapping is replaced outright by the file `get()` tries
+    /// first, so if `a/b.yaml` held a scalar instead, there would be no leaves beneath `a.b`.
+    /// `get()` can still reach those leaves, since it only asks whether a file has a value at the
+    /// whole keypath, but they aren't part of the map, which is always a single tree.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use serde_yaml::Value;
+    /// use yaml_datastore::Datastore;
+    ///
+    /// let dir = tempfile::tempdir().unwrap();
+    /// std::fs::write(dir.path().join("a.yaml"), "b: {c: 1, d: [x, y]}\ne: 2").unwrap();
+    /// std::fs::create_dir(dir.path().join("a")).unwrap();
+    /// std::fs::write(dir.path().join("a/e.yaml"), "f: 3").unwrap();
+    ///
+    /// let flat = Datastore::open(dir.path()).flatten().unwrap();
+    /// let keypaths: Vec<&str> = flat.keys().map(String::as_str).collect();
+    /// assert_eq!(keypaths, ["a.b.c", "a.b.d.0", "a.b.d.1", "a.e.f"]);
+    /// assert_eq!(flat["a.b.d.1"], Value::from("y"));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Walk`] for the first file or directory that cannot be read or parsed.
+    pub fn flatten(&self) -> Result<BTreeMap<String, Value>, Error> {
+        Ok(self
+            .materialize_leaves(None)?
+            .into_iter()
+            .map(|(keypath, value)| (keypath.into(), value))
+            .collect())
+    }
+
+    /// Write a flat map of keypaths to values into the datastore's files.
+    ///
+    /// This is the inverse of [`flatten()`](Datastore::flatten). Each keypath is placed into a file
+    /// according to `layout`, creating files, directories and nested containers as needed. Numeric
+    /// components create sequences, so `tags.0` and `tags.1` become a two-element sequence. Existing
+    /// files are updated in place: keys not present in `flat` are left untouched.
+    ///
+    /// Each affected file is written once, after all values have been placed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::KeyPathError`] if any key of `flat` is not a valid keypath. Nothing is
+    /// written in that case.
+    ///
+    /// Returns [`Error::DataParseError`] if an existing file that would be updated can't be parsed.
+    /// Nothing is written in that case either.
+    ///
+    /// Returns [`Error::IOError`] if a file can't be read or written, in which case any files
+    /// already written are restored.
+    ///
+    /// Returns [`Error::SchemaViolation`] as described in
+    /// [`enforce_schemas()`](Datastore::enforce_schemas).
+    pub fn import_flat(&self, flat: &BTreeMap<String, Value>, layout: Layout) -> Result<(), Error> {
+        let mut files: BTreeMap<PathBuf, Value> = BTreeMap::new();
+        for (keypath, value) in flat {
+            let keypath = KeyPath::try_from(keypath.as_str())?;
+            let (path, keys) = self.place(&keypath, layout, &files);
+            let file = match files.entry(path) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let existing = self.read_value_for_update(entry.key())?;
+                    entry.insert(existing)
+                }
+            };
+            set_value_at(file, &keys, value.clone());
+        }
+        let mut transaction = Transaction::new(self);
+        for (path, value) in &files {
+            transaction.write(path, value)?;
+        }
+        transaction.commit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::MANIFEST_PATH;
+
+    fn write(dir: &Path, path: &str, contents: &str) {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn flatten_prefers_deeper_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.yaml", "b: {c: 1, d: 2}\nx: 1");
+        write(dir.path(), "a/b.yaml", "c: 3");
+        let flat = Datastore::open(dir.path()).flatten().unwrap();
+        assert_eq!(flat["a.b.c"], Value::from(3));
+        assert_eq!(flat["a.x"], Value::from(1));
+        // `a/b.yaml` doesn't have `d`, so `get("a.b.d")` still finds it in `a.yaml`.
+        assert_eq!(flat["a.b.d"], Value::from(2));
+    }
+
+    #[test]
+    fn flatten_matches_get() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.yaml", "b: {c: 1, d: 2}\ne: [1]");
+        write(dir.path(), "a/b/c.yaml", "x: 3");
+        write(dir.path(), "a/e.yml", "4");
+        write(dir.path(), "a/e.yaml", "5");
+        let datastore = Datastore::open(dir.path());
+        let flat = datastore.flatten().unwrap();
+
+        // `a.b.c` and `a.e` resolve to whole files, which shadow what's beneath them in `a.yaml`.
+        let keypaths: Vec<&str> = flat.keys().map(String::as_str).collect();
+        assert_eq!(keypaths, ["a.b.c.x", "a.b.d", "a.e"]);
+        for (keypath, value) in &flat {
+            assert_eq!(&datastore.get_value(keypath).unwrap(), value, "{keypath}");
+        }
+        assert_eq!(flat["a.e"], Value::from(5));
+        // The scalar in `a/e.yaml` replaces the sequence, though `get()` can still reach into it.
+        assert_eq!(datastore.get::<i64>("a.e.0").unwrap(), 1);
+    }
+
+    #[test]
+    fn flatten_agrees_with_get() {
+        const LEAVES: [&str; 6] = ["a.b.c", "a.b.d", "a.x", "a.y.z", "e.f", "e.g"];
+        const FILES: [(&str, &str); 8] = [
+            ("a.yaml", "a"),
+            ("a.yml", "a"),
+            ("a/b.yaml", "a.b"),
+            ("a/b.yml", "a.b"),
+            ("a/b/c.yaml", "a.b.c"),
+            ("a/y.yaml", "a.y"),
+            ("e.yaml", "e"),
+            ("e/g.yaml", "e.g"),
+        ];
+        // A fixed xorshift generator, so failures can be reproduced.
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for case in 0..200 {
+            let dir = tempfile::tempdir().unwrap();
+            if case % 2 == 1 {
+                write(dir.path(), MANIFEST_PATH, "resolution: shallowest_first");
+            }
+            // Every leaf of every file gets a different value.
+            let mut next = 0;
+            for (path, prefix) in FILES {
+                if random() % 2 == 0 {
+                    continue;
+                }
+                let mut value = Value::Null;
+                for leaf in LEAVES {
+                    let Some(keys) = leaf.strip_prefix(prefix) else {
+                        continue;
+                    };
+                    if (!keys.is_empty() && !keys.starts_with('.')) || random() % 2 == 0 {
+                        continue;
+                    }
+                    let keys: Vec<&str> = keys.split('.').filter(|k| !k.is_empty()).collect();
+                    next += 1;
+                    set_value_at(&mut value, &keys, Value::from(next));
+                }
+                if !value.is_null() {
+                    write(dir.path(), path, &serde_yaml::to_string(&value).unwrap());
+                }
+            }
+
+            let datastore = Datastore::open(dir.path());
+            let flat = datastore.flatten().unwrap();
+            for (keypath, value) in &flat {
+                assert_eq!(
+                    &datastore.get_value(keypath).unwrap(),
+                    value,
+                    "{case}: {keypath}"
+                );
+            }
+            for leaf in LEAVES {
+                let resolved = datastore.get_value(leaf).ok();
+                assert_eq!(resolved.as_ref(), flat.get(leaf), "{case}: {leaf}");
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let source = tempfile::tempdir().unwrap();
+        write(
+            source.path(),
+            "x.yaml",
+            "name: X\ntags: [a, b]\nnested: {deep: {v: 1}}",
+        );
+        write(source.path(), "dir/y.yaml", "empty: {}\nnothing: null");
+        let flat = Datastore::open(source.path()).flatten().unwrap();
+        assert_eq!(flat["x.tags.1"], Value::from("b"));
+
+        for layout in [Layout::Depth(1), Layout::Depth(3)] {
+            let target = tempfile::tempdir().unwrap();
+            let datastore = Datastore::open(target.path());
+            datastore.import_flat(&flat, layout).unwrap();
+            assert_eq!(datastore.flatten().unwrap(), flat);
+        }
+
+        let target = tempfile::tempdir().unwrap();
+        let datastore = Datastore::open(target.path());
+        datastore.import_flat(&flat, Layout::Depth(1)).unwrap();
+        let tags: Vec<String> = datastore.get("x.tags").unwrap();
+        assert_eq!(tags, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn import_into_existing_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a/b.yaml", "c: 1\nkeep: true");
+        let datastore = Datastore::open(dir.path());
+        let flat = BTreeMap::from([("a.b.c".to_owned(), Value::from(2))]);
+
+        datastore.import_flat(&flat, Layout::Existing(1)).unwrap();
+        assert!(!dir.path().join("a.yaml").exists());
+        assert_eq!(datastore.get::<u64>("a.b.c").unwrap(), 2);
+        assert!(datastore.get::<bool>("a.b.keep").unwrap());
+    }
+
+    #[test]
+    fn import_invalid_keypath() {
+        let dir = tempfile::tempdir().unwrap();
+        let flat = BTreeMap::from([("a..b".to_owned(), Value::Null)]);
+        let result = Datastore::open(dir.path())
+            .import_flat(&flat, Layout::Depth(1))
+            .unwrap_err();
+        assert!(matches!(result, Error::KeyPathError(_)));
+    }
+}
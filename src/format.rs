@@ -0,0 +1,283 @@
+//! Rewriting data files in a single consistent style.
+//!
+//! See [`Datastore::formatter`] for details.
+use crate::{Datastore, Error, transaction::Transaction, walk::WalkError};
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use std::{
+    cmp::Ordering,
+    path::{Path, PathBuf},
+};
+
+/// A data file that is, or would be, rewritten by a [`Formatter`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FormatChange {
+    /// The file as it is now, relative to the root.
+    pub path: PathBuf,
+    /// Where the formatted file is written, which differs from `path` if its extension is
+    /// normalized.
+    pub new_path: PathBuf,
+}
+
+/// The outcome of [checking](Formatter::check) or [applying](Formatter::apply) a [`Formatter`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FormatReport {
+    /// The files that are, or would be, rewritten.
+    pub changed: Vec<FormatChange>,
+    /// The files that weren't checked because they may contain comments, relative to the root.
+    ///
+    /// These may or may not be formatted already. They're only checked if
+    /// [`strip_comments()`](Formatter::strip_comments) is set.
+    pub skipped: Vec<PathBuf>,
+}
+
+/// What formatting a single data file does.
+enum FileOutcome {
+    /// The file is already formatted.
+    Unchanged,
+    /// The file is rewritten with the given contents.
+    Changed(FormatChange, String),
+    /// The file may contain comments, so it isn't formatted.
+    Skipped,
+}
+
+/// Return whether `contents` might contain a YAML comment.
+///
+/// This errs on the side of caution: any `#` at the start of a line or after whitespace counts,
+/// even inside a quoted string.
+fn may_have_comments(contents: &str) -> bool {
+    contents.lines().any(|line| {
+        line.trim_start().starts_with('#') || line.contains(" #") || line.contains("\t#")
+    })
+}
+
+/// Sort the keys of every mapping within `value`, recursively.
+fn sort_keys(value: &mut Value) {
+    match value {
+        Value::Mapping(mapping) => {
+            let mut entries: Vec<(Value, Value)> = std::mem::take(mapping).into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+            for (_, child) in &mut entries {
+                sort_keys(child);
+            }
+            *mapping = entries.into_iter().collect::<Mapping>();
+        }
+        Value::Sequence(sequence) => sequence.iter_mut().for_each(sort_keys),
+        Value::Tagged(tagged) => sort_keys(&mut tagged.value),
+        _ => {}
+    }
+}
+
+/// Rewrites data files in a canonical style, created by [`Datastore::formatter`].
+#[derive(Debug)]
+pub struct Formatter<'a> {
+    /// The datastore being formatted.
+    datastore: &'a Datastore,
+    /// Whether mapping keys are sorted.
+    sort_keys: bool,
+    /// Whether `.yml` files are renamed to `.yaml`.
+    normalize_extensions: bool,
+    /// Whether files that may contain comments are formatted anyway.
+    strip_comments: bool,
+}
+
+impl<'a> Formatter<'a> {
+    /// Create a formatter for `datastore` with the default settings.
+    fn new(datastore: &'a Datastore) -> Self {
+        Self {
+            datastore,
+            sort_keys: false,
+            normalize_extensions: true,
+            strip_comments: false,
+        }
+    }
+
+    /// Set whether the keys of every mapping are sorted. Off by default.
+    #[must_use]
+    pub fn sort_keys(mut self, sort_keys: bool) -> Self {
+        self.sort_keys = sort_keys;
+        self
+    }
+
+    /// Set whether `.yml` files are renamed to `.yaml`, or to the first extension listed in the
+    /// [manifest](crate::manifest). On by default.
+    ///
+    /// A file is never renamed over an existing `.yaml` file of the same name; it is formatted
+    /// where it is instead.
+    #[must_use]
+    pub fn normalize_extensions(mut self, normalize_extensions: bool) -> Self {
+        self.normalize_extensions = normalize_extensions;
+        self
+    }
+
+    /// Set whether files that may contain comments are formatted. Off by default.
+    ///
+    /// Formatting doesn't preserve comments, so such files are skipped unless this is set.
+    /// Detection is conservative: a `#` after whitespace inside a string also counts.
+    #[must_use]
+    pub fn strip_comments(mut self, strip_comments: bool) -> Self {
+        self.strip_comments = strip_comments;
+        self
+    }
+
+    /// Format the data file at `path`, returning its new contents and path if either would change.
+    fn format_file(&self, path: &Path) -> Result<FileOutcome, Error> {
+        let in_walk = |error: Error| WalkError {
+            path: path.to_owned(),
+            error,
+        };
+        let contents = self.datastore.read_data_file(path).map_err(in_walk)?;
+        if !self.strip_comments && may_have_comments(&contents) {
+            return Ok(FileOutcome::Skipped);
+        }
+        let mut value: Value = serde_yaml::from_str(&contents).map_err(|e| in_walk(e.into()))?;
+        if self.sort_keys {
+            sort_keys(&mut value);
+        }
+        let formatted = match value {
+            Value::Null => String::new(),
+            value => serde_yaml::to_string(&value)?,
+        };
+
+        let mut new_path = path.to_owned();
+        let extension = self.datastore.write_extension();
+        if self.normalize_extensions && path.extension().is_some_and(|e| e != extension) {
+            let normalized = path.with_extension(extension);
+            if !self.datastore.path_exists(&normalized) {
+                new_path = normalized;
+            }
+        }
+        if formatted == contents && new_path == path {
+            return Ok(FileOutcome::Unchanged);
+        }
+        let change = FormatChange {
+            path: path.to_owned(),
+            new_path,
+        };
+        Ok(FileOutcome::Changed(change, formatted))
+    }
+
+    /// Format every data file, returning the report and the new contents of each changed file.
+    fn plan(&self) -> Result<(FormatReport, Vec<String>), Error> {
+        let mut paths = Vec::new();
+        self.datastore
+            .collect_contributing_files(Path::new(""), &mut paths)?;
+        paths.sort();
+        let mut report = FormatReport::default();
+        let mut contents = Vec::new();
+        for path in paths {
+            match self.format_file(&path)? {
+                FileOutcome::Unchanged => {}
+                FileOutcome::Changed(change, formatted) => {
+                    report.changed.push(change);
+                    contents.push(formatted);
+                }
+                FileOutcome::Skipped => report.skipped.push(path),
+            }
+        }
+        Ok((report, contents))
+    }
+
+    /// Return the files that [`apply()`](Self::apply) would change, and the files it would skip,
+    /// without changing them.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`apply()`](Self::apply), except that nothing is written.
+    pub fn check(&self) -> Result<FormatReport, Error> {
+        Ok(self.plan()?.0)
+    }
+
+    /// Format every data file, returning the files that were changed and the files that were
+    /// skipped because they may contain comments.
+    ///
+    /// Files are checked before any are written, so if one can't be parsed, nothing is changed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Walk`] naming the first file or directory that can't be read or parsed.
+    ///
+    /// Returns [`Error::IOError`] if a file can't be written or renamed, in which case any files
+    /// already written are restored.
+    ///
+    /// Returns [`Error::SchemaViolation`] as described in
+    /// [`enforce_schemas()`](Datastore::enforce_schemas).
+    pub fn apply(&self) -> Result<FormatReport, Error> {
+        let (report, contents) = self.plan()?;
+        let mut transaction = Transaction::new(self.datastore);
+        for (change, contents) in report.changed.iter().zip(&contents) {
+            transaction.write_contents(&change.new_path, contents)?;
+            if change.new_path != change.path {
+                transaction.remove(&change.path)?;
+            }
+        }
+        transaction.commit()?;
+        Ok(report)
+    }
+}
+
+impl Datastore {
+    /// Create a formatter that rewrites every data file in a canonical style.
+    ///
+    /// Each file is parsed and written back out with the same style the datastore uses when
+    /// writing files: two-space indentation, block style for mappings and sequences, and quotes
+    /// only where needed. Empty files stay empty. Keys can optionally be
+    /// [sorted](Formatter::sort_keys), and `.yml` files are renamed to `.yaml` unless
+    /// [disabled](Formatter::normalize_extensions). Comments are not preserved, so files that may
+    /// contain them are skipped unless [allowed](Formatter::strip_comments), and reported in
+    /// [`FormatReport::skipped`].
+    ///
+    /// [Ignored](Datastore::builder) files and files whose names aren't valid keypath components
+    /// are skipped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use yaml_datastore::Datastore;
+    ///
+    /// let datastore = Datastore::open("tests/data");
+    /// // The test data includes a file with duplicate keys, which can't be formatted.
+    /// assert!(datastore.formatter().sort_keys(true).check().is_err());
+    /// ```
+    #[must_use]
+    pub fn formatter(&self) -> Formatter<'_> {
+        Formatter::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.yml"), "{b: 1, a: [x,   y]}").unwrap();
+        std::fs::write(dir.path().join("done.yaml"), "a: 1\n").unwrap();
+        std::fs::write(dir.path().join("commented.yaml"), "# note\nb: 1\na: 2").unwrap();
+        let datastore = Datastore::open(dir.path());
+
+        let formatter = datastore.formatter().sort_keys(true);
+        let expected = FormatReport {
+            changed: vec![FormatChange {
+                path: PathBuf::from("a.yml"),
+                new_path: PathBuf::from("a.yaml"),
+            }],
+            skipped: vec![PathBuf::from("commented.yaml")],
+        };
+        assert_eq!(formatter.check().unwrap(), expected);
+        assert!(dir.path().join("a.yml").exists());
+
+        assert_eq!(formatter.apply().unwrap(), expected);
+        assert!(!dir.path().join("a.yml").exists());
+        let contents = std::fs::read_to_string(dir.path().join("a.yaml")).unwrap();
+        assert_eq!(contents, "a:\n- x\n- y\nb: 1\n");
+        let report = formatter.check().unwrap();
+        assert!(report.changed.is_empty());
+        assert_eq!(report.skipped, [PathBuf::from("commented.yaml")]);
+
+        let report = datastore.formatter().strip_comments(true).check().unwrap();
+        assert_eq!(report.changed[0].path, PathBuf::from("commented.yaml"));
+        assert!(report.skipped.is_empty());
+    }
+}
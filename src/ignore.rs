@@ -0,0 +1,203 @@
+//! Gitignore-style rules for excluding files under the root from the datastore.
+use std::path::{Component, Path};
+
+/// A single ignore rule, parsed from one line of gitignore syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    /// Glob for each path component. A `**` component matches any number of components.
+    segments: Vec<String>,
+    /// Whether the rule re-includes paths instead of ignoring them.
+    negated: bool,
+    /// Whether the rule only matches directories.
+    directory_only: bool,
+    /// Whether the rule matches whole paths from the root, rather than names at any depth.
+    anchored: bool,
+}
+
+impl Rule {
+    /// Parse a line of gitignore syntax, returning `None` for blank lines and comments.
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (directory_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        // A slash anywhere but the end anchors the pattern to the root.
+        let anchored = line.contains('/');
+        let segments: Vec<String> = line
+            .trim_start_matches('/')
+            .split('/')
+            .map(str::to_owned)
+            .collect();
+        if segments.iter().all(String::is_empty) {
+            return None;
+        }
+        Some(Self {
+            segments,
+            negated,
+            directory_only,
+            anchored,
+        })
+    }
+
+    /// Return whether the rule matches `components`, the path of a file or directory.
+    fn matches(&self, components: &[&str], is_dir: bool) -> bool {
+        if self.directory_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            match_segments(&self.segments, components)
+        } else {
+            components
+                .last()
+                .is_some_and(|name| glob(&self.segments[0], name))
+        }
+    }
+}
+
+/// Return whether `segments`, which may include `**`, match `components` in full.
+fn match_segments(segments: &[String], components: &[&str]) -> bool {
+    match segments.split_first() {
+        None => components.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=components.len()).any(|skip| match_segments(rest, &components[skip..]))
+        }
+        Some((first, rest)) => components
+            .split_first()
+            .is_some_and(|(name, others)| glob(first, name) && match_segments(rest, others)),
+    }
+}
+
+/// Return whether the glob `pattern` matches `name`.
+///
+/// `*` matches any run of characters, `?` any single character, and `[...]` any character in
+/// the set, which may contain ranges such as `a-z` and be negated with a leading `!` or `^`.
+/// A `\` matches the character after it literally.
+fn glob(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    glob_chars(&pattern, &name)
+}
+
+/// Match `pattern` against `name`, character by character.
+fn glob_chars(pattern: &[char], name: &[char]) -> bool {
+    let Some((&first, rest)) = pattern.split_first() else {
+        return name.is_empty();
+    };
+    match first {
+        '*' => (0..=name.len()).any(|skip| glob_chars(rest, &name[skip..])),
+        '?' => !name.is_empty() && glob_chars(rest, &name[1..]),
+        '[' => {
+            let Some(end) = rest.iter().skip(1).position(|&c| c == ']').map(|i| i + 1) else {
+                return name.first() == Some(&'[') && glob_chars(rest, &name[1..]);
+            };
+            let Some((&c, others)) = name.split_first() else {
+                return false;
+            };
+            let (negated, set) = match rest[..end].split_first() {
+                Some(('!' | '^', set)) => (true, set),
+                _ => (false, &rest[..end]),
+            };
+            let mut found = false;
+            let mut i = 0;
+            while i < set.len() {
+                if i + 2 < set.len() && set[i + 1] == '-' {
+                    found |= (set[i]..=set[i + 2]).contains(&c);
+                    i += 3;
+                } else {
+                    found |= set[i] == c;
+                    i += 1;
+                }
+            }
+            found != negated && glob_chars(&rest[end + 1..], others)
+        }
+        '\\' if !rest.is_empty() => {
+            name.first() == Some(&rest[0]) && glob_chars(&rest[1..], &name[1..])
+        }
+        c => name.first() == Some(&c) && glob_chars(rest, &name[1..]),
+    }
+}
+
+/// An ordered set of gitignore-style rules.
+///
+/// As with gitignore, the last rule matching a path decides whether it's ignored, and nothing
+/// inside an ignored directory can be re-included.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct IgnoreRules {
+    /// The rules, in the order they were added.
+    rules: Vec<Rule>,
+}
+
+impl IgnoreRules {
+    /// Add a rule for each line of `contents`, which uses gitignore syntax.
+    pub(crate) fn add_lines(&mut self, contents: &str) {
+        self.rules.extend(contents.lines().filter_map(Rule::parse));
+    }
+
+    /// Return whether the rules ignore `components` themselves, ignoring their ancestors.
+    fn ignores(&self, components: &[&str], is_dir: bool) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(components, is_dir))
+            .is_some_and(|rule| !rule.negated)
+    }
+
+    /// Return whether `path`, relative to the root, is ignored, either itself or because one of
+    /// its ancestor directories is.
+    pub(crate) fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+        let components: Vec<&str> = path
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => name.to_str(),
+                _ => None,
+            })
+            .collect();
+        (1..components.len()).any(|len| self.ignores(&components[..len], true))
+            || self.ignores(&components, is_dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob("*.md", "README.md"));
+        assert!(!glob("*.md", "README.yaml"));
+        assert!(glob("file?.yaml", "file1.yaml"));
+        assert!(glob("[a-c]x", "bx"));
+        assert!(!glob("[!a-c]x", "bx"));
+        assert!(glob("\\*", "*"));
+        assert!(!glob("\\*", "a"));
+    }
+
+    #[test]
+    fn ignore_rules() {
+        let mut rules = IgnoreRules::default();
+        rules.add_lines("# comment\n*.md\nscratch/\n/build\nci/**/out.yaml\n!keep.md\n");
+        let ignored = |path: &str, is_dir| rules.is_ignored(Path::new(path), is_dir);
+        assert!(ignored("README.md", false));
+        assert!(ignored("a/notes.md", false));
+        assert!(!ignored("keep.md", false));
+        assert!(ignored("scratch", true));
+        assert!(!ignored("scratch", false));
+        assert!(ignored("a/scratch/x.yaml", false));
+        assert!(ignored("build/x.yaml", false));
+        assert!(!ignored("a/build/x.yaml", false));
+        assert!(ignored("ci/out.yaml", false));
+        assert!(ignored("ci/a/b/out.yaml", false));
+        assert!(!ignored("data.yaml", false));
+    }
+}
@@ -0,0 +1,483 @@
+//! Secondary indexes over records in a datastore.
+//!
+//! An index is declared with a [pattern](crate::keypath::KeyPathPattern) selecting the records to
+//! index, and a field keypath naming the value within each record to index by. For example, the
+//! pattern `users/*` with field `email` indexes every user by email address, regardless of whether
+//! the users are stored as `users/alice.yaml` or as keys of `users.yaml`.
+//!
+//! If the field is a sequence, each scalar element is indexed separately, which makes multi-valued
+//! fields like `tags` searchable by any one of their values.
+//!
+//! Indexes are created and queried through the [`Datastore`](crate::Datastore):
+//!
+//! ```
+//! use yaml_datastore::Datastore;
+//!
+//! let datastore = Datastore::open("tests/data");
+//! datastore.create_index("by_tag", "*", "tags").unwrap();
+//! let found = datastore.lookup("by_tag", "done").unwrap();
+//! assert_eq!(found[0].to_string(), "complete");
+//! ```
+use crate::{
+    cache::FileStamp,
+    keypath::{KeyPath, KeyPathPattern},
+};
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Number, Value};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    ops::RangeBounds,
+    path::{Path, PathBuf},
+};
+
+/// A scalar value that records can be indexed by.
+///
+/// Keys of different kinds are ordered `Null < Bool < Number < String`, and numbers compare by
+/// value regardless of whether they were written as integers or floats.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum IndexKey {
+    /// The YAML `null` value.
+    Null,
+    /// A boolean.
+    Bool(bool),
+    /// An integer or floating point number.
+    Number(Number),
+    /// A string.
+    String(String),
+}
+
+impl IndexKey {
+    /// Convert a YAML scalar into an index key, returning `None` for non-scalar values.
+    #[must_use]
+    pub fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Null => Some(Self::Null),
+            Value::Bool(b) => Some(Self::Bool(*b)),
+            Value::Number(n) => Some(Self::Number(n.clone())),
+            Value::String(s) => Some(Self::String(s.clone())),
+            Value::Tagged(tagged) => Self::from_value(&tagged.value),
+            Value::Sequence(_) | Value::Mapping(_) => None,
+        }
+    }
+
+    /// Rank of the variant, used to order keys of different kinds.
+    fn rank(&self) -> u8 {
+        match self {
+            Self::Null => 0,
+            Self::Bool(_) => 1,
+            Self::Number(_) => 2,
+            Self::String(_) => 3,
+        }
+    }
+}
+
+impl Ord for IndexKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Bool(a), Self::Bool(b)) => a.cmp(b),
+            (Self::String(a), Self::String(b)) => a.cmp(b),
+            (Self::Number(a), Self::Number(b)) => compare_numbers(a, b),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+/// Compare two numbers by value, exactly, whether they were written as integers or floats.
+pub(crate) fn compare_numbers(a: &Number, b: &Number) -> Ordering {
+    match (integer(a), integer(b)) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(a), None) => compare_integer_float(a, float(b)),
+        (None, Some(b)) => compare_integer_float(b, float(a)).reverse(),
+        (None, None) => float(a).total_cmp(&float(b)),
+    }
+}
+
+/// The value of `number` if it's an integer, widened so that every `i64` and `u64` fits.
+fn integer(number: &Number) -> Option<i128> {
+    number
+        .as_i64()
+        .map(i128::from)
+        .or_else(|| number.as_u64().map(i128::from))
+}
+
+/// The value of `number` as a float. Only used for numbers that aren't integers, so it's exact.
+fn float(number: &Number) -> f64 {
+    number.as_f64().unwrap_or(f64::NAN)
+}
+
+/// Compare an integer with a float exactly, without rounding the integer to the nearest float.
+///
+/// `NaN` is ordered as by [`f64::total_cmp`], after every integer unless its sign is negative.
+fn compare_integer_float(integer: i128, float: f64) -> Ordering {
+    // Every integer is strictly between these bounds, which are exactly representable.
+    const BOUND: f64 = 170_141_183_460_469_231_731_687_303_715_884_105_728.0; // 2^127
+    if float.is_nan() {
+        return if float.is_sign_negative() {
+            Ordering::Greater
+        } else {
+            Ordering::Less
+        };
+    }
+    let whole = float.trunc();
+    if whole >= BOUND {
+        return Ordering::Less;
+    }
+    if whole < -BOUND {
+        return Ordering::Greater;
+    }
+    // `whole` is an integer within range, so the cast is exact.
+    #[allow(clippy::cast_possible_truncation)]
+    let whole_integer = whole as i128;
+    integer
+        .cmp(&whole_integer)
+        .then_with(|| 0.0.partial_cmp(&(float - whole)).unwrap_or(Ordering::Equal))
+}
+
+impl PartialOrd for IndexKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for IndexKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for IndexKey {}
+
+impl From<bool> for IndexKey {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<i32> for IndexKey {
+    fn from(value: i32) -> Self {
+        Self::Number(i64::from(value).into())
+    }
+}
+
+impl From<i64> for IndexKey {
+    fn from(value: i64) -> Self {
+        Self::Number(value.into())
+    }
+}
+
+impl From<u64> for IndexKey {
+    fn from(value: u64) -> Self {
+        Self::Number(value.into())
+    }
+}
+
+impl From<f64> for IndexKey {
+    fn from(value: f64) -> Self {
+        Self::Number(value.into())
+    }
+}
+
+impl From<&str> for IndexKey {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_owned())
+    }
+}
+
+impl From<String> for IndexKey {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+/// Collect every keypath at or beneath `keypath` in `value` that is matched by `pattern`.
+///
+/// Only mapping keys that are valid keypath components are descended into.
+pub(crate) fn matching_records<'a>(
+    pattern: &KeyPathPattern,
+    keypath: &KeyPath,
+    value: &'a Value,
+    records: &mut Vec<(KeyPath, &'a Value)>,
+) {
+    if pattern.matches(keypath) {
+        records.push((keypath.clone(), value));
+    }
+    if !pattern.could_match_within(keypath) {
+        return;
+    }
+    if let Value::Mapping(mapping) = value {
+        for (key, child) in mapping {
+            if let Some(key) = key.as_str()
+                && let Ok(child_keypath) = keypath.join(key)
+            {
+                matching_records(pattern, &child_keypath, child, records);
+            }
+        }
+    }
+}
+
+/// Walk `value` along the components of `field`, returning the value found there, if any.
+fn field_value<'a>(value: &'a Value, field: &KeyPath) -> Option<&'a Value> {
+    field
+        .components()
+        .into_iter()
+        .try_fold(value, |value, key| value.as_mapping()?.get(key))
+}
+
+/// A single secondary index and its entries.
+///
+/// Only the per-file entries are serialized; the lookup table is rebuilt from them on load.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(from = "SerializedIndex")]
+pub(crate) struct Index {
+    /// Pattern selecting which records are indexed.
+    pattern: KeyPathPattern,
+    /// Field within each record that is indexed.
+    field: KeyPath,
+    /// Index entries, mapping each key to the records containing it.
+    #[serde(skip)]
+    entries: BTreeMap<IndexKey, BTreeSet<KeyPath>>,
+    /// Entries contributed by each file, so a single file can be reindexed.
+    files: BTreeMap<PathBuf, Vec<(IndexKey, KeyPath)>>,
+}
+
+/// Deserialized form of an [`Index`].
+#[derive(Deserialize)]
+struct SerializedIndex {
+    pattern: KeyPathPattern,
+    field: KeyPath,
+    files: BTreeMap<PathBuf, Vec<(IndexKey, KeyPath)>>,
+}
+
+impl From<SerializedIndex> for Index {
+    fn from(value: SerializedIndex) -> Self {
+        let mut entries: BTreeMap<IndexKey, BTreeSet<KeyPath>> = BTreeMap::new();
+        for (key, record) in value.files.values().flatten() {
+            entries
+                .entry(key.clone())
+                .or_default()
+                .insert(record.clone());
+        }
+        Self {
+            pattern: value.pattern,
+            field: value.field,
+            entries,
+            files: value.files,
+        }
+    }
+}
+
+impl Index {
+    /// Create a new, empty index.
+    pub(crate) fn new(pattern: KeyPathPattern, field: KeyPath) -> Self {
+        Self {
+            pattern,
+            field,
+            entries: BTreeMap::new(),
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Return whether this index was declared with the given pattern and field.
+    pub(crate) fn is_defined_as(&self, pattern: &KeyPathPattern, field: &KeyPath) -> bool {
+        self.pattern == *pattern && self.field == *field
+    }
+
+    /// Remove every entry from the index.
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.files.clear();
+    }
+
+    /// Index the records found in the file at `path`, replacing any it previously contributed.
+    ///
+    /// `keypath` is the keypath addressing the file and `value` its parsed contents.
+    pub(crate) fn insert_file(&mut self, path: &Path, keypath: &KeyPath, value: &Value) {
+        self.remove_file(path);
+        let mut records = Vec::new();
+        matching_records(&self.pattern, keypath, value, &mut records);
+
+        let mut contributed = Vec::new();
+        for (record, value) in records {
+            let keys = match field_value(value, &self.field) {
+                Some(Value::Sequence(sequence)) => {
+                    sequence.iter().filter_map(IndexKey::from_value).collect()
+                }
+                Some(value) => IndexKey::from_value(value).into_iter().collect(),
+                None => Vec::new(),
+            };
+            for key in keys {
+                self.entries
+                    .entry(key.clone())
+                    .or_default()
+                    .insert(record.clone());
+                contributed.push((key, record.clone()));
+            }
+        }
+        if !contributed.is_empty() {
+            self.files.insert(path.to_owned(), contributed);
+        }
+    }
+
+    /// Remove any entries contributed by the file at `path`.
+    pub(crate) fn remove_file(&mut self, path: &Path) {
+        for (key, record) in self.files.remove(path).unwrap_or_default() {
+            if let Some(records) = self.entries.get_mut(&key) {
+                records.remove(&record);
+                if records.is_empty() {
+                    self.entries.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// Return the records whose field equals `key`.
+    pub(crate) fn get(&self, key: &IndexKey) -> Vec<KeyPath> {
+        self.entries
+            .get(key)
+            .map(|records| records.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Return the records whose field falls within `range`, ordered by key.
+    pub(crate) fn range<R: RangeBounds<IndexKey>>(&self, range: R) -> Vec<KeyPath> {
+        let mut seen = BTreeSet::new();
+        let mut found = Vec::new();
+        for records in self.entries.range(range).map(|(_, records)| records) {
+            for record in records {
+                if seen.insert(record) {
+                    found.push(record.clone());
+                }
+            }
+        }
+        found
+    }
+}
+
+/// Every index of a datastore, along with the state of the files they were built from.
+///
+/// This is the unit persisted by the [index cache](crate::cache).
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Indexes {
+    /// Indexes by name.
+    pub(crate) indexes: BTreeMap<String, Index>,
+    /// Stamp of each data file as of the last time it was indexed.
+    pub(crate) stamps: BTreeMap<PathBuf, FileStamp>,
+}
+
+impl Indexes {
+    /// Forget all entries and stamps, keeping only index definitions.
+    pub(crate) fn clear(&mut self) {
+        self.indexes.values_mut().for_each(Index::clear);
+        self.stamps.clear();
+    }
+
+    /// Index the file at `path` in every index.
+    pub(crate) fn insert_file(&mut self, path: &Path, keypath: &KeyPath, value: &Value) {
+        for index in self.indexes.values_mut() {
+            index.insert_file(path, keypath, value);
+        }
+    }
+
+    /// Remove the file at `path` from every index, and forget its stamp.
+    pub(crate) fn remove_file(&mut self, path: &Path) {
+        for index in self.indexes.values_mut() {
+            index.remove_file(path);
+        }
+        self.stamps.remove(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl Index {
+        fn parse(pattern: &str, field: &str) -> Self {
+            Self::new(
+                KeyPathPattern::try_from(pattern).unwrap(),
+                KeyPath::try_from(field).unwrap(),
+            )
+        }
+    }
+
+    fn index_yaml(index: &mut Index, path: &str, yaml: &str) {
+        let value: Value = serde_yaml::from_str(yaml).unwrap();
+        let keypath = KeyPath::from_path(Path::new(path)).unwrap();
+        index.insert_file(Path::new(path), &keypath, &value);
+    }
+
+    #[test]
+    fn key_ordering() {
+        assert!(IndexKey::Null < IndexKey::from(false));
+        assert!(IndexKey::from(true) < IndexKey::from(0));
+        assert!(IndexKey::from(1) < IndexKey::from(1.5));
+        assert!(IndexKey::from(-1) < IndexKey::from(0.0));
+        assert!(IndexKey::from(2.0) == IndexKey::from(2));
+        assert!(IndexKey::from(u64::MAX) > IndexKey::from(i64::MAX));
+        assert!(IndexKey::from(1) < IndexKey::from("1"));
+    }
+
+    #[test]
+    fn large_integers_compare_exactly() {
+        // 2^53 + 1 rounds to 2^53 as a float, but must still sort above it.
+        let float = IndexKey::from(9_007_199_254_740_992.0);
+        let above = IndexKey::from(9_007_199_254_740_993_i64);
+        let below = IndexKey::from(9_007_199_254_740_991_i64);
+        assert!(below < float && float < above && below < above);
+        assert!(IndexKey::from(u64::MAX) < IndexKey::from(1e20));
+        assert!(IndexKey::from(i64::MIN) > IndexKey::from(-1e19));
+        assert!(IndexKey::from(2) < IndexKey::from(2.5));
+        assert!(IndexKey::from(-2) > IndexKey::from(-2.5));
+        assert!(IndexKey::from(i64::MAX) < IndexKey::from(f64::NAN));
+    }
+
+    #[test]
+    fn records_split_across_files() {
+        let mut index = Index::parse("users/*", "email");
+        index_yaml(&mut index, "users/alice.yaml", "email: alice@example.com");
+        index_yaml(&mut index, "users.yaml", "bob:\n  email: bob@example.com");
+
+        let alice = index.get(&"alice@example.com".into());
+        assert_eq!(alice, vec![KeyPath::try_from("users.alice").unwrap()]);
+        let bob = index.get(&"bob@example.com".into());
+        assert_eq!(bob, vec![KeyPath::try_from("users.bob").unwrap()]);
+    }
+
+    #[test]
+    fn reindex_file_replaces_entries() {
+        let mut index = Index::parse("users/*", "email");
+        index_yaml(&mut index, "users/alice.yaml", "email: old@example.com");
+        index_yaml(&mut index, "users/alice.yaml", "email: new@example.com");
+        assert!(index.get(&"old@example.com".into()).is_empty());
+        assert_eq!(index.get(&"new@example.com".into()).len(), 1);
+
+        index.remove_file(Path::new("users/alice.yaml"));
+        assert!(index.get(&"new@example.com".into()).is_empty());
+    }
+
+    #[test]
+    fn multi_valued_range() {
+        let mut index = Index::parse("*", "scores");
+        index_yaml(&mut index, "a.yaml", "scores: [1, 5]");
+        index_yaml(&mut index, "b.yaml", "scores: [3]");
+        let found = index.range(IndexKey::from(2)..=IndexKey::from(5));
+        let expected: Vec<KeyPath> = vec![
+            KeyPath::try_from("b").unwrap(),
+            KeyPath::try_from("a").unwrap(),
+        ];
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn serialization_round_trip() {
+        let mut index = Index::parse("*", "tags");
+        index_yaml(&mut index, "a.yaml", "tags: [x, y]");
+        let serialized = serde_yaml::to_string(&index).unwrap();
+        let index: Index = serde_yaml::from_str(&serialized).unwrap();
+        assert_eq!(
+            index.get(&"y".into()),
+            vec![KeyPath::try_from("a").unwrap()]
+        );
+    }
+}
@@ -66,7 +66,12 @@
 //! a/b/c.yml   | []
 //! a/b/c.yaml  | []
 //! ```
-use std::{ffi::OsStr, path::PathBuf};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    ffi::OsStr,
+    path::{Component, Path, PathBuf},
+};
 use thiserror::Error;
 
 /// Default file extensions for the iterator.
@@ -80,6 +85,12 @@ pub const DELIMITER: &str = ".";
 /// Characters that are disallowed in a keypath and will cause failure.
 const INVALID_CHARACTERS: &[char] = &['.', '/'];
 
+/// Wildcard matching exactly one component in a [`KeyPathPattern`].
+pub const WILDCARD: &str = "*";
+
+/// Wildcard matching zero or more components in a [`KeyPathPattern`].
+pub const RECURSIVE_WILDCARD: &str = "**";
+
 /// Error type for keypaths.
 ///
 /// Only one error at this time, and that is for parsing failure.
@@ -93,12 +104,28 @@ pub enum KeyPathParseError {
 /// Internal struct for parsing and managing keypath components.
 ///
 /// Construct using [`try_from`](KeyPath::try_from).
-#[derive(Debug)]
+///
+/// Keypaths are ordered component by component, so `a.b` sorts before `a-b`.
+/// They are serialized as their string form.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
 pub struct KeyPath {
     /// Raw string that components point to.
     raw: String,
 }
 
+impl Ord for KeyPath {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.raw.split(DELIMITER).cmp(other.raw.split(DELIMITER))
+    }
+}
+
+impl PartialOrd for KeyPath {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 /// Check a single keypath component for validity and return a String if it's valid.
 fn validate_and_trim(component: &str) -> Result<&str, KeyPathParseError> {
     let component = component.trim();
@@ -109,6 +136,25 @@ fn validate_and_trim(component: &str) -> Result<&str, KeyPathParseError> {
     }
 }
 
+/// Check whether `component` can be used as a single keypath component exactly as written.
+///
+/// This is stricter than parsing a keypath, which trims spaces from components. It's used to
+/// decide whether names found in the datastore, such as file names and mapping keys, can be
+/// addressed by a keypath.
+///
+/// # Example
+///
+/// ```rust
+/// # use yaml_datastore::keypath::is_valid_component;
+/// assert!(is_valid_component("users"));
+/// assert!(!is_valid_component("users.yaml"));
+/// assert!(!is_valid_component(" users"));
+/// ```
+#[must_use]
+pub fn is_valid_component(component: &str) -> bool {
+    validate_and_trim(component).is_ok_and(|trimmed| trimmed == component)
+}
+
 impl TryFrom<&str> for KeyPath {
     /// The error returned if any components are empty or contain invalid characters.
     type Error = KeyPathParseError;
@@ -129,6 +175,22 @@ impl TryFrom<&str> for KeyPath {
     }
 }
 
+impl TryFrom<String> for KeyPath {
+    /// The error returned if any components are empty or contain invalid characters.
+    type Error = KeyPathParseError;
+
+    /// Construct a [`KeyPath`] from an owned string. See [`KeyPath::try_from`].
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::try_from(value.as_str())
+    }
+}
+
+impl From<KeyPath> for String {
+    fn from(value: KeyPath) -> Self {
+        value.raw
+    }
+}
+
 impl std::fmt::Display for KeyPath {
     /// Format the keypath in its internal, parsed state.
     /// In most cases this should be identical to the source string.
@@ -138,6 +200,98 @@ impl std::fmt::Display for KeyPath {
 }
 
 impl KeyPath {
+    /// Construct a [`KeyPath`] from a list of already-split components.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`KeyPathParseError::InvalidKeyPath`] if the list is empty or any component is not
+    /// [valid](is_valid_component) exactly as written.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use yaml_datastore::keypath::KeyPath;
+    /// let keypath = KeyPath::from_components(&["a", "b", "c"]).expect("keypath parsed");
+    /// assert_eq!(keypath.to_string(), "a.b.c");
+    /// ```
+    pub fn from_components<S: AsRef<str>>(components: &[S]) -> Result<Self, KeyPathParseError> {
+        if components.is_empty() || !components.iter().all(|c| is_valid_component(c.as_ref())) {
+            return Err(KeyPathParseError::InvalidKeyPath);
+        }
+        Ok(Self {
+            raw: components
+                .iter()
+                .map(AsRef::as_ref)
+                .collect::<Vec<_>>()
+                .join(DELIMITER),
+        })
+    }
+
+    /// Construct the [`KeyPath`] addressing a file, given its path relative to the datastore root.
+    ///
+    /// The extension of the final component is dropped, so `a/b/c.yaml` becomes `a.b.c`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`KeyPathParseError::InvalidKeyPath`] if the path is not relative, or if any
+    /// component cannot be represented in a keypath (for example a file named `a.b.yaml`).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use yaml_datastore::keypath::KeyPath;
+    /// # use std::path::Path;
+    /// let keypath = KeyPath::from_path(Path::new("a/b/c.yaml")).expect("keypath parsed");
+    /// assert_eq!(keypath.to_string(), "a.b.c");
+    /// ```
+    pub fn from_path(path: &Path) -> Result<Self, KeyPathParseError> {
+        let mut components = path
+            .components()
+            .map(|c| match c {
+                Component::Normal(name) => name.to_str().ok_or(KeyPathParseError::InvalidKeyPath),
+                _ => Err(KeyPathParseError::InvalidKeyPath),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(last) = components.last_mut()
+            && let Some(stem) = Path::new(*last).file_stem().and_then(OsStr::to_str)
+        {
+            *last = stem;
+        }
+        Self::from_components(&components)
+    }
+
+    /// Return a new keypath with `component` appended to the end.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`KeyPathParseError::InvalidKeyPath`] if `component` is not
+    /// [valid](is_valid_component) exactly as written.
+    pub fn join(&self, component: &str) -> Result<Self, KeyPathParseError> {
+        if !is_valid_component(component) {
+            return Err(KeyPathParseError::InvalidKeyPath);
+        }
+        Ok(Self {
+            raw: [self.raw.as_str(), component].join(DELIMITER),
+        })
+    }
+
+    /// Check whether `prefix` is this keypath or one of its ancestors, component by component.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use yaml_datastore::keypath::KeyPath;
+    /// let keypath = KeyPath::try_from("a.b.c").expect("keypath parsed");
+    /// assert!(keypath.starts_with(&KeyPath::try_from("a.b").expect("keypath parsed")));
+    /// assert!(!keypath.starts_with(&KeyPath::try_from("a.bc").expect("keypath parsed")));
+    /// ```
+    #[must_use]
+    pub fn starts_with(&self, prefix: &KeyPath) -> bool {
+        self.raw
+            .strip_prefix(prefix.raw.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(DELIMITER))
+    }
+
     /// Return an iterator over the keypath using the [default list of extensions][DEFAULT_EXTENSIONS].
     ///
     /// # Example
@@ -243,6 +397,135 @@ impl KeyPath {
     }
 }
 
+/// A single component of a [`KeyPathPattern`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    /// Matches a component exactly.
+    Literal(String),
+    /// Matches any single component.
+    Any,
+    /// Matches zero or more components.
+    AnyDepth,
+}
+
+/// A pattern selecting a set of keypaths, such as `users.*`.
+///
+/// Patterns are written like keypaths, with two additions:
+///
+/// * A component of `*` matches exactly one component.
+/// * A component of `**` matches zero or more components.
+///
+/// Since patterns often describe files, `/` is accepted as a delimiter as well, so `users/*` and
+/// `users.*` are the same pattern.
+///
+/// # Example
+///
+/// ```rust
+/// # use yaml_datastore::keypath::{KeyPath, KeyPathPattern};
+/// let pattern = KeyPathPattern::try_from("users/*").expect("pattern parsed");
+/// assert!(pattern.matches(&KeyPath::try_from("users.alice").expect("keypath parsed")));
+/// assert!(!pattern.matches(&KeyPath::try_from("users.alice.email").expect("keypath parsed")));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct KeyPathPattern {
+    /// Parsed segments of the pattern.
+    segments: Vec<Segment>,
+}
+
+impl TryFrom<&str> for KeyPathPattern {
+    /// The error returned if any components are empty or contain invalid characters.
+    type Error = KeyPathParseError;
+
+    /// Construct a [`KeyPathPattern`] from a string.
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let segments = value
+            .split(['.', '/'])
+            .map(|component| match component.trim() {
+                WILDCARD => Ok(Segment::Any),
+                RECURSIVE_WILDCARD => Ok(Segment::AnyDepth),
+                other => validate_and_trim(other).map(|c| Segment::Literal(c.to_owned())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { segments })
+    }
+}
+
+impl TryFrom<String> for KeyPathPattern {
+    /// The error returned if any components are empty or contain invalid characters.
+    type Error = KeyPathParseError;
+
+    /// Construct a [`KeyPathPattern`] from an owned string.
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::try_from(value.as_str())
+    }
+}
+
+impl From<KeyPathPattern> for String {
+    fn from(value: KeyPathPattern) -> Self {
+        value.to_string()
+    }
+}
+
+impl std::fmt::Display for KeyPathPattern {
+    /// Format the pattern in its normalized, `.`-delimited form.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let components: Vec<&str> = self
+            .segments
+            .iter()
+            .map(|s| match s {
+                Segment::Literal(c) => c.as_str(),
+                Segment::Any => WILDCARD,
+                Segment::AnyDepth => RECURSIVE_WILDCARD,
+            })
+            .collect();
+        write!(f, "{}", components.join(DELIMITER))
+    }
+}
+
+/// Recursively match pattern segments against keypath components.
+fn segments_match(segments: &[Segment], components: &[&str]) -> bool {
+    match (segments.first(), components.first()) {
+        (None, None) => true,
+        (Some(Segment::AnyDepth), _) => {
+            segments_match(&segments[1..], components)
+                || (!components.is_empty() && segments_match(segments, &components[1..]))
+        }
+        (Some(Segment::Any), Some(_)) => segments_match(&segments[1..], &components[1..]),
+        (Some(Segment::Literal(l)), Some(c)) if l == c => {
+            segments_match(&segments[1..], &components[1..])
+        }
+        _ => false,
+    }
+}
+
+impl KeyPathPattern {
+    /// Return whether `keypath` is matched by this pattern in its entirety.
+    #[must_use]
+    pub fn matches(&self, keypath: &KeyPath) -> bool {
+        segments_match(&self.segments, &keypath.components())
+    }
+
+    /// Return whether some keypath starting with `keypath` could be matched by this pattern.
+    ///
+    /// This is used to prune searches: if a directory or file can't contain a match, there is no
+    /// need to look inside it.
+    #[must_use]
+    pub fn could_match_within(&self, keypath: &KeyPath) -> bool {
+        fn prefix_match(segments: &[Segment], components: &[&str]) -> bool {
+            match (segments.first(), components.first()) {
+                (_, None) | (Some(Segment::AnyDepth), _) => true,
+                (None, Some(_)) => false,
+                (Some(Segment::Any), Some(_)) => prefix_match(&segments[1..], &components[1..]),
+                (Some(Segment::Literal(l)), Some(c)) => {
+                    l == c && prefix_match(&segments[1..], &components[1..])
+                }
+            }
+        }
+        prefix_match(&self.segments, &keypath.components())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -369,4 +652,43 @@ mod tests {
         let result = KeyPath::try_from(input).expect_err("invalid keypath");
         assert!(matches!(result, KeyPathParseError::InvalidKeyPath));
     }
+
+    #[test]
+    fn from_path() {
+        let result = KeyPath::from_path(Path::new("this/is/a/keypath.yaml")).expect("key parsed");
+        assert_eq!(result.to_string(), "this.is.a.keypath");
+    }
+
+    #[test]
+    fn err_from_path_dotted_stem() {
+        let result = KeyPath::from_path(Path::new("this/is.a.yaml")).expect_err("invalid keypath");
+        assert!(matches!(result, KeyPathParseError::InvalidKeyPath));
+    }
+
+    #[test]
+    fn ordering_is_by_component() {
+        let dotted = KeyPath::try_from("a.b").expect("key parsed");
+        let dashed = KeyPath::try_from("a-b").expect("key parsed");
+        assert!(dotted < dashed);
+    }
+
+    #[test]
+    fn pattern_wildcards() {
+        let keypath = KeyPath::try_from("users.alice.email").expect("key parsed");
+        let matching = [
+            "users.*.email",
+            "users/*/email",
+            "**",
+            "users.**",
+            "**.email",
+        ];
+        for pattern in matching {
+            let pattern = KeyPathPattern::try_from(pattern).expect("pattern parsed");
+            assert!(pattern.matches(&keypath), "{pattern} should match");
+        }
+        let pattern = KeyPathPattern::try_from("users.*").expect("pattern parsed");
+        assert!(!pattern.matches(&keypath));
+        assert!(pattern.could_match_within(&KeyPath::try_from("users").expect("key parsed")));
+        assert!(!pattern.could_match_within(&KeyPath::try_from("groups").expect("key parsed")));
+    }
 }
@@ -37,13 +37,47 @@
 //!
 //! [00]: https://yaml.org/
 
-use keypath::{KeyPath, KeyPathParseError};
+use cache::FileStamp;
+use index::{Index, IndexKey, Indexes};
+use keypath::{KeyPath, KeyPathParseError, KeyPathPattern};
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
-use serde_yaml::{Mapping, value::from_value};
-use std::path::{Path, PathBuf};
+use serde_yaml::{Mapping, Value, value::from_value};
+use std::{
+    ops::RangeBounds,
+    path::{Path, PathBuf},
+    sync::{PoisonError, RwLock},
+};
 use thiserror::Error;
 
+pub mod builder;
+pub mod cache;
+pub mod diff;
+pub mod document;
+pub mod flat;
+pub mod format;
+mod ignore;
+pub mod index;
 pub mod keypath;
+pub mod limits;
+pub mod lint;
+pub mod list;
+#[cfg(feature = "watch")]
+pub mod live;
+pub mod manifest;
+pub mod patch;
+mod relocate;
+mod restructure;
+mod sandbox;
+pub mod schema;
+mod settings;
+#[cfg(feature = "watch")]
+pub mod subscribe;
+mod transaction;
+pub mod walk;
+#[cfg(feature = "watch")]
+pub mod watch;
+
+pub use diff::diff;
 
 /// Error type for this crate.
 #[derive(Error, Debug)]
@@ -56,6 +90,10 @@ pub enum Error {
     #[error("data parse error")]
     DataParseError(#[from] serde_yaml::Error),
 
+    /// Data could not be converted to or from JSON.
+    #[error("JSON error")]
+    JsonError(#[from] serde_json::Error),
+
     /// A key requested via [`Datastore::get_with_key`] or [`Datastore::get_with_key_vec`] was not found.
     #[error("key not found in data")]
     KeyNotFound,
@@ -67,6 +105,62 @@ pub enum Error {
     /// Error returned from the keypath parser during parsing.
     #[error(transparent)]
     KeyPathError(#[from] KeyPathParseError),
+
+    /// An index was queried by a name that was never [created](Datastore::create_index).
+    #[error("no index named {0:?}")]
+    IndexNotFound(String),
+
+    /// A document or file was expected to contain a mapping, but contained something else.
+    #[error("expected a mapping")]
+    NotAMapping,
+
+    /// Restructuring the files at a keypath would have changed the value of some keypath beneath
+    /// it, so nothing was changed.
+    #[error("restructuring {0} would change resolved values")]
+    ValueChanged(String),
+
+    /// The source and destination of a [move](Datastore::rename) or [copy](Datastore::copy) are
+    /// the same keypath, or one is beneath the other.
+    #[error("{0} and {1} overlap")]
+    OverlappingKeyPaths(String, String),
+
+    /// A `test` operation of a [patch](Datastore::apply_patch) failed at the given path, so the
+    /// patch wasn't applied.
+    #[error("patch test failed at {0}")]
+    PatchTestFailed(String),
+
+    /// A file or directory could not be read while walking the datastore.
+    #[error(transparent)]
+    Walk(Box<walk::WalkError>),
+
+    /// A schema passed to [`Datastore::add_schema`] is not a valid JSON Schema.
+    #[error("invalid schema: {0}")]
+    InvalidSchema(String),
+
+    /// A write would have left data that violates its [schema](Datastore::add_schema), so
+    /// nothing was changed. Only returned when schemas are
+    /// [enforced](Datastore::enforce_schemas).
+    #[error("data violates its schema: {}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "))]
+    SchemaViolation(Vec<schema::Violation>),
+
+    /// The datastore's [manifest] is well-formed YAML but its settings are invalid.
+    #[error("invalid manifest: {0}")]
+    InvalidManifest(String),
+
+    /// A path escapes the root of a [sandboxed](builder::Builder::sandbox) datastore, so it
+    /// wasn't accessed.
+    #[error("{} is outside the datastore root", .0.display())]
+    OutsideRoot(PathBuf),
+
+    /// A data file exceeds one of the datastore's [limits](limits::Limits), so it wasn't parsed.
+    #[error("{} exceeds the {limit}", .0.display(), limit = .1)]
+    LimitExceeded(PathBuf, limits::Limit),
+}
+
+impl From<walk::WalkError> for Error {
+    fn from(value: walk::WalkError) -> Self {
+        Self::Walk(Box::new(value))
+    }
 }
 
 fn yaml_mapping_recurse<T, S>(map: &Mapping, keys: &[S]) -> Result<T, Error>
@@ -93,6 +187,77 @@ where
     }
 }
 
+/// Walk `value` along `keys`, indexing mappings by key and sequences by position.
+///
+/// This is how the keys part of a keypath is resolved within a file, so `tags.0` refers to the
+/// first element of the sequence `tags`.
+pub(crate) fn value_at<S: AsRef<str>>(value: Value, keys: &[S]) -> Option<Value> {
+    keys.iter().try_fold(value, |value, key| {
+        let key = key.as_ref();
+        match value {
+            Value::Mapping(mut mapping) => mapping.remove(key),
+            Value::Sequence(mut sequence) => {
+                let index: usize = key.parse().ok()?;
+                (index < sequence.len()).then(|| sequence.swap_remove(index))
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Set `new` at `keys` within `value`, creating containers as needed.
+///
+/// This is the inverse of [`value_at`]. Missing containers are created as sequences if the key
+/// indexing into them is numeric, and as mappings otherwise. Sequences are padded with `null` if
+/// an index past their end is set. Anything in the way that isn't a container is replaced.
+pub(crate) fn set_value_at<S: AsRef<str>>(value: &mut Value, keys: &[S], new: Value) {
+    let Some((key, rest)) = keys.split_first() else {
+        *value = new;
+        return;
+    };
+    let key = key.as_ref();
+    let index = key.parse::<usize>().ok();
+    let child = match (value, index) {
+        (Value::Sequence(sequence), Some(index)) => {
+            if sequence.len() <= index {
+                sequence.resize(index + 1, Value::Null);
+            }
+            &mut sequence[index]
+        }
+        (Value::Mapping(mapping), _) => mapping.entry(key.into()).or_insert(Value::Null),
+        (value, _) => {
+            *value = if index.is_some() {
+                Value::Sequence(Vec::new())
+            } else {
+                Value::Mapping(Mapping::new())
+            };
+            return set_value_at(value, keys, new);
+        }
+    };
+    set_value_at(child, rest, new);
+}
+
+/// Remove and return the value at `keys` within `value`.
+///
+/// Mapping keys keep their order, and later elements of a sequence shift down to fill the gap.
+/// Returns `None`, leaving `value` untouched, if there is nothing at `keys` or `keys` is empty.
+pub(crate) fn remove_value_at<S: AsRef<str>>(value: &mut Value, keys: &[S]) -> Option<Value> {
+    let (last, parents) = keys.split_last()?;
+    let parent = parents.iter().try_fold(value, |value, key| match value {
+        Value::Mapping(mapping) => mapping.get_mut(key.as_ref()),
+        Value::Sequence(sequence) => sequence.get_mut(key.as_ref().parse::<usize>().ok()?),
+        _ => None,
+    })?;
+    match parent {
+        Value::Mapping(mapping) => mapping.shift_remove(last.as_ref()),
+        Value::Sequence(sequence) => {
+            let index: usize = last.as_ref().parse().ok()?;
+            (index < sequence.len()).then(|| sequence.remove(index))
+        }
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod yaml_mapping_recurse_tests {
     use super::Error;
@@ -145,38 +310,431 @@ mod yaml_mapping_recurse_tests {
     }
 }
 
+/// The kind of a YAML value, as returned by [`Datastore::kind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ValueKind {
+    /// The `null` value, which is also what an empty file contains.
+    Null,
+    /// A boolean.
+    Bool,
+    /// An integer.
+    Integer,
+    /// A floating point number.
+    Float,
+    /// A string.
+    String,
+    /// A sequence of values.
+    Sequence,
+    /// A mapping of keys to values.
+    Mapping,
+    /// A value with an explicit YAML tag, such as `!Thing`.
+    Tagged,
+}
+
+impl From<&Value> for ValueKind {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Null => Self::Null,
+            Value::Bool(_) => Self::Bool,
+            Value::Number(n) if n.is_f64() => Self::Float,
+            Value::Number(_) => Self::Integer,
+            Value::String(_) => Self::String,
+            Value::Sequence(_) => Self::Sequence,
+            Value::Mapping(_) => Self::Mapping,
+            Value::Tagged(_) => Self::Tagged,
+        }
+    }
+}
+
 /// Handle for a YAML datastore.
 ///
-/// Open with [`open()`](Datastore::open).
+/// Open with [`open()`](Datastore::open) or [`try_open()`](Datastore::try_open).
 /// Access with [`get()`](Datastore::get).
+/// Explore with [`list()`](Datastore::list) and [`walk()`](Datastore::walk).
+/// Search with [`create_index()`](Datastore::create_index) and [`lookup()`](Datastore::lookup).
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Datastore {
     /// The filesystem root of the datastore. All lookups are done relative to this path.
     root: PathBuf,
+
+    /// Secondary indexes. These are derived from the data, so they aren't serialized.
+    #[serde(skip)]
+    indexes: RwLock<Indexes>,
+
+    /// Schemas attached to keypath patterns. Like indexes, these aren't serialized.
+    #[serde(skip)]
+    schemas: RwLock<schema::Schemas>,
+
+    /// Extensions and ignore rules, from the [manifest] and [builder] options.
+    #[serde(skip)]
+    settings: settings::Settings,
+
+    /// Errors ignored while opening, since [`open()`](Datastore::open) can't fail.
+    #[serde(skip)]
+    open_errors: Vec<Error>,
 }
 
 impl Datastore {
     /// Open a handle to a datastore at the given path.
     ///
-    /// At present, this doesn't actually perform any operations.
+    /// The only operations performed are reading the [manifest] and [ignore
+    /// file](builder::IGNORE_PATH), and loading the [index cache](cache), if any exist. In the
+    /// latter case any files changed since the cache was saved are reindexed. Use
+    /// [`builder()`](Self::builder) to open with options.
+    ///
+    /// Opening can't fail, so a manifest or ignore file that can't be read or is invalid is
+    /// ignored, as are schemas and indexes the manifest declares that can't be added, and
+    /// cached indexes that can't be refreshed. Each of those errors is reported by
+    /// [`open_errors()`](Self::open_errors).
     pub fn open<P: Into<PathBuf>>(path: P) -> Datastore {
-        Datastore { root: path.into() }
+        Self::builder(path).open()
+    }
+
+    /// Return the errors that were ignored while opening the datastore, in the order they
+    /// happened.
+    ///
+    /// [`open()`](Self::open) can't fail, so it carries on with whatever could be loaded, such
+    /// as default settings in place of a [manifest] that can't be parsed, or indexes from the
+    /// [index cache](cache) that couldn't be refreshed. Those errors are kept here so they can be
+    /// reported. [`try_open()`](Self::try_open) returns them instead,
+    /// so this is always empty for a datastore it opened.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use yaml_datastore::Datastore;
+    ///
+    /// let datastore = Datastore::open("tests/data");
+    /// assert!(datastore.open_errors().is_empty());
+    /// ```
+    #[must_use]
+    pub fn open_errors(&self) -> &[Error] {
+        &self.open_errors
+    }
+
+    /// Acquire the indexes for reading. Indexes are always left consistent, so poisoning is ignored.
+    fn read_indexes(&self) -> std::sync::RwLockReadGuard<'_, Indexes> {
+        self.indexes.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Acquire the indexes for writing. Indexes are always left consistent, so poisoning is ignored.
+    fn write_indexes(&self) -> std::sync::RwLockWriteGuard<'_, Indexes> {
+        self.indexes.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// List every data file in the datastore, relative to the root, in sorted order.
+    fn data_files(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut files = Vec::new();
+        self.collect_data_files(Path::new(""), &mut files)?;
+        Ok(files)
+    }
+
+    /// Recursively collect the data files under `dir`, relative to the root, in sorted order.
+    fn collect_data_files(&self, dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+        let mut entries = self.read_directory(dir)?;
+        entries.sort_by_key(std::fs::DirEntry::file_name);
+        for entry in entries {
+            let path = dir.join(entry.file_name());
+            if path == Path::new(cache::SIDECAR_DIR) {
+                continue;
+            }
+            let is_dir = entry.file_type()?.is_dir();
+            if self.is_ignored(&path, is_dir) {
+                continue;
+            }
+            if is_dir {
+                self.collect_data_files(&path, files)?;
+            } else if self.has_data_extension(&path) {
+                files.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Read and parse the data file at `path`, relative to the root.
+    pub(crate) fn read_value(&self, path: &Path) -> Result<Value, Error> {
+        let file_string = self.read_data_file(path)?;
+        Ok(serde_yaml::from_str(&file_string)?)
+    }
+
+    /// Serialize `value` and write it to the data file at `path`, relative to the root.
+    ///
+    /// See [`write_contents()`](Self::write_contents) for how the file is written.
+    pub(crate) fn write_value(&self, path: &Path, value: &Value) -> Result<(), Error> {
+        self.write_contents(path, &serde_yaml::to_string(value)?)
+    }
+
+    /// Write `contents` to the data file at `path`, relative to the root.
+    ///
+    /// Missing parent directories are created. The file is written to a hidden temporary file next
+    /// to it and renamed into place, so readers never see a partially written file. Indexes are
+    /// updated with the new contents.
+    pub(crate) fn write_contents(&self, path: &Path, contents: &str) -> Result<(), Error> {
+        let full_path = self.confine(path)?;
+        let parent = full_path.parent().unwrap_or(&self.root);
+        std::fs::create_dir_all(parent)?;
+        let mut temporary_name = std::ffi::OsString::from(".");
+        temporary_name.push(full_path.file_name().unwrap_or_default());
+        temporary_name.push(".tmp");
+        let temporary = parent.join(temporary_name);
+        std::fs::write(&temporary, contents)?;
+        std::fs::rename(&temporary, &full_path)?;
+
+        let mut indexes = self.write_indexes();
+        if !indexes.indexes.is_empty()
+            && let Ok(keypath) = KeyPath::from_path(path)
+        {
+            let value = serde_yaml::from_str(contents).unwrap_or(Value::Null);
+            indexes.insert_file(path, &keypath, &value);
+            let stamp = FileStamp::new(&std::fs::metadata(&full_path)?, contents.as_bytes())?;
+            indexes.stamps.insert(path.to_owned(), stamp);
+        }
+        Ok(())
+    }
+
+    /// Remove the data file at `path`, relative to the root, and drop it from the indexes.
+    pub(crate) fn remove_data_file(&self, path: &Path) -> Result<(), Error> {
+        std::fs::remove_file(self.confine(path)?)?;
+        self.write_indexes().remove_file(path);
+        Ok(())
     }
 
-    /// Helper function to support [`Self::get`] that attempts to access the given path and YAML key.
-    fn try_get<P, S, T>(path: P, keys: &[S]) -> Option<T>
+    /// Parse the data file at `path` if it has changed since `stamp` was taken.
+    ///
+    /// Returns the new stamp along with the parsed value, or `None` for the value if the file is
+    /// unchanged. Files that can't be parsed or exceed the datastore's [limits](limits::Limits)
+    /// produce a `Null` value, so they are indexed as empty. A file too large for the limit is
+    /// only read, and stamped, as far as the limit, so it stays empty until it fits.
+    fn reparse_if_changed(
+        &self,
+        path: &Path,
+        stamp: Option<&FileStamp>,
+    ) -> Result<(FileStamp, Option<Value>), Error> {
+        let full_path = self.confine(path)?;
+        let metadata = std::fs::metadata(&full_path)?;
+        if let Some(stamp) = stamp
+            && stamp.matches_metadata(&metadata)
+        {
+            return Ok((stamp.clone(), None));
+        }
+        let contents = self.read_bounded(&full_path)?;
+        let new_stamp = FileStamp::new(&metadata, &contents)?;
+        if stamp.is_some_and(|s| s.matches_contents(&contents)) {
+            return Ok((new_stamp, None));
+        }
+        let value = std::str::from_utf8(&contents)
+            .ok()
+            .filter(|_| self.within_limits(&contents))
+            .and_then(|s| serde_yaml::from_str(s).ok())
+            .unwrap_or(Value::Null);
+        Ok((new_stamp, Some(value)))
+    }
+
+    /// Bring `indexes` up to date with the files on disk, reparsing only files that changed.
+    fn sync_indexes(&self, indexes: &mut Indexes) -> Result<(), Error> {
+        let files = self.data_files()?;
+        let removed: Vec<PathBuf> = indexes
+            .stamps
+            .keys()
+            .filter(|path| files.binary_search(path).is_err())
+            .cloned()
+            .collect();
+        for path in removed {
+            indexes.remove_file(&path);
+        }
+
+        for path in files {
+            let Ok(keypath) = KeyPath::from_path(&path) else {
+                continue;
+            };
+            let (stamp, value) = self.reparse_if_changed(&path, indexes.stamps.get(&path))?;
+            if let Some(value) = value {
+                indexes.insert_file(&path, &keypath, &value);
+            }
+            indexes.stamps.insert(path, stamp);
+        }
+        Ok(())
+    }
+
+    /// Create a secondary index named `name`, replacing any existing index of the same name.
+    ///
+    /// Records are selected by `pattern` (see [`KeyPathPattern`]) and indexed by the value found at
+    /// the keypath `field` within each record. The index is built immediately by parsing every file
+    /// in the datastore, unless an identical index was loaded from the [index cache](cache).
+    /// See the [index] module for details.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::KeyPathError`] if `pattern` or `field` is invalid.
+    ///
+    /// Returns [`Error::IOError`] if the datastore's files cannot be read, and
+    /// [`Error::OutsideRoot`] if one leads outside a [sandboxed](builder::Builder::sandbox) root.
+    pub fn create_index(&self, name: &str, pattern: &str, field: &str) -> Result<(), Error> {
+        let pattern = KeyPathPattern::try_from(pattern)?;
+        let field = KeyPath::try_from(field)?;
+        let mut indexes = self.write_indexes();
+        if indexes
+            .indexes
+            .get(name)
+            .is_some_and(|index| index.is_defined_as(&pattern, &field))
+        {
+            return Ok(());
+        }
+
+        // Forgetting the stamps reparses every file into every index. Reindexing a file is
+        // idempotent, so the existing indexes are unaffected apart from being refreshed.
+        indexes
+            .indexes
+            .insert(name.to_owned(), Index::new(pattern, field));
+        indexes.stamps.clear();
+        self.sync_indexes(&mut indexes)
+    }
+
+    /// Rebuild every index from scratch by reparsing every file in the datastore.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IOError`] if the datastore's files cannot be read.
+    pub fn rebuild_indexes(&self) -> Result<(), Error> {
+        let mut indexes = self.write_indexes();
+        indexes.clear();
+        self.sync_indexes(&mut indexes)
+    }
+
+    /// Update every index for files that were added, removed or changed since they were indexed.
+    ///
+    /// This is needed if files were changed outside of this handle. Unlike
+    /// [`rebuild_indexes()`](Datastore::rebuild_indexes), only files whose modification time, size
+    /// and contents differ from when they were last indexed are reparsed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IOError`] if the datastore's files cannot be read.
+    pub fn refresh_indexes(&self) -> Result<(), Error> {
+        self.sync_indexes(&mut self.write_indexes())
+    }
+
+    /// Save every index to the [index cache](cache) so later calls to [`open()`](Datastore::open)
+    /// can reuse them.
+    ///
+    /// Indexes are [refreshed](Datastore::refresh_indexes) first, so the cache reflects the files
+    /// on disk at the time of saving.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IOError`] if the datastore's files cannot be read or the cache cannot be
+    /// written, and [`Error::OutsideRoot`] if the cache would be written outside a
+    /// [sandboxed](builder::Builder::sandbox) root.
+    pub fn save_index_cache(&self) -> Result<(), Error> {
+        let mut indexes = self.write_indexes();
+        self.sync_indexes(&mut indexes)?;
+        cache::save(self, &indexes)
+    }
+
+    /// Look up the keypaths of all records whose indexed field equals `key`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IndexNotFound`] if there is no index named `index`.
+    pub fn lookup<K: Into<IndexKey>>(&self, index: &str, key: K) -> Result<Vec<KeyPath>, Error> {
+        let indexes = self.read_indexes();
+        let index = indexes
+            .indexes
+            .get(index)
+            .ok_or_else(|| Error::IndexNotFound(index.to_owned()))?;
+        Ok(index.get(&key.into()))
+    }
+
+    /// Look up the keypaths of all records whose indexed field falls within `range`.
+    ///
+    /// Records are returned in order of their indexed value.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IndexNotFound`] if there is no index named `index`.
+    pub fn lookup_range<R: RangeBounds<IndexKey>>(
+        &self,
+        index: &str,
+        range: R,
+    ) -> Result<Vec<KeyPath>, Error> {
+        let indexes = self.read_indexes();
+        let index = indexes
+            .indexes
+            .get(index)
+            .ok_or_else(|| Error::IndexNotFound(index.to_owned()))?;
+        Ok(index.range(range))
+    }
+
+    /// Helper function to support [`Self::get`] that attempts to access the given path, relative
+    /// to the root, and YAML key.
+    ///
+    /// A file that doesn't exist, can't be read or can't be parsed gives `None`, but one that is
+    /// outside a [sandboxed](builder::Builder::sandbox) root or exceeds the datastore's
+    /// [limits](limits::Limits) is an error.
+    pub(crate) fn try_get<S>(&self, path: &Path, keys: &[S]) -> Result<Option<Value>, Error>
     where
-        P: AsRef<Path>,
-        S: AsRef<str> + serde_yaml::mapping::Index,
-        T: DeserializeOwned,
+        S: AsRef<str>,
+    {
+        let file_string = match self.read_data_file(path) {
+            Ok(file_string) => file_string,
+            Err(e @ (Error::OutsideRoot(_) | Error::LimitExceeded(..))) => return Err(e),
+            Err(_) => return Ok(None),
+        };
+        let Ok(value) = serde_yaml::from_str(&file_string) else {
+            return Ok(None);
+        };
+        Ok(value_at(value, keys))
+    }
+
+    /// Resolve a keypath to the first candidate value accepted by `accept`.
+    ///
+    /// This is the resolution shared by [`get()`](Self::get) and friends. Candidates are visited in
+    /// the order described there, and a candidate rejected by `accept` is skipped as if it didn't
+    /// exist.
+    fn resolve<T, F>(&self, keypath: &str, mut accept: F) -> Result<T, Error>
+    where
+        F: FnMut(Value) -> Option<T>,
     {
-        let file_string = std::fs::read_to_string(path).ok()?;
-        if keys.is_empty() {
-            Some(serde_yaml::from_str(&file_string).ok()?)
-        } else {
-            let mapping: Mapping = serde_yaml::from_str(&file_string).ok()?;
-            yaml_mapping_recurse(&mapping, keys).ok()?
+        let keypath = KeyPath::try_from(keypath)?;
+        for (path, keys) in self.candidates(&keypath) {
+            if let Some(data) = self.try_get(&path, &keys)?.and_then(&mut accept) {
+                return Ok(data);
+            }
+        }
+        Err(Error::KeyNotFound)
+    }
+
+    /// Resolve `keypath` to its raw value, visiting candidates as [`resolve()`](Self::resolve)
+    /// does, except that a candidate file that exists but can't be read or parsed is an error
+    /// rather than being skipped, since it might hold the value.
+    ///
+    /// Such errors are returned as a [`WalkError`](walk::WalkError) naming the file.
+    pub(crate) fn resolve_checked(&self, keypath: &KeyPath) -> Result<Value, Error> {
+        for (path, keys) in self.candidates(keypath) {
+            let contents = match self.read_data_file(&path) {
+                Ok(contents) => contents,
+                Err(Error::IOError(e)) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(error) => return Err(walk::WalkError { path, error }.into()),
+            };
+            let value = serde_yaml::from_str(&contents).map_err(|e| walk::WalkError {
+                path: path.clone(),
+                error: e.into(),
+            })?;
+            if let Some(value) = value_at(value, &keys) {
+                return Ok(value);
+            }
         }
+        Err(Error::KeyNotFound)
+    }
+
+    /// Resolve `keypath` as [`resolve_checked()`](Self::resolve_checked) does, returning the
+    /// error for a file that can't be read or parsed without the path.
+    fn resolve_value(&self, keypath: &str) -> Result<Value, Error> {
+        let keypath = KeyPath::try_from(keypath)?;
+        self.resolve_checked(&keypath).map_err(|e| match e {
+            Error::Walk(walk) => walk.error,
+            e => e,
+        })
     }
 
     /// Get a value from the datastore given a keypath.
@@ -200,6 +758,9 @@ impl Datastore {
     /// 3. The contents of the key `c.d` in `a/b.yaml`.
     /// 4. The contents of the key `b.c.d` in `a.yaml`.
     ///
+    /// A [manifest](manifest::Resolution) can reverse this order, so that the key `b.c.d` in
+    /// `a.yaml` is tried first.
+    ///
     /// For the above, the dot-notation for YAML keys implies nesting. So for `b.c.d`:
     ///
     /// ```text
@@ -208,19 +769,98 @@ impl Datastore {
     ///     d: 42
     /// ```
     ///
+    /// Keys that are non-negative integers also index into sequences, so `tags.0` is the first
+    /// element of the sequence under `tags`.
+    ///
     /// # Errors
     ///
     /// Returns [`Error::KeyPathError`] if `keypath` is invalid.
     ///
     /// Returns [`Error::KeyNotFound`] if the given key was not found.
+    ///
+    /// Returns [`Error::LimitExceeded`] if a candidate file exceeds the datastore's
+    /// [limits](limits::Limits), and [`Error::OutsideRoot`] if one leads outside a
+    /// [sandboxed](builder::Builder::sandbox) root. Unlike files that can't be read or parsed,
+    /// these aren't skipped, even if a later candidate would resolve the keypath, so a file can't
+    /// hide itself from the limits or sandbox to expose another value in its place.
     pub fn get<T: DeserializeOwned>(&self, keypath: &str) -> Result<T, Error> {
+        self.resolve(keypath, |value| from_value(value).ok())
+    }
+
+    /// Get the raw YAML value at a keypath, without deserializing it into a Rust type.
+    ///
+    /// Candidates are visited in the same order as in [`get()`](Self::get). Unlike `get()`,
+    /// which skips files it can't use, a candidate file that exists but can't be read or parsed
+    /// is an error, since it might be where the value is.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::KeyPathError`] if `keypath` is invalid.
+    ///
+    /// Returns [`Error::KeyNotFound`] if the given key was not found.
+    ///
+    /// Returns [`Error::DataParseError`] or [`Error::IOError`] if a candidate file can't be
+    /// parsed or read.
+    pub fn get_value(&self, keypath: &str) -> Result<Value, Error> {
+        self.resolve_value(keypath)
+    }
+
+    /// Check whether a keypath resolves to a value.
+    ///
+    /// The keypath is resolved exactly as in [`get_value()`](Self::get_value).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::KeyPathError`] if `keypath` is invalid.
+    ///
+    /// Returns [`Error::DataParseError`] or [`Error::IOError`] if a candidate file can't be
+    /// parsed or read.
+    pub fn exists(&self, keypath: &str) -> Result<bool, Error> {
+        match self.resolve_value(keypath) {
+            Ok(_) => Ok(true),
+            Err(Error::KeyNotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the [kind](ValueKind) of YAML value at a keypath.
+    ///
+    /// The keypath is resolved exactly as in [`get_value()`](Self::get_value).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::KeyPathError`] if `keypath` is invalid.
+    ///
+    /// Returns [`Error::KeyNotFound`] if the given key was not found.
+    ///
+    /// Returns [`Error::DataParseError`] or [`Error::IOError`] if a candidate file can't be
+    /// parsed or read.
+    pub fn kind(&self, keypath: &str) -> Result<ValueKind, Error> {
+        self.resolve_value(keypath)
+            .map(|value| ValueKind::from(&value))
+    }
+
+    /// Set the value at a keypath, replacing whatever was there, as a JSON Patch `replace` does
+    /// but without requiring it to exist. Tests use this as shorthand for changing files.
+    #[cfg(test)]
+    pub(crate) fn set<T: Serialize>(&self, keypath: &str, value: &T) -> Result<(), Error> {
         let keypath = KeyPath::try_from(keypath)?;
-        for (path, keys) in keypath.iter() {
-            if let Some(data) = Self::try_get(self.root.join(path), &keys) {
-                return Ok(data);
-            }
+        let value = serde_yaml::to_value(value)?;
+        let mut transaction = transaction::Transaction::new(self);
+        self.replace_in(&mut transaction, &keypath, value)?;
+        transaction.commit()
+    }
+
+    /// Remove the value at a keypath, as a JSON Patch `remove` does. Tests use this as shorthand
+    /// for changing files.
+    #[cfg(test)]
+    pub(crate) fn remove(&self, keypath: &str) -> Result<(), Error> {
+        let keypath = KeyPath::try_from(keypath)?;
+        let mut transaction = transaction::Transaction::new(self);
+        if !self.remove_in(&mut transaction, &keypath)? {
+            return Err(Error::KeyNotFound);
         }
-        Err(Error::KeyNotFound)
+        transaction.commit()
     }
 
     /// Get all the data from a given YAML file in the datastore.
@@ -233,6 +873,9 @@ impl Datastore {
     ///
     /// Will return [`Error::IOError`] if a file at `path` cannot be read.
     ///
+    /// Will return [`Error::OutsideRoot`] if the datastore is [sandboxed](builder::Builder::sandbox)
+    /// and `path` escapes its root.
+    ///
     /// Will return [`Error::DataParseError`] if:
     /// * A file at `path` is not able to be parsed as valid YAML
     /// * The return type specified does not match the type found in the input file.
@@ -241,8 +884,7 @@ impl Datastore {
         P: AsRef<Path>,
         T: DeserializeOwned,
     {
-        let full_path = self.root.join(&path);
-        let file_string = std::fs::read_to_string(&full_path)?;
+        let file_string = self.read_data_file(path.as_ref())?;
         let result = serde_yaml::from_str(&file_string)?;
         Ok(result)
     }
@@ -257,6 +899,9 @@ impl Datastore {
     ///
     /// Will return [`Error::IOError`] if a file at `path` cannot be read.
     ///
+    /// Will return [`Error::OutsideRoot`] if the datastore is [sandboxed](builder::Builder::sandbox)
+    /// and `path` escapes its root.
+    ///
     /// Will return [`Error::DataParseError`] if:
     /// * A file at `path` is not able to be parsed as valid YAML
     /// * The return type specified does not match the type found in the input file.
@@ -273,8 +918,7 @@ impl Datastore {
             return self.get_with_path(path);
         }
 
-        let full_path = self.root.join(&path);
-        let file_string = std::fs::read_to_string(&full_path)?;
+        let file_string = self.read_data_file(path.as_ref())?;
         let mapping: Mapping = serde_yaml::from_str(&file_string)?;
         let value = mapping.get(key).ok_or(Error::KeyNotFound)?.to_owned();
         Ok(from_value(value)?)
@@ -304,6 +948,9 @@ impl Datastore {
     ///
     /// Will return [`Error::IOError`] if a file at `path` cannot be read.
     ///
+    /// Will return [`Error::OutsideRoot`] if the datastore is [sandboxed](builder::Builder::sandbox)
+    /// and `path` escapes its root.
+    ///
     /// Will return [`Error::DataParseError`] if:
     /// * A file at `path` is not able to be parsed as valid YAML
     /// * The return type specified does not match the type found in the input file.
@@ -321,8 +968,7 @@ impl Datastore {
             return self.get_with_path(path);
         }
 
-        let full_path = self.root.join(&path);
-        let file_string = std::fs::read_to_string(&full_path)?;
+        let file_string = self.read_data_file(path.as_ref())?;
         let mapping: Mapping = serde_yaml::from_str(&file_string)?;
         yaml_mapping_recurse(&mapping, key_vec)
     }
@@ -476,4 +1122,184 @@ mod tests {
             .unwrap_err();
         assert!(matches!(result, Error::DataParseError(_)));
     }
+
+    #[test]
+    fn index_lookup() {
+        let datastore: Datastore = Datastore::open(TEST_DATASTORE_PATH);
+        datastore.create_index("by_id", "*", "id").unwrap();
+        let found = datastore.lookup("by_id", 2u64).unwrap();
+        assert_eq!(found, vec![KeyPath::try_from("no_tags").unwrap()]);
+
+        let found = datastore
+            .lookup_range("by_id", IndexKey::from(1)..=IndexKey::from(2))
+            .unwrap();
+        let found: Vec<String> = found.iter().map(ToString::to_string).collect();
+        assert_eq!(found, vec!["complete", "no_tags"]);
+    }
+
+    #[test]
+    fn index_not_found() {
+        let datastore: Datastore = Datastore::open(TEST_DATASTORE_PATH);
+        let result = datastore.lookup("missing", "value").unwrap_err();
+        assert!(matches!(result, Error::IndexNotFound(_)));
+    }
+
+    #[test]
+    fn index_cache_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.yaml"), "id: 1").unwrap();
+        std::fs::write(dir.path().join("b.yaml"), "id: 2").unwrap();
+        let datastore = Datastore::open(dir.path());
+        datastore.create_index("by_id", "*", "id").unwrap();
+        datastore.save_index_cache().unwrap();
+        assert!(dir.path().join(cache::CACHE_PATH).is_file());
+
+        std::fs::write(dir.path().join("b.yaml"), "id: 3").unwrap();
+        std::fs::remove_file(dir.path().join("a.yaml")).unwrap();
+        let reopened = Datastore::open(dir.path());
+        assert!(reopened.lookup("by_id", 1).unwrap().is_empty());
+        assert!(reopened.lookup("by_id", 2).unwrap().is_empty());
+        let found = reopened.lookup("by_id", 3).unwrap();
+        assert_eq!(found, vec![KeyPath::try_from("b").unwrap()]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn index_cache_refresh_error() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.yaml"), "id: 1").unwrap();
+        let datastore = Datastore::open(dir.path());
+        datastore.create_index("by_id", "*", "id").unwrap();
+        datastore.save_index_cache().unwrap();
+
+        // A dangling symlink is listed as a data file, but can't be read to reindex it.
+        std::os::unix::fs::symlink("missing.yaml", dir.path().join("b.yaml")).unwrap();
+        let reopened = Datastore::open(dir.path());
+        assert!(matches!(reopened.open_errors(), [Error::IOError(_)]));
+        assert!(matches!(
+            Datastore::try_open(dir.path()),
+            Err(Error::IOError(_))
+        ));
+    }
+
+    #[test]
+    fn exists_and_value() {
+        let datastore: Datastore = Datastore::open(TEST_DATASTORE_PATH);
+        assert!(datastore.exists("complete.nested").unwrap());
+        assert!(!datastore.exists("complete.missing").unwrap());
+        assert!(matches!(
+            datastore.exists("contains/slash").unwrap_err(),
+            Error::KeyPathError(_)
+        ));
+
+        let value = datastore.get_value("complete.tags").unwrap();
+        assert_eq!(value.as_sequence().map(Vec::len), Some(3));
+    }
+
+    #[test]
+    fn broken_candidate_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("a")).unwrap();
+        std::fs::write(dir.path().join("a/b.yaml"), "x: [unclosed").unwrap();
+        std::fs::write(dir.path().join("a.yaml"), "b: {x: 1}").unwrap();
+        let datastore = Datastore::open(dir.path());
+
+        // `get()` skips the broken file, but the others can't know the value isn't in it.
+        assert_eq!(datastore.get::<i64>("a.b.x").unwrap(), 1);
+        assert!(matches!(
+            datastore.get_value("a.b.x"),
+            Err(Error::DataParseError(_))
+        ));
+        assert!(matches!(
+            datastore.exists("a.b.x"),
+            Err(Error::DataParseError(_))
+        ));
+        assert!(matches!(
+            datastore.kind("a.b"),
+            Err(Error::DataParseError(_))
+        ));
+        // Candidates before the broken file are still found.
+        assert_eq!(datastore.kind("a").unwrap(), ValueKind::Mapping);
+    }
+
+    #[test]
+    fn keypath_sequence_index() {
+        let datastore: Datastore = Datastore::open(TEST_DATASTORE_PATH);
+        let parsed: String = datastore.get("complete.tags.1").unwrap();
+        assert_eq!(parsed, "done");
+        assert!(!datastore.exists("complete.tags.3").unwrap());
+    }
+
+    #[test]
+    fn keypath_into_sequence_file() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("list.yaml"), "- a\n- {b: 1}").unwrap();
+        let datastore = Datastore::open(dir.path());
+        assert_eq!(datastore.get::<String>("list.0").unwrap(), "a");
+        assert_eq!(datastore.get::<i64>("list.1.b").unwrap(), 1);
+        assert!(!datastore.exists("list.b").unwrap());
+        assert!(!datastore.exists("list.2").unwrap());
+    }
+
+    #[test]
+    fn value_kinds() {
+        let datastore: Datastore = Datastore::open(TEST_DATASTORE_PATH);
+        let kinds = [
+            ("complete", ValueKind::Mapping),
+            ("complete.tags", ValueKind::Sequence),
+            ("complete.name", ValueKind::String),
+            ("complete.id", ValueKind::Integer),
+            ("complete.rating", ValueKind::Float),
+            ("complete.complete", ValueKind::Bool),
+            ("empty", ValueKind::Null),
+        ];
+        for (keypath, kind) in kinds {
+            assert_eq!(datastore.kind(keypath).unwrap(), kind, "{keypath}");
+        }
+    }
+
+    #[test]
+    fn set_value_creates_containers() {
+        let mut value = Value::Null;
+        set_value_at(&mut value, &["a", "b"], Value::Bool(true));
+        set_value_at(&mut value, &["a", "list", "1"], Value::Bool(true));
+        let expected: Value = serde_yaml::from_str("a: {b: true, list: [null, true]}").unwrap();
+        assert_eq!(value, expected);
+    }
+
+    #[test]
+    fn remove_value_keeps_order() {
+        let mut value: Value = serde_yaml::from_str("a: {x: 1, y: 2, z: [1, 2, 3]}").unwrap();
+        assert_eq!(
+            remove_value_at(&mut value, &["a", "x"]),
+            Some(Value::from(1))
+        );
+        assert_eq!(
+            remove_value_at(&mut value, &["a", "z", "0"]),
+            Some(Value::from(1))
+        );
+        assert_eq!(remove_value_at(&mut value, &["a", "missing"]), None);
+        let expected: Value = serde_yaml::from_str("a: {y: 2, z: [2, 3]}").unwrap();
+        assert_eq!(value, expected);
+    }
+
+    #[test]
+    fn set_and_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.yaml"), "b: {c: 1}\nkeep: true").unwrap();
+        let datastore = Datastore::open(dir.path());
+
+        datastore.set("a.b.c", &2).unwrap();
+        datastore.set("new.x", &vec!["y"]).unwrap();
+        assert_eq!(datastore.get::<u64>("a.b.c").unwrap(), 2);
+        assert_eq!(datastore.get::<String>("new.x.0").unwrap(), "y");
+
+        datastore.remove("a.b").unwrap();
+        assert!(!datastore.exists("a.b").unwrap());
+        assert!(datastore.get::<bool>("a.keep").unwrap());
+        datastore.remove("new").unwrap();
+        assert!(!dir.path().join("new.yaml").exists());
+        let result = datastore.remove("new").unwrap_err();
+        assert!(matches!(result, Error::KeyNotFound));
+    }
 }
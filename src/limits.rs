@@ -0,0 +1,555 @@
+//! Limits on the data files a datastore will parse.
+//!
+//! Reading a data file means holding all of it in memory, and parsing it can take far more: a few
+//! lines of nested aliases can expand into billions of nodes. When data files may come from
+//! untrusted sources, [`Limits`] bound the work done for each file, and a file exceeding them is
+//! refused with [`Error::LimitExceeded`] before it's parsed. See
+//! [`Builder::limits`](crate::builder::Builder::limits).
+//!
+//! # Examples
+//!
+//! ```
+//! use yaml_datastore::{Datastore, Error, limits::Limits};
+//!
+//! let limits = Limits::default().max_file_size(1 << 20).max_alias_expansion(10_000);
+//! let datastore = Datastore::builder("tests/data").limits(limits).open();
+//! assert_eq!(datastore.get::<String>("complete.name").unwrap(), "Complete");
+//! ```
+use crate::{Datastore, Error};
+use serde::{
+    Deserializer,
+    de::{
+        self, DeserializeSeed, EnumAccess, IgnoredAny, MapAccess, SeqAccess, VariantAccess, Visitor,
+    },
+};
+use std::{fmt::Display, io::Read, path::Path};
+
+/// A single limit, as reported by [`Error::LimitExceeded`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Limit {
+    /// The maximum size of a file, in bytes.
+    FileSize(u64),
+    /// The maximum nesting depth of mappings and sequences, counting those reached through
+    /// aliases.
+    Depth(usize),
+    /// The maximum number of nodes that aliases in a file may expand to, in total.
+    AliasExpansion(u64),
+    /// The maximum number of nodes in a file, counting those reached through aliases.
+    Nodes(u64),
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FileSize(max) => write!(f, "maximum file size of {max} bytes"),
+            Self::Depth(max) => write!(f, "maximum nesting depth of {max}"),
+            Self::AliasExpansion(max) => write!(f, "maximum alias expansion of {max} nodes"),
+            Self::Nodes(max) => write!(f, "maximum of {max} nodes"),
+        }
+    }
+}
+
+/// Limits on each data file a datastore parses. Every limit is off by default.
+///
+/// Nodes are scalars, including the keys of mappings, mappings and sequences. Depth counts nested
+/// mappings and sequences, so a file containing `a: {b: 1}` has a depth of 2. Both are measured
+/// as if every alias were replaced by a copy of what it refers to. Measuring stops as soon as a
+/// limit is exceeded, so a hostile file is refused after visiting no more nodes than the limits
+/// allow, however far its aliases would expand.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// The maximum size of a file, in bytes.
+    file_size: Option<u64>,
+    /// The maximum nesting depth.
+    depth: Option<usize>,
+    /// The maximum number of nodes that aliases may expand to.
+    alias_expansion: Option<u64>,
+    /// The maximum number of nodes.
+    nodes: Option<u64>,
+}
+
+impl Limits {
+    /// Refuse files larger than `bytes`.
+    ///
+    /// Files are read no further than this, so memory use is bounded even for a file that is
+    /// still growing.
+    #[must_use]
+    pub fn max_file_size(mut self, bytes: u64) -> Self {
+        self.file_size = Some(bytes);
+        self
+    }
+
+    /// Refuse files whose mappings and sequences nest more than `depth` deep.
+    #[must_use]
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.depth = Some(depth);
+        self
+    }
+
+    /// Refuse files whose aliases add more than `nodes` nodes in total.
+    ///
+    /// This is the number of nodes in the file with every alias expanded, less the number with
+    /// every alias counted as a single node. An alias to a scalar adds nothing, and an alias to a
+    /// mapping or sequence adds every node within it, including through other aliases, less one.
+    #[must_use]
+    pub fn max_alias_expansion(mut self, nodes: u64) -> Self {
+        self.alias_expansion = Some(nodes);
+        self
+    }
+
+    /// Refuse files with more than `nodes` nodes in total.
+    #[must_use]
+    pub fn max_nodes(mut self, nodes: u64) -> Self {
+        self.nodes = Some(nodes);
+        self
+    }
+
+    /// Check the structure of `contents` against the limits, returning the first one exceeded.
+    ///
+    /// Contents that can't be parsed pass, so the parser reports the problem as usual.
+    fn check(&self, contents: &str) -> Result<(), Limit> {
+        if self.depth.is_none() && self.alias_expansion.is_none() && self.nodes.is_none() {
+            return Ok(());
+        }
+        let written = match self.alias_expansion {
+            // Without aliases, the document is measured in time proportional to its size. If it
+            // can't be parsed that way, everything counts as added by aliases.
+            Some(_) => {
+                Counter::measure(&without_aliases(contents), &Limits::default()).unwrap_or(0)
+            }
+            None => 0,
+        };
+        let mut counter = Counter {
+            limits: self,
+            written,
+            nodes: 0,
+            exceeded: None,
+        };
+        // Any other error is a parse error, which is left to the parser.
+        let _ = Measure {
+            counter: &mut counter,
+            depth: 0,
+        }
+        .deserialize(serde_yaml::Deserializer::from_str(contents));
+        counter.exceeded.map_or(Ok(()), Err)
+    }
+}
+
+/// Replace every alias in `contents` with a plain scalar, so the document has the same structure
+/// with each alias as a single node.
+///
+/// Alias names are ASCII letters, digits, `-` and `_`, as in libyaml, which `serde_yaml` parses
+/// with. Any other `*` followed by such a name is inside a scalar, comment or tag, where replacing
+/// it with a letter changes the text but not the structure.
+fn without_aliases(contents: &str) -> String {
+    let mut replaced = contents.as_bytes().to_vec();
+    for i in 0..replaced.len().saturating_sub(1) {
+        let next = replaced[i + 1];
+        if replaced[i] == b'*' && (next.is_ascii_alphanumeric() || next == b'-' || next == b'_') {
+            replaced[i] = b'x';
+        }
+    }
+    // Only an ASCII byte was replaced with another, so the contents are still UTF-8.
+    String::from_utf8(replaced).unwrap_or_default()
+}
+
+/// Running totals for a document, checked against limits as its nodes are visited.
+struct Counter<'a> {
+    /// The limits to check.
+    limits: &'a Limits,
+    /// The number of nodes in the document with each alias counted as a single node.
+    written: u64,
+    /// The number of nodes so far.
+    nodes: u64,
+    /// The limit that stopped measuring, if one did.
+    exceeded: Option<Limit>,
+}
+
+impl Counter<'_> {
+    /// Count the nodes in `contents`, returning `None` if it can't be parsed or exceeds `limits`.
+    fn measure(contents: &str, limits: &Limits) -> Option<u64> {
+        let mut counter = Counter {
+            limits,
+            written: 0,
+            nodes: 0,
+            exceeded: None,
+        };
+        Measure {
+            counter: &mut counter,
+            depth: 0,
+        }
+        .deserialize(serde_yaml::Deserializer::from_str(contents))
+        .ok()?;
+        Some(counter.nodes)
+    }
+
+    /// Count a node nested `depth` deep, stopping the parser with an error if it exceeds a limit.
+    fn add<E: de::Error>(&mut self, depth: usize) -> Result<(), E> {
+        self.nodes = self.nodes.saturating_add(1);
+        let limits = self.limits;
+        let exceeded = if let Some(max) = limits.nodes
+            && self.nodes > max
+        {
+            Limit::Nodes(max)
+        } else if let Some(max) = limits.depth
+            && depth > max
+        {
+            Limit::Depth(max)
+        } else if let Some(max) = limits.alias_expansion
+            && self.nodes.saturating_sub(self.written) > max
+        {
+            Limit::AliasExpansion(max)
+        } else {
+            return Ok(());
+        };
+        self.exceeded = Some(exceeded);
+        Err(E::custom(format!("exceeds the {exceeded}")))
+    }
+}
+
+/// Visits a node and everything within it, counting them with a [`Counter`].
+///
+/// `serde_yaml` replays the node an alias refers to wherever the alias appears, so aliases are
+/// counted as if expanded. Nothing is kept, and the first limit exceeded stops the parser.
+struct Measure<'c, 'l> {
+    /// The totals so far.
+    counter: &'c mut Counter<'l>,
+    /// The number of mappings and sequences containing the node.
+    depth: usize,
+}
+
+impl<'l> Measure<'_, 'l> {
+    /// Measure a child of the mapping or sequence this node is.
+    fn child(&mut self) -> Measure<'_, 'l> {
+        Measure {
+            counter: &mut *self.counter,
+            depth: self.depth + 1,
+        }
+    }
+
+    /// Count this node as a scalar.
+    fn scalar<E: de::Error>(self) -> Result<(), E> {
+        self.counter.add(self.depth)
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for Measure<'_, '_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Measure<'_, '_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("any YAML value")
+    }
+
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<(), E> {
+        self.scalar()
+    }
+
+    fn visit_i64<E: de::Error>(self, _: i64) -> Result<(), E> {
+        self.scalar()
+    }
+
+    fn visit_i128<E: de::Error>(self, _: i128) -> Result<(), E> {
+        self.scalar()
+    }
+
+    fn visit_u64<E: de::Error>(self, _: u64) -> Result<(), E> {
+        self.scalar()
+    }
+
+    fn visit_u128<E: de::Error>(self, _: u128) -> Result<(), E> {
+        self.scalar()
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<(), E> {
+        self.scalar()
+    }
+
+    fn visit_str<E: de::Error>(self, _: &str) -> Result<(), E> {
+        self.scalar()
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<(), E> {
+        self.scalar()
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<(), E> {
+        self.scalar()
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        self.deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<(), A::Error> {
+        self.counter.add(self.depth + 1)?;
+        while seq.next_element_seed(self.child())?.is_some() {}
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(mut self, mut map: A) -> Result<(), A::Error> {
+        self.counter.add(self.depth + 1)?;
+        while map.next_key_seed(self.child())?.is_some() {
+            map.next_value_seed(self.child())?;
+        }
+        Ok(())
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<(), A::Error> {
+        // A tagged value, which is a single node: the tag is the variant.
+        let (IgnoredAny, variant) = data.variant()?;
+        variant.newtype_variant_seed(self)
+    }
+}
+
+impl Datastore {
+    /// Read the data file at `path`, relative to the root, checking it against the datastore's
+    /// [limits](Limits).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::LimitExceeded`] if the file exceeds a limit, and the errors from
+    /// [`confine()`](Self::confine) or reading the file otherwise.
+    pub(crate) fn read_data_file(&self, path: &Path) -> Result<String, Error> {
+        let contents = self.read_sized_file(path)?;
+        self.settings
+            .limits
+            .check(&contents)
+            .map_err(|limit| Error::LimitExceeded(path.to_owned(), limit))?;
+        Ok(contents)
+    }
+
+    /// Read the data file at `path`, relative to the root, checking only its size against the
+    /// datastore's [limits](Limits).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::LimitExceeded`] if the file is too large, and the errors from
+    /// [`confine()`](Self::confine) or reading the file otherwise.
+    pub(crate) fn read_sized_file(&self, path: &Path) -> Result<String, Error> {
+        let bytes = self.read_bounded(&self.confine(path)?)?;
+        if let Some(max) = self.settings.limits.file_size
+            && bytes.len() as u64 > max
+        {
+            return Err(Error::LimitExceeded(path.to_owned(), Limit::FileSize(max)));
+        }
+        Ok(String::from_utf8(bytes)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?)
+    }
+
+    /// Read the file at `full_path`, stopping one byte past the maximum file size.
+    ///
+    /// A file too large for the limit is cut short, but is still longer than the limit, so
+    /// [`within_limits()`](Self::within_limits) refuses it. Memory use is bounded even for a file
+    /// that is still growing.
+    pub(crate) fn read_bounded(&self, full_path: &Path) -> std::io::Result<Vec<u8>> {
+        let file = std::fs::File::open(full_path)?;
+        let mut bytes = Vec::new();
+        match self.settings.limits.file_size {
+            Some(max) => file.take(max.saturating_add(1)).read_to_end(&mut bytes)?,
+            None => (&file).read_to_end(&mut bytes)?,
+        };
+        Ok(bytes)
+    }
+
+    /// Return whether `contents`, the contents of a data file, are within the datastore's
+    /// [limits](Limits), including the limit on file size.
+    pub(crate) fn within_limits(&self, contents: &[u8]) -> bool {
+        let limits = &self.settings.limits;
+        limits
+            .file_size
+            .is_none_or(|max| contents.len() as u64 <= max)
+            && std::str::from_utf8(contents).is_ok_and(|s| limits.check(s).is_ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builder::IGNORE_PATH, cache, manifest::MANIFEST_PATH};
+    use std::fmt::Write;
+
+    #[test]
+    fn structure_limits() {
+        let nested = "a: {b: [1, {c: 2}]}";
+        assert_eq!(Limits::default().max_depth(4).check(nested), Ok(()));
+        assert_eq!(
+            Limits::default().max_depth(3).check(nested),
+            Err(Limit::Depth(3))
+        );
+        assert_eq!(Limits::default().max_nodes(9).check(nested), Ok(()));
+        assert_eq!(
+            Limits::default().max_nodes(8).check(nested),
+            Err(Limit::Nodes(8))
+        );
+
+        let aliases = "a: &a [1, 2]\nb: &b [*a, *a]\nc: [*b, *b]\n";
+        // Each alias to `a` adds 2 nodes to the 3 of `a` itself, and each alias to `b` adds 6,
+        // since `b` expands to 7.
+        assert_eq!(
+            Limits::default().max_alias_expansion(16).check(aliases),
+            Ok(())
+        );
+        assert_eq!(
+            Limits::default().max_alias_expansion(15).check(aliases),
+            Err(Limit::AliasExpansion(15))
+        );
+        // A `*` that isn't an alias doesn't change the structure when replaced.
+        let stars = "a: &a [1, 2]\nb: \"*a\" # *a\nc: 2*a\nd: |\n  *a\ne: !x*a [*a]\n";
+        assert_eq!(
+            Counter::measure(&without_aliases(stars), &Limits::default()),
+            Some(14)
+        );
+        assert_eq!(
+            Limits::default().max_alias_expansion(2).check(stars),
+            Ok(())
+        );
+        assert_eq!(
+            Limits::default().max_alias_expansion(1).check(stars),
+            Err(Limit::AliasExpansion(1))
+        );
+        assert_eq!(
+            Limits::default().max_depth(3).check(aliases),
+            Err(Limit::Depth(3))
+        );
+
+        // An alias bomb is refused once it exceeds the limit, long before it's fully expanded.
+        let mut bomb = String::from("a0: &a0 [x, x, x, x, x, x, x, x, x, x]\n");
+        for i in 1..40 {
+            let p = i - 1;
+            writeln!(bomb, "a{i}: &a{i} [*a{p}, *a{p}, *a{p}, *a{p}, *a{p}]").unwrap();
+        }
+        assert_eq!(
+            Limits::default().max_nodes(100_000).check(&bomb),
+            Err(Limit::Nodes(100_000))
+        );
+        assert_eq!(
+            Limits::default().max_alias_expansion(100_000).check(&bomb),
+            Err(Limit::AliasExpansion(100_000))
+        );
+        // Unparseable contents are left to the parser.
+        assert_eq!(
+            Limits::default().max_nodes(10).check("a: [unclosed"),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn limited_datastore() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("small.yaml"), "x: 1").unwrap();
+        std::fs::write(dir.path().join("large.yaml"), "x: 1\ny: 2\nz: 3\nw: 4\n").unwrap();
+        std::fs::write(dir.path().join("deep.yaml"), "x: {y: {z: 1}}").unwrap();
+        let limits = Limits::default().max_file_size(16).max_depth(2);
+        let datastore = Datastore::builder(dir.path()).limits(limits).open();
+
+        assert_eq!(datastore.get::<i64>("small.x").unwrap(), 1);
+        let error = datastore.get::<i64>("large.x").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "large.yaml exceeds the maximum file size of 16 bytes"
+        );
+        assert!(matches!(
+            datastore.get_with_path::<_, serde_yaml::Value>("deep.yaml"),
+            Err(Error::LimitExceeded(path, Limit::Depth(2))) if path == Path::new("deep.yaml")
+        ));
+        let errors: Vec<_> = datastore.walk().filter_map(Result::err).collect();
+        assert_eq!(errors.len(), 2);
+
+        // An over-limit candidate isn't skipped in favour of a later one, but lint reports it.
+        std::fs::create_dir(dir.path().join("small")).unwrap();
+        std::fs::write(dir.path().join("small/x.yaml"), "0123456789abcdefg").unwrap();
+        assert!(matches!(
+            datastore.get::<i64>("small.x"),
+            Err(Error::LimitExceeded(path, Limit::FileSize(16))) if path == Path::new("small/x.yaml")
+        ));
+        let issues = datastore.lint().unwrap();
+        assert!(
+            issues
+                .iter()
+                .any(|issue| issue.path == Path::new("small/x.yaml")
+                    && issue.message.contains("maximum file size"))
+        );
+    }
+
+    #[test]
+    fn bounded_reads() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.yaml"), "x: 1").unwrap();
+        let limits = Limits::default().max_file_size(16);
+        let datastore = Datastore::builder(dir.path()).limits(limits).open();
+        datastore.create_index("x", "*", "x").unwrap();
+        assert_eq!(datastore.lookup("x", 1).unwrap().len(), 1);
+
+        std::fs::write(
+            dir.path().join("a.yaml"),
+            format!("x: 1\n#{}", "-".repeat(1000)),
+        )
+        .unwrap();
+        let bytes = datastore.read_bounded(&dir.path().join("a.yaml")).unwrap();
+        assert_eq!(bytes.len(), 17);
+        datastore.rebuild_indexes().unwrap();
+        assert!(datastore.lookup("x", 1).unwrap().is_empty());
+        assert!(matches!(
+            datastore.set("a.y", &2),
+            Err(Error::LimitExceeded(_, Limit::FileSize(16)))
+        ));
+    }
+
+    #[test]
+    fn limited_configuration() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut bomb = String::from("ignore: &a0 [x, x, x, x, x, x, x, x, x, x]\n");
+        for i in 1..40 {
+            let p = i - 1;
+            writeln!(bomb, "a{i}: &a{i} [*a{p}, *a{p}, *a{p}, *a{p}, *a{p}]").unwrap();
+        }
+        std::fs::write(dir.path().join(MANIFEST_PATH), bomb).unwrap();
+        std::fs::write(dir.path().join(IGNORE_PATH), "#".repeat(5000)).unwrap();
+        std::fs::write(
+            dir.path().join("schema.yaml"),
+            "type: [object, object, object]",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("a.yaml"), "x: 1").unwrap();
+        let limits = Limits::default()
+            .max_file_size(4096)
+            .max_alias_expansion(1000);
+
+        let datastore = Datastore::builder(dir.path()).limits(limits).open();
+        assert!(matches!(
+            datastore.open_errors(),
+            [
+                Error::LimitExceeded(manifest, Limit::AliasExpansion(1000)),
+                Error::LimitExceeded(ignore, Limit::FileSize(4096)),
+            ] if manifest == Path::new(MANIFEST_PATH) && ignore == Path::new(IGNORE_PATH)
+        ));
+        assert!(matches!(
+            Datastore::builder(dir.path()).limits(limits).try_open(),
+            Err(Error::LimitExceeded(_, Limit::AliasExpansion(1000)))
+        ));
+
+        let limits = Limits::default().max_nodes(4);
+        let datastore = Datastore::builder(dir.path()).limits(limits).open();
+        assert!(matches!(
+            datastore.add_schema_file("*", "schema.yaml"),
+            Err(Error::LimitExceeded(_, Limit::Nodes(4)))
+        ));
+
+        // A cache beyond the limits is ignored, and the indexes rebuilt.
+        let datastore = Datastore::open(dir.path());
+        datastore.create_index("by_x", "*", "x").unwrap();
+        datastore.save_index_cache().unwrap();
+        let datastore = Datastore::builder(dir.path()).limits(limits).open();
+        assert!(cache::load(&datastore).is_none());
+        datastore.create_index("by_x", "*", "x").unwrap();
+        assert_eq!(datastore.lookup("by_x", 1).unwrap().len(), 1);
+    }
+}
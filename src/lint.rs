@@ -0,0 +1,581 @@
+//! Checking a datastore for problems that make it fragile or ambiguous.
+//!
+//! None of these problems stop the datastore from working, but each one means some data can't be
+//! read, or isn't read from where it appears to be. See [`Datastore::lint`] for details.
+use crate::{
+    Datastore, Error, ValueKind,
+    cache::SIDECAR_DIR,
+    keypath::{KeyPath, is_valid_component},
+    manifest::Resolution,
+};
+use serde::{
+    Deserialize, Deserializer, Serialize,
+    de::{DeserializeSeed, EnumAccess, MapAccess, SeqAccess, VariantAccess, Visitor},
+};
+use serde_yaml::Value;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// The kind of problem an [`Issue`] describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// A data file can't be parsed as YAML, so none of its keypaths resolve.
+    ParseError,
+    /// A mapping in a data file has the same key more than once, so the file can't be parsed.
+    DuplicateKey,
+    /// A data file contains no data.
+    EmptyFile,
+    /// A data file's root is not a mapping, so no keys beneath it can be resolved.
+    NotAMapping,
+    /// A key in a data file is also the name of a data file beside it, which takes precedence when
+    /// resolving keypaths beneath it. If the [manifest](crate::manifest::Resolution) puts
+    /// shallower files first, the key takes precedence instead, and the file is reported.
+    ShadowedKeyPath,
+    /// A file or directory name isn't a valid keypath component, so it can't be addressed.
+    InvalidName,
+    /// Data files with the same stem but different extensions exist, and all but the one whose
+    /// extension comes first in the [configured list](crate::manifest::Manifest::extensions) are
+    /// shadowed.
+    ExtensionTwins,
+    /// A file or directory resolves through a symlink to somewhere outside a
+    /// [sandboxed](crate::builder::Builder::sandbox) root, so it can't be read.
+    OutsideRoot,
+}
+
+/// A single problem found by [`Datastore::lint`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Issue {
+    /// The kind of problem.
+    pub kind: IssueKind,
+    /// The file or directory with the problem, relative to the root.
+    pub path: PathBuf,
+    /// The keypath affected, for problems within a file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keypath: Option<KeyPath>,
+    /// A human-readable description of the problem.
+    pub message: String,
+}
+
+impl Issue {
+    /// Create an issue about `path` that doesn't concern a particular keypath.
+    fn new(kind: IssueKind, path: &Path, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            path: path.to_owned(),
+            keypath: None,
+            message: message.into(),
+        }
+    }
+}
+
+/// Finds the first mapping key that appears twice in a document, without keeping the document.
+///
+/// The parser rejects such a document outright, so this walks it again with a visitor that
+/// accepts every mapping and tracks the keys itself. The value is the keys leading to the
+/// duplicate, ending with the duplicate key.
+struct FindDuplicateKey;
+
+/// Return a mapping key as it would appear in a keypath.
+fn key_name(key: &Value) -> String {
+    match key {
+        Value::String(key) => key.clone(),
+        key => serde_yaml::to_string(key)
+            .map(|key| key.trim_end().to_owned())
+            .unwrap_or_default(),
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for FindDuplicateKey {
+    type Value = Option<Vec<String>>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for FindDuplicateKey {
+    type Value = Option<Vec<String>>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any YAML value")
+    }
+
+    fn visit_bool<E>(self, _: bool) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_i64<E>(self, _: i64) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_i128<E>(self, _: i128) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_u64<E>(self, _: u64) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_u128<E>(self, _: u128) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_f64<E>(self, _: f64) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_str<E>(self, _: &str) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_none<E>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        self.deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut found = None;
+        let mut index = 0_usize;
+        while let Some(duplicate) = seq.next_element_seed(FindDuplicateKey)? {
+            if found.is_none()
+                && let Some(mut keys) = duplicate
+            {
+                keys.insert(0, index.to_string());
+                found = Some(keys);
+            }
+            index += 1;
+        }
+        Ok(found)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        // Every entry must be consumed, even after a duplicate is found.
+        let mut found = None;
+        let mut keys = HashSet::new();
+        while let Some(key) = map.next_key::<Value>()? {
+            let duplicate = map.next_value_seed(FindDuplicateKey)?;
+            if found.is_some() {
+                continue;
+            }
+            if keys.contains(&key) {
+                found = Some(vec![key_name(&key)]);
+            } else if let Some(mut path) = duplicate {
+                path.insert(0, key_name(&key));
+                found = Some(path);
+            } else {
+                keys.insert(key);
+            }
+        }
+        Ok(found)
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        // A tagged value: the tag is the variant, and the value beneath it is checked.
+        let (_, variant) = data.variant::<Value>()?;
+        variant.newtype_variant_seed(FindDuplicateKey)
+    }
+}
+
+/// Return the keys leading to the first duplicate mapping key in `contents`, if it has one.
+fn find_duplicate_key(contents: &str) -> Option<Vec<String>> {
+    FindDuplicateKey
+        .deserialize(serde_yaml::Deserializer::from_str(contents))
+        .ok()
+        .flatten()
+}
+
+impl Datastore {
+    /// Check the data file at `path` and add any problems to `issues`.
+    fn lint_file(&self, path: &Path, issues: &mut Vec<Issue>) -> Result<(), Error> {
+        let contents = match self.read_data_file(path) {
+            Err(e @ Error::LimitExceeded(..)) => {
+                issues.push(Issue::new(IssueKind::ParseError, path, e.to_string()));
+                return Ok(());
+            }
+            Err(e @ Error::OutsideRoot(_)) => {
+                issues.push(Issue::new(IssueKind::OutsideRoot, path, e.to_string()));
+                return Ok(());
+            }
+            Err(Error::IOError(e)) if e.kind() == std::io::ErrorKind::InvalidData => {
+                let message = format!("file is not valid UTF-8: {e}");
+                issues.push(Issue::new(IssueKind::ParseError, path, message));
+                return Ok(());
+            }
+            contents => contents?,
+        };
+        let mapping = match serde_yaml::from_str::<Value>(&contents) {
+            Err(e) => {
+                if let Some(keys) = find_duplicate_key(&contents) {
+                    let keypath = KeyPath::from_path(path).ok().and_then(|keypath| {
+                        keys.iter().try_fold(keypath, |k, key| k.join(key).ok())
+                    });
+                    issues.push(Issue {
+                        kind: IssueKind::DuplicateKey,
+                        path: path.to_owned(),
+                        keypath,
+                        message: format!("duplicate key {:?}: {e}", keys.join(".")),
+                    });
+                    return Ok(());
+                }
+                issues.push(Issue::new(IssueKind::ParseError, path, e.to_string()));
+                return Ok(());
+            }
+            Ok(Value::Null) => {
+                issues.push(Issue::new(
+                    IssueKind::EmptyFile,
+                    path,
+                    "file contains no data",
+                ));
+                return Ok(());
+            }
+            Ok(Value::Mapping(mapping)) => mapping,
+            Ok(value) => {
+                let kind = ValueKind::from(&value);
+                let message = format!("expected a mapping at the root, found {kind:?}");
+                issues.push(Issue::new(IssueKind::NotAMapping, path, message));
+                return Ok(());
+            }
+        };
+
+        if let Ok(keypath) = KeyPath::from_path(path) {
+            self.lint_shadowed(path, &keypath, &path.with_extension(""), &mapping, issues);
+        }
+        Ok(())
+    }
+
+    /// Add an issue for each key in `mapping`, found at `keypath` in the data file at `path`, that
+    /// is shadowed by a data file in `directory`.
+    ///
+    /// Keys beneath a shadowed key aren't checked, since the whole of it is already reported.
+    /// A directory doesn't shadow a key itself, only the keys beneath it that its files name.
+    fn lint_shadowed(
+        &self,
+        path: &Path,
+        keypath: &KeyPath,
+        directory: &Path,
+        mapping: &serde_yaml::Mapping,
+        issues: &mut Vec<Issue>,
+    ) {
+        for (key, value) in mapping {
+            let Some(key) = key.as_str() else {
+                continue;
+            };
+            let Ok(child) = keypath.join(key) else {
+                continue;
+            };
+            let shadow = directory.join(key);
+            let shadowed_by = self
+                .extensions()
+                .iter()
+                .map(|e| shadow.with_extension(e))
+                .find(|p| self.is_file(p));
+            if let Some(file) = shadowed_by {
+                let (path, message) = match self.settings.resolution {
+                    Resolution::DeepestFirst => (
+                        path.to_owned(),
+                        format!("{child} is shadowed by {}", file.display()),
+                    ),
+                    Resolution::ShallowestFirst => {
+                        let message = format!(
+                            "{} is shadowed by {child} in {}",
+                            file.display(),
+                            path.display()
+                        );
+                        (file, message)
+                    }
+                };
+                issues.push(Issue {
+                    kind: IssueKind::ShadowedKeyPath,
+                    path,
+                    message,
+                    keypath: Some(child),
+                });
+            } else if let Value::Mapping(mapping) = value {
+                self.lint_shadowed(path, &child, &shadow, mapping, issues);
+            }
+        }
+    }
+
+    /// Check the directory at `dir`, recursively, and add any problems to `issues`.
+    fn lint_directory(&self, dir: &Path, issues: &mut Vec<Issue>) -> Result<(), Error> {
+        let mut entries = match self.read_directory(dir) {
+            Err(e @ Error::OutsideRoot(_)) => {
+                issues.push(Issue::new(IssueKind::OutsideRoot, dir, e.to_string()));
+                return Ok(());
+            }
+            entries => entries?,
+        };
+        entries.sort_by_key(std::fs::DirEntry::file_name);
+        let mut extensions: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+        for entry in entries {
+            let path = dir.join(entry.file_name());
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if path == Path::new(SIDECAR_DIR) {
+                continue;
+            }
+            let is_dir = entry.file_type()?.is_dir();
+            if self.is_ignored(&path, is_dir) {
+                continue;
+            }
+            if is_dir {
+                if is_valid_component(&name) {
+                    self.lint_directory(&path, issues)?;
+                } else {
+                    let message =
+                        format!("directory name {name:?} is not a valid keypath component");
+                    issues.push(Issue::new(IssueKind::InvalidName, &path, message));
+                }
+                continue;
+            }
+            if !self.has_data_extension(&path) {
+                continue;
+            }
+            let stem = path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            if !is_valid_component(&stem) {
+                let message = format!("file stem {stem:?} is not a valid keypath component");
+                issues.push(Issue::new(IssueKind::InvalidName, &path, message));
+                continue;
+            }
+            extensions.entry(stem).or_default().push(path.clone());
+            self.lint_file(&path, issues)?;
+        }
+
+        for paths in extensions.values().filter(|paths| paths.len() > 1) {
+            // Extensions earlier in the list take precedence.
+            let mut paths = paths.clone();
+            paths.sort_by_key(|p| {
+                self.extensions()
+                    .iter()
+                    .position(|e| p.extension().is_some_and(|x| x == e.as_str()))
+            });
+            let extension = |path: &Path| {
+                path.extension()
+                    .map(|e| e.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            };
+            for shadowed in &paths[1..] {
+                let message = format!(
+                    "shadowed by {}, since .{} comes before .{} in the extension list",
+                    paths[0].display(),
+                    extension(&paths[0]),
+                    extension(shadowed)
+                );
+                issues.push(Issue::new(IssueKind::ExtensionTwins, shadowed, message));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check every file and directory in the datastore for problems.
+    ///
+    /// This reports:
+    ///
+    /// * Data files that can't be parsed, with [duplicate keys](IssueKind::DuplicateKey) reported
+    ///   separately from [other parse errors](IssueKind::ParseError).
+    /// * [Empty files](IssueKind::EmptyFile), and files whose [root is not a
+    ///   mapping](IssueKind::NotAMapping).
+    /// * Keys in a file that are [shadowed](IssueKind::ShadowedKeyPath) by a data file of the
+    ///   same name beside it, such as the key `b` in `a.yaml` when `a/b.yaml` exists, or the
+    ///   nested key `b.c` in `a.yaml` when `a/b/c.yaml` exists.
+    /// * File and directory names that [aren't valid keypath components](IssueKind::InvalidName).
+    /// * [Data files with the same stem and different extensions](IssueKind::ExtensionTwins).
+    /// * Files and directories that resolve [outside a sandboxed root](IssueKind::OutsideRoot).
+    ///
+    /// [Ignored](Datastore::builder) files and directories, which include hidden ones by default,
+    /// are skipped. Issues are returned sorted by path, and [`Issue`] can be serialized for
+    /// machine-readable output.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use yaml_datastore::{Datastore, lint::IssueKind};
+    ///
+    /// let datastore = Datastore::open("tests/data");
+    /// let kinds: Vec<IssueKind> = datastore.lint().unwrap().iter().map(|i| i.kind).collect();
+    /// assert_eq!(kinds, vec![IssueKind::DuplicateKey, IssueKind::EmptyFile]);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IOError`] if a file or directory can't be read.
+    pub fn lint(&self) -> Result<Vec<Issue>, Error> {
+        let mut issues = Vec::new();
+        self.lint_directory(Path::new(""), &mut issues)?;
+        issues.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(issues)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::MANIFEST_PATH;
+
+    fn write(dir: &Path, path: &str, contents: &str) {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn lint_problems() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.yaml", "b: 1\nc: 2");
+        write(dir.path(), "a/b.yaml", "x: 1");
+        write(dir.path(), "list.yaml", "[1, 2]");
+        write(dir.path(), "bad.yaml", "key: [unclosed");
+        write(dir.path(), "twin.yaml", "x: 1");
+        write(dir.path(), "twin.yml", "x: 2");
+        write(dir.path(), "not.valid/ok.yaml", "x: 1");
+        write(dir.path(), ".hidden.yaml", "");
+
+        let issues = Datastore::open(dir.path()).lint().unwrap();
+        let found: Vec<(IssueKind, &Path)> =
+            issues.iter().map(|i| (i.kind, i.path.as_path())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (IssueKind::ShadowedKeyPath, Path::new("a.yaml")),
+                (IssueKind::ParseError, Path::new("bad.yaml")),
+                (IssueKind::NotAMapping, Path::new("list.yaml")),
+                (IssueKind::InvalidName, Path::new("not.valid")),
+                (IssueKind::ExtensionTwins, Path::new("twin.yml")),
+            ]
+        );
+        assert_eq!(issues[0].keypath.as_ref().unwrap().to_string(), "a.b");
+    }
+
+    #[test]
+    fn lint_nested_shadowing() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.yaml", "b: {c: {d: 1}, e: 2}\nf: {g: 3}");
+        write(dir.path(), "a/b/c.yaml", "d: 4");
+
+        let issues = Datastore::open(dir.path()).lint().unwrap();
+        let keypaths: Vec<String> = issues
+            .iter()
+            .map(|i| i.keypath.as_ref().unwrap().to_string())
+            .collect();
+        assert_eq!(keypaths, ["a.b.c"]);
+        assert_eq!(issues[0].kind, IssueKind::ShadowedKeyPath);
+    }
+
+    #[test]
+    fn lint_invalid_utf8() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.yaml", "x: 1");
+        std::fs::write(dir.path().join("b.yaml"), b"x: \xff").unwrap();
+        write(dir.path(), "c.yaml", "");
+
+        let issues = Datastore::open(dir.path()).lint().unwrap();
+        let found: Vec<(IssueKind, &Path)> =
+            issues.iter().map(|i| (i.kind, i.path.as_path())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (IssueKind::ParseError, Path::new("b.yaml")),
+                (IssueKind::EmptyFile, Path::new("c.yaml")),
+            ]
+        );
+    }
+
+    #[test]
+    fn lint_duplicate_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "a.yaml",
+            "big: !!int 100000000000000000000\nb: {c: 1, c: 2}",
+        );
+        write(
+            dir.path(),
+            "d.yaml",
+            "e: [x, {f: 1, g: 2, f: 3}]\nh: 4\nh: 5",
+        );
+        write(dir.path(), "i.yaml", "j: !tag {k: 1, k: 2}");
+        // A parse error that mentions a duplicate isn't one.
+        write(dir.path(), "l.yaml", "m: \"duplicate entry\"\nn: [");
+
+        let issues = Datastore::open(dir.path()).lint().unwrap();
+        let found: Vec<(IssueKind, Option<String>)> = issues
+            .iter()
+            .map(|i| (i.kind, i.keypath.as_ref().map(ToString::to_string)))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (IssueKind::DuplicateKey, Some("a.b.c".to_owned())),
+                (IssueKind::DuplicateKey, Some("d.e.1.f".to_owned())),
+                (IssueKind::DuplicateKey, Some("i.j.k".to_owned())),
+                (IssueKind::ParseError, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn lint_extension_order() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "twin.yaml", "x: 1");
+        write(dir.path(), "twin.yml", "x: 2");
+        write(dir.path(), MANIFEST_PATH, "extensions: [yml, yaml]");
+
+        let issues = Datastore::open(dir.path()).lint().unwrap();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].kind, IssueKind::ExtensionTwins);
+        assert_eq!(issues[0].path, Path::new("twin.yaml"));
+        assert_eq!(
+            issues[0].message,
+            "shadowed by twin.yml, since .yml comes before .yaml in the extension list"
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn lint_outside_root() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "outside.yaml", "secret: 1");
+        write(dir.path(), "root/a.yaml", "");
+        let root = dir.path().join("root");
+        std::os::unix::fs::symlink(dir.path().join("outside.yaml"), root.join("link.yaml"))
+            .unwrap();
+
+        let issues = Datastore::builder(&root)
+            .sandbox(true)
+            .open()
+            .lint()
+            .unwrap();
+        let found: Vec<(IssueKind, &Path)> =
+            issues.iter().map(|i| (i.kind, i.path.as_path())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (IssueKind::EmptyFile, Path::new("a.yaml")),
+                (IssueKind::OutsideRoot, Path::new("link.yaml")),
+            ]
+        );
+    }
+
+    #[test]
+    fn serialize_issue() {
+        let issue = Issue::new(IssueKind::EmptyFile, Path::new("empty.yaml"), "empty");
+        let json = serde_json::to_string(&issue).unwrap();
+        assert_eq!(
+            json,
+            r#"{"kind":"empty_file","path":"empty.yaml","message":"empty"}"#
+        );
+    }
+}
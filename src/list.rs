@@ -0,0 +1,246 @@
+//! Enumerating the children of a keypath.
+//!
+//! Since a keypath may be resolved through directories, files or mapping keys, the children of a
+//! keypath can come from any of them. See [`Datastore::list`] for details.
+use crate::{Datastore, Error, cache::SIDECAR_DIR, keypath::KeyPath, keypath::is_valid_component};
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+/// Where a [`Child`] of a keypath was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum ChildKind {
+    /// A data file, named by its stem.
+    File,
+    /// A directory that contains no data file of the same name.
+    Directory,
+    /// A key of a YAML mapping.
+    Key,
+    /// A position in a YAML sequence, starting from `0`.
+    Index,
+}
+
+/// A single child of a keypath, as returned by [`Datastore::list`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Child {
+    /// Name of the child, which is a valid keypath component.
+    pub name: String,
+    /// Where the child was found.
+    pub kind: ChildKind,
+}
+
+impl Datastore {
+    /// Collect the files and directories in `dir`, relative to the root, into `children`.
+    ///
+    /// Returns whether `dir` exists.
+    fn list_directory(
+        &self,
+        dir: &Path,
+        children: &mut BTreeMap<String, ChildKind>,
+    ) -> Result<bool, Error> {
+        if self.is_ignored(dir, true) {
+            return Ok(false);
+        }
+        let entries = match self.read_directory(dir) {
+            Ok(entries) => entries,
+            Err(Error::IOError(e))
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::NotFound | std::io::ErrorKind::NotADirectory
+                ) =>
+            {
+                return Ok(false);
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut directories = Vec::new();
+        for entry in entries {
+            let path = PathBuf::from(entry.file_name());
+            if dir.as_os_str().is_empty() && path == Path::new(SIDECAR_DIR) {
+                continue;
+            }
+            let is_dir = entry.file_type()?.is_dir();
+            if self.is_ignored(&dir.join(&path), is_dir) {
+                continue;
+            }
+            if is_dir {
+                if let Some(name) = path.to_str()
+                    && is_valid_component(name)
+                {
+                    directories.push(name.to_owned());
+                }
+            } else if self.has_data_extension(&path)
+                && let Some(stem) = path.file_stem().and_then(|s| s.to_str())
+                && is_valid_component(stem)
+            {
+                children.insert(stem.to_owned(), ChildKind::File);
+            }
+        }
+        for directory in directories {
+            children.entry(directory).or_insert(ChildKind::Directory);
+        }
+        Ok(true)
+    }
+
+    /// List the immediate children of a keypath.
+    ///
+    /// Children are gathered from every place [`get()`](Datastore::get) would look when resolving a
+    /// keypath one component longer, which is the same set of candidates produced by
+    /// [`KeyPath::iter`]. For a keypath of `a.b`, that means:
+    ///
+    /// 1. Data files and subdirectories of the directory `a/b/`.
+    /// 2. The keys of the mapping in `a/b.yaml`.
+    /// 3. The keys of the mapping under the key `b` in `a.yaml`.
+    ///
+    /// If a value found this way is a sequence rather than a mapping, its indices are children.
+    ///
+    /// A name found in more than one place is reported once, with the kind from the earliest place
+    /// it was found in the list above. Files are preferred over directories of the same name, since
+    /// that is what the keypath resolves to. Names that aren't valid keypath components are
+    /// skipped, since they can't be addressed. Children are returned sorted by name, except that
+    /// numeric names come first and are sorted by value.
+    ///
+    /// Passing an empty keypath lists the root of the datastore.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use yaml_datastore::{Datastore, list::ChildKind};
+    ///
+    /// let datastore = Datastore::open("tests/data");
+    /// let children = datastore.list("complete.nested").unwrap();
+    /// assert_eq!(children[0].name, "value");
+    /// assert_eq!(children[0].kind, ChildKind::Key);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::KeyPathError`] if `keypath` is invalid.
+    ///
+    /// Returns [`Error::KeyNotFound`] if nothing exists at `keypath`.
+    ///
+    /// Returns [`Error::IOError`] if an existing directory cannot be read.
+    pub fn list(&self, keypath: &str) -> Result<Vec<Child>, Error> {
+        let mut children = BTreeMap::new();
+        let mut found;
+        if keypath.trim().is_empty() {
+            found = self.list_directory(Path::new(""), &mut children)?;
+        } else {
+            let keypath = KeyPath::try_from(keypath)?;
+            let dir: PathBuf = keypath.components().iter().collect();
+            found = self.list_directory(&dir, &mut children)?;
+            for (path, keys) in self.candidates(&keypath) {
+                let Some(value) = self.try_get(&path, &keys)? else {
+                    continue;
+                };
+                found = true;
+                match value {
+                    Value::Mapping(mapping) => {
+                        for key in mapping.keys().filter_map(Value::as_str) {
+                            if is_valid_component(key) {
+                                children.entry(key.to_owned()).or_insert(ChildKind::Key);
+                            }
+                        }
+                    }
+                    Value::Sequence(sequence) => {
+                        for index in 0..sequence.len() {
+                            children
+                                .entry(index.to_string())
+                                .or_insert(ChildKind::Index);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if !found {
+            return Err(Error::KeyNotFound);
+        }
+        let mut children: Vec<Child> = children
+            .into_iter()
+            .map(|(name, kind)| Child { name, kind })
+            .collect();
+        // Sequence indices should sort numerically, and before any names.
+        children.sort_by_cached_key(|c| match c.name.parse::<usize>() {
+            Ok(index) => (false, index, String::new()),
+            Err(_) => (true, 0, c.name.clone()),
+        });
+        Ok(children)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(children: &[Child]) -> Vec<(&str, ChildKind)> {
+        children.iter().map(|c| (c.name.as_str(), c.kind)).collect()
+    }
+
+    #[test]
+    fn list_root() {
+        let datastore = Datastore::open(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data"));
+        let children = datastore.list("").unwrap();
+        assert_eq!(
+            names(&children),
+            vec![
+                ("complete", ChildKind::File),
+                ("duplicate", ChildKind::File),
+                ("empty", ChildKind::File),
+                ("no_tags", ChildKind::File),
+            ]
+        );
+    }
+
+    #[test]
+    fn list_merges_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("a/b/sub")).unwrap();
+        std::fs::write(dir.path().join("a/b/file.yaml"), "x: 1").unwrap();
+        std::fs::write(dir.path().join("a/b.yaml"), "from_file: 1\nfile: 2").unwrap();
+        std::fs::write(dir.path().join("a.yaml"), "b:\n  from_key: 1").unwrap();
+
+        let datastore = Datastore::open(dir.path());
+        let children = datastore.list("a.b").unwrap();
+        assert_eq!(
+            names(&children),
+            vec![
+                ("file", ChildKind::File),
+                ("from_file", ChildKind::Key),
+                ("from_key", ChildKind::Key),
+                ("sub", ChildKind::Directory),
+            ]
+        );
+    }
+
+    #[test]
+    fn list_skips_invalid_names() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join(".git")).unwrap();
+        std::fs::create_dir_all(dir.path().join("sub.dir")).unwrap();
+        std::fs::write(dir.path().join("a.b.yaml"), "x: 1").unwrap();
+        std::fs::write(dir.path().join("c.yaml"), "\" d\": 1\ne.f: 2\ng: 3").unwrap();
+
+        let datastore = Datastore::open(dir.path());
+        let children = datastore.list("").unwrap();
+        assert_eq!(names(&children), vec![("c", ChildKind::File)]);
+        let children = datastore.list("c").unwrap();
+        assert_eq!(names(&children), vec![("g", ChildKind::Key)]);
+    }
+
+    #[test]
+    fn list_leaf_and_missing() {
+        let datastore = Datastore::open(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data"));
+        assert!(datastore.list("complete.id").unwrap().is_empty());
+        let tags = datastore.list("complete.tags").unwrap();
+        assert_eq!(tags.len(), 3);
+        assert_eq!(tags[0].kind, ChildKind::Index);
+        let result = datastore.list("complete.missing").unwrap_err();
+        assert!(matches!(result, Error::KeyNotFound));
+    }
+}
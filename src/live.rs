@@ -0,0 +1,257 @@
+//! Handles to values that reload themselves when their files change.
+//!
+//! See [`Datastore::live`] for details. This module requires the `watch` feature.
+use crate::{
+    Datastore, Error,
+    keypath::KeyPath,
+    watch::{PollThread, Watch},
+};
+use serde::de::DeserializeOwned;
+use serde_yaml::{Value, value::from_value};
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// The current state of a [`Live`] value, shared with its polling thread.
+struct State<T> {
+    /// The latest value that deserialized successfully.
+    value: Arc<T>,
+    /// The raw value `value` was deserialized from, for deciding whether it changed.
+    raw: Value,
+    /// How many times `value` has been replaced.
+    generation: u64,
+    /// Why the latest reload failed, if it did.
+    error: Option<String>,
+}
+
+/// A value at a keypath that is reloaded whenever its files change, created by
+/// [`Datastore::live`].
+///
+/// Reloading stops when this is dropped.
+pub struct Live<T> {
+    /// The state, updated by the polling thread.
+    state: Arc<RwLock<State<T>>>,
+    /// The polling thread, stopped when dropped.
+    _thread: PollThread,
+}
+
+impl<T> std::fmt::Debug for Live<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Live")
+            .field("generation", &self.generation())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> Live<T> {
+    /// Read the state. It's only ever replaced whole, so poisoning is ignored.
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, State<T>> {
+        self.state.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Return the latest value that loaded successfully.
+    ///
+    /// The returned value is never modified; a reload replaces it with a new one, so it's safe to
+    /// hold on to for as long as needed.
+    #[must_use]
+    pub fn get(&self) -> Arc<T> {
+        Arc::clone(&self.read().value)
+    }
+
+    /// Return how many times the value has been replaced since it was first loaded.
+    ///
+    /// Comparing generations is a cheap way to tell whether the value has changed.
+    #[must_use]
+    pub fn generation(&self) -> u64 {
+        self.read().generation
+    }
+
+    /// Return why the latest reload failed, or `None` if it succeeded.
+    ///
+    /// While this is set, [`get()`](Self::get) still returns the last good value.
+    #[must_use]
+    pub fn last_error(&self) -> Option<String> {
+        self.read().error.clone()
+    }
+}
+
+impl<T> State<T>
+where
+    T: DeserializeOwned,
+{
+    /// Update the state with the result of resolving the keypath again.
+    fn reload(&mut self, result: Result<Value, Error>) {
+        let raw = match result {
+            Ok(raw) => raw,
+            Err(e) => {
+                self.error = Some(e.to_string());
+                return;
+            }
+        };
+        if raw == self.raw {
+            self.error = None;
+            return;
+        }
+        match from_value(raw.clone()) {
+            Ok(value) => {
+                self.value = Arc::new(value);
+                self.raw = raw;
+                self.generation += 1;
+                self.error = None;
+            }
+            Err(e) => self.error = Some(Error::from(e).to_string()),
+        }
+    }
+}
+
+impl Watch {
+    /// Start watching, returning a [`Live`] handle to the value at `keypath`.
+    ///
+    /// See [`Datastore::live`] for details.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Datastore::live`].
+    pub fn live<T>(self, keypath: &str) -> Result<Live<T>, Error>
+    where
+        T: DeserializeOwned + Send + Sync + 'static,
+    {
+        let keypath = KeyPath::try_from(keypath)?;
+        let datastore = Arc::clone(self.datastore());
+        let raw = datastore.resolve_checked(&keypath)?;
+        let state = Arc::new(RwLock::new(State {
+            value: Arc::new(from_value(raw.clone())?),
+            raw,
+            generation: 0,
+            error: None,
+        }));
+
+        let shared = Arc::clone(&state);
+        let thread = PollThread::spawn(Arc::clone(&datastore), &self, move |event| {
+            if event.affects(&keypath) {
+                // Resolve before locking so readers aren't blocked on the filesystem.
+                let result = datastore.resolve_checked(&keypath);
+                let mut state = shared.write().unwrap_or_else(PoisonError::into_inner);
+                state.reload(result);
+            }
+            true
+        });
+        Ok(Live {
+            state,
+            _thread: thread,
+        })
+    }
+}
+
+impl Datastore {
+    /// Load the value at `keypath` into a handle that reloads it whenever its files change.
+    ///
+    /// The value is resolved as in [`subscribe()`](Datastore::subscribe), and whenever it
+    /// changes it's deserialized and swapped in atomically: readers calling [`Live::get`] see
+    /// either the old value or the new one, never a mix. Each swap increments the
+    /// [generation](Live::generation).
+    ///
+    /// If a reload fails, because a file can't be parsed, the keypath no longer exists, or the
+    /// value can't be deserialized into `T`, the last good value is kept and the failure is
+    /// available from [`Live::last_error`] until a later reload succeeds.
+    ///
+    /// Files are polled with the default settings; use [`watch()`](Datastore::watch) and
+    /// [`Watch::live`] to configure them.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use serde::Deserialize;
+    /// use std::sync::Arc;
+    /// use yaml_datastore::Datastore;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Limits {
+    ///     max_connections: u32,
+    /// }
+    ///
+    /// let datastore = Arc::new(Datastore::open("config"));
+    /// let limits = datastore.live::<Limits>("service.limits").unwrap();
+    /// // Later, anywhere the handle is shared:
+    /// println!("{}", limits.get().max_connections);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::KeyPathError`] if `keypath` is invalid.
+    ///
+    /// Returns [`Error::KeyNotFound`] if `keypath` doesn't exist, and [`Error::DataParseError`]
+    /// if its value can't be deserialized into `T`, since a handle always holds a value.
+    ///
+    /// Returns [`Error::Walk`] if a file `keypath` would be resolved through can't be read or
+    /// parsed.
+    pub fn live<T>(self: &Arc<Self>, keypath: &str) -> Result<Live<T>, Error>
+    where
+        T: DeserializeOwned + Send + Sync + 'static,
+    {
+        self.watch().live(keypath)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        path::Path,
+        time::{Duration, Instant},
+    };
+
+    fn write(dir: &Path, path: &str, contents: &str) {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    fn wait_until(condition: impl Fn() -> bool) {
+        let start = Instant::now();
+        while !condition() {
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn live_reloads() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "service.yaml", "max: 1");
+        let datastore = Arc::new(Datastore::open(dir.path()));
+        let max = datastore
+            .watch()
+            .poll_interval(Duration::from_millis(10))
+            .debounce(Duration::from_millis(30))
+            .live::<u64>("service.max")
+            .unwrap();
+        assert_eq!((*max.get(), max.generation()), (1, 0));
+
+        write(dir.path(), "service.yaml", "max: 2");
+        wait_until(|| max.generation() == 1);
+        assert_eq!(*max.get(), 2);
+
+        write(dir.path(), "service.yaml", "max: [unclosed");
+        wait_until(|| max.last_error().is_some());
+        assert_eq!((*max.get(), max.generation()), (2, 1));
+
+        write(dir.path(), "service.yaml", "max: 3");
+        wait_until(|| max.generation() == 2);
+        assert_eq!(*max.get(), 3);
+        assert!(max.last_error().is_none());
+    }
+
+    #[test]
+    fn live_requires_value() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "service.yaml", "max: many");
+        let datastore = Arc::new(Datastore::open(dir.path()));
+        assert!(matches!(
+            datastore.live::<u64>("service.max"),
+            Err(Error::DataParseError(_))
+        ));
+        assert!(matches!(
+            datastore.live::<u64>("service.min"),
+            Err(Error::KeyNotFound)
+        ));
+    }
+}
@@ -0,0 +1,581 @@
+//! Command-line interface to a YAML datastore.
+//!
+//! This wraps the [`Datastore`] API so the datastore can be used from shell scripts. Run
+//! `yaml-datastore --help` for usage. It's only built with the `cli` feature enabled.
+use serde::Serialize;
+use serde_yaml::Value;
+use std::{io::Write, path::PathBuf, process::ExitCode};
+use yaml_datastore::{
+    Datastore, Error, diff,
+    document::Format,
+    keypath::KeyPath,
+    patch::{Operation, Patch},
+};
+
+/// Help text printed by `--help`.
+const USAGE: &str = "\
+Usage: yaml-datastore [OPTIONS] <COMMAND> [ARGS]...
+
+Commands:
+  get <KEYPATH>          Print the value at KEYPATH
+  set <KEYPATH> <VALUE>  Set KEYPATH to VALUE, parsed as YAML, or read from stdin if VALUE is -
+  ls [KEYPATH]           List the children of KEYPATH, or of the root, one per line
+  rm <KEYPATH>           Remove the value at KEYPATH
+  lint                   Check the datastore for problems, printing them in the output format
+  fmt                    Rewrite data files in a canonical style, printing the files changed
+  diff <OLD> <NEW>       Print the keypaths that differ between two datastore roots
+
+Options:
+  -r, --root <DIR>       Root directory of the datastore [default: .]
+  -f, --format <FORMAT>  Output format for values, yaml or json [default: yaml]
+      --check            With fmt, only print the files that would change, and fail if there
+                         are any, or if any were skipped for having comments. With diff, fail
+                         if there are any differences
+      --sort-keys        With fmt, sort the keys of every mapping
+      --strip-comments   With fmt, also format files with comments, removing them
+  -h, --help             Print this help
+  -V, --version          Print the version
+
+Arguments after -- are never treated as options, so `set a.b -- -1` sets a negative number.
+
+Exit status:
+  0  Success
+  1  Any other error, such as a root or file that can't be read or written
+  2  Invalid arguments or keypath
+  3  Keypath not found
+  4  A file or value can't be parsed
+  5  A check failed, such as lint finding problems or --check finding changes
+";
+
+/// Exit status for errors that don't have a more specific status.
+const EXIT_FAILURE: u8 = 1;
+
+/// Exit status for invalid arguments, including invalid keypaths.
+const EXIT_USAGE: u8 = 2;
+
+/// Exit status for a keypath that doesn't resolve to anything.
+const EXIT_NOT_FOUND: u8 = 3;
+
+/// Exit status for a file or value that can't be parsed.
+const EXIT_PARSE: u8 = 4;
+
+/// Exit status for a check that ran successfully but failed.
+const EXIT_CHECK: u8 = 5;
+
+/// A command given on the command line.
+#[derive(Debug, PartialEq)]
+enum Command {
+    /// Print the value at a keypath.
+    Get(String),
+    /// Set a keypath to a YAML value, or to one read from stdin if it is `-`.
+    Set(String, String),
+    /// List the children of a keypath, or of the root.
+    List(Option<String>),
+    /// Remove the value at a keypath.
+    Remove(String),
+    /// Check the datastore for problems.
+    Lint,
+    /// Rewrite data files in a canonical style.
+    Fmt,
+    /// Compare two datastore roots.
+    Diff(PathBuf, PathBuf),
+    /// Print the help text.
+    Help,
+    /// Print the version.
+    Version,
+}
+
+/// Options shared by every command.
+#[derive(Debug, PartialEq)]
+struct Options {
+    /// Root directory of the datastore.
+    root: PathBuf,
+    /// Format values are printed in.
+    format: Format,
+    /// Whether `fmt` only reports files instead of changing them.
+    check: bool,
+    /// Whether `fmt` sorts keys.
+    sort_keys: bool,
+    /// Whether `fmt` formats files with comments.
+    strip_comments: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            root: PathBuf::from("."),
+            format: Format::Yaml,
+            check: false,
+            sort_keys: false,
+            strip_comments: false,
+        }
+    }
+}
+
+/// Reasons the command can fail.
+#[derive(Debug)]
+enum Failure {
+    /// The command line was invalid.
+    Usage(String),
+    /// The datastore root couldn't be opened.
+    Open(PathBuf, Error),
+    /// The datastore returned an error.
+    Datastore(Error),
+    /// A check ran and failed.
+    Check(String),
+}
+
+impl From<Error> for Failure {
+    fn from(value: Error) -> Self {
+        Self::Datastore(value)
+    }
+}
+
+impl From<std::io::Error> for Failure {
+    fn from(value: std::io::Error) -> Self {
+        Self::Datastore(value.into())
+    }
+}
+
+impl Failure {
+    /// Return the exit status for this failure.
+    fn exit_code(&self) -> u8 {
+        match self {
+            Self::Usage(_) => EXIT_USAGE,
+            Self::Open(..) => EXIT_FAILURE,
+            Self::Datastore(error) => error_exit_code(error),
+            Self::Check(_) => EXIT_CHECK,
+        }
+    }
+}
+
+/// Return the exit status for a datastore error.
+fn error_exit_code(error: &Error) -> u8 {
+    match error {
+        Error::KeyNotFound | Error::IndexNotFound(_) => EXIT_NOT_FOUND,
+        Error::KeyPathError(_) | Error::EmptyKeyVector | Error::OverlappingKeyPaths(..) => {
+            EXIT_USAGE
+        }
+        Error::DataParseError(_) | Error::JsonError(_) | Error::NotAMapping => EXIT_PARSE,
+        // Reading a file that isn't valid UTF-8 fails with this kind.
+        Error::IOError(e) if e.kind() == std::io::ErrorKind::InvalidData => EXIT_PARSE,
+        Error::Walk(walk) => error_exit_code(&walk.error),
+        _ => EXIT_FAILURE,
+    }
+}
+
+/// Parse a `--format` value.
+fn parse_format(format: &str) -> Result<Format, Failure> {
+    match format {
+        "yaml" => Ok(Format::Yaml),
+        "json" => Ok(Format::Json),
+        _ => Err(Failure::Usage(format!(
+            "unknown format {format:?}, expected yaml or json"
+        ))),
+    }
+}
+
+/// Parse the command line, not including the program name.
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<(Options, Command), Failure> {
+    let mut options = Options::default();
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        // Long options may be given as `--name=value` as well as `--name value`.
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if arg.starts_with("--") => (name, Some(value.to_owned())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| Failure::Usage(format!("{name} requires a value")))
+        };
+        match name {
+            "--" => {
+                positional.extend(args.by_ref());
+                break;
+            }
+            "-h" | "--help" => return Ok((options, Command::Help)),
+            "-V" | "--version" => return Ok((options, Command::Version)),
+            "-r" | "--root" => options.root = value()?.into(),
+            "-f" | "--format" => options.format = parse_format(&value()?)?,
+            "--check" => options.check = true,
+            "--sort-keys" => options.sort_keys = true,
+            "--strip-comments" => options.strip_comments = true,
+            _ if name.starts_with('-') && name != "-" => {
+                return Err(Failure::Usage(format!("unknown option {name}")));
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let mut required = |what: &str| {
+        positional
+            .next()
+            .ok_or_else(|| Failure::Usage(format!("missing {what}")))
+    };
+    let command = match required("command")?.as_str() {
+        "get" => Command::Get(required("keypath")?),
+        "set" => Command::Set(required("keypath")?, required("value")?),
+        "ls" => Command::List(positional.next()),
+        "rm" => Command::Remove(required("keypath")?),
+        "lint" => Command::Lint,
+        "fmt" => Command::Fmt,
+        "diff" => Command::Diff(required("old root")?.into(), required("new root")?.into()),
+        command => return Err(Failure::Usage(format!("unknown command {command:?}"))),
+    };
+    if let Some(extra) = positional.next() {
+        return Err(Failure::Usage(format!("unexpected argument {extra:?}")));
+    }
+    Ok((options, command))
+}
+
+/// Serialize `value` to `out` in `format`, ending with a newline.
+fn print<W: Write, T: Serialize>(mut out: W, format: Format, value: &T) -> Result<(), Failure> {
+    match format {
+        Format::Yaml => serde_yaml::to_writer(out, value).map_err(Error::from)?,
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut out, value).map_err(Error::from)?;
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Open the datastore at `root`, failing if the root can't be read.
+fn open(root: &std::path::Path) -> Result<Datastore, Failure> {
+    let datastore = Datastore::try_open(root).map_err(|e| Failure::Open(root.to_owned(), e))?;
+    // Commands still run without an unusable ignore file or index cache, so only warn about them.
+    for error in datastore.open_errors() {
+        eprintln!("yaml-datastore: warning: {error}");
+    }
+    Ok(datastore)
+}
+
+/// Build the patch that sets `keypath` to `value`.
+///
+/// An existing value is replaced. Otherwise the value is added, along with empty mappings for
+/// any of its parents that don't exist yet.
+fn set_patch(datastore: &Datastore, keypath: &str, value: Value) -> Result<Patch, Failure> {
+    if datastore.exists(keypath)? {
+        let path = keypath.to_owned();
+        return Ok(Patch::Json(vec![Operation::Replace { path, value }]));
+    }
+    let parsed = KeyPath::try_from(keypath).map_err(Error::from)?;
+    let components = parsed.components();
+    let mut operations = Vec::new();
+    for depth in 1..components.len() {
+        let parent = components[..depth].join(".");
+        if !datastore.exists(&parent)? {
+            let value = Value::Mapping(serde_yaml::Mapping::new());
+            operations.push(Operation::Add {
+                path: parent,
+                value,
+            });
+        }
+    }
+    let path = keypath.to_owned();
+    operations.push(Operation::Add { path, value });
+    Ok(Patch::Json(operations))
+}
+
+/// Run `command`, writing its output to `out`.
+fn run<W: Write>(options: &Options, command: Command, mut out: W) -> Result<(), Failure> {
+    match command {
+        Command::Help => return Ok(write!(out, "{USAGE}")?),
+        Command::Version => {
+            return Ok(writeln!(
+                out,
+                "yaml-datastore {}",
+                env!("CARGO_PKG_VERSION")
+            )?);
+        }
+        Command::Diff(old, new) => {
+            let differences = diff(&open(&old)?, &open(&new)?)?;
+            print(&mut out, options.format, &differences)?;
+            if options.check && !differences.is_empty() {
+                let message = format!("{} keypaths differ", differences.len());
+                return Err(Failure::Check(message));
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+    let datastore = open(&options.root)?;
+    match command {
+        Command::Get(keypath) => print(&mut out, options.format, &datastore.get_value(&keypath)?)?,
+        Command::Set(keypath, value) => {
+            let value: Value = if value == "-" {
+                serde_yaml::from_reader(std::io::stdin().lock()).map_err(Error::from)?
+            } else {
+                serde_yaml::from_str(&value).map_err(Error::from)?
+            };
+            datastore.apply_patch(&set_patch(&datastore, &keypath, value)?)?;
+        }
+        Command::List(keypath) => {
+            for child in datastore.list(keypath.as_deref().unwrap_or(""))? {
+                writeln!(out, "{}", child.name)?;
+            }
+        }
+        Command::Remove(path) => {
+            datastore.apply_patch(&Patch::Json(vec![Operation::Remove { path }]))?;
+        }
+        Command::Lint => {
+            let issues = datastore.lint()?;
+            print(&mut out, options.format, &issues)?;
+            if !issues.is_empty() {
+                return Err(Failure::Check(format!("found {} problems", issues.len())));
+            }
+        }
+        Command::Fmt => {
+            let formatter = datastore
+                .formatter()
+                .sort_keys(options.sort_keys)
+                .strip_comments(options.strip_comments);
+            let report = if options.check {
+                formatter.check()?
+            } else {
+                formatter.apply()?
+            };
+            for change in &report.changed {
+                writeln!(out, "{}", change.path.display())?;
+            }
+            for path in &report.skipped {
+                eprintln!(
+                    "yaml-datastore: skipped {}, which may contain comments",
+                    path.display()
+                );
+            }
+            if options.check && !(report.changed.is_empty() && report.skipped.is_empty()) {
+                let message = format!(
+                    "{} files need formatting, {} files skipped",
+                    report.changed.len(),
+                    report.skipped.len()
+                );
+                return Err(Failure::Check(message));
+            }
+        }
+        // These don't need the datastore at the root, so they were run above.
+        Command::Diff(..) | Command::Help | Command::Version => {}
+    }
+    Ok(())
+}
+
+/// Print `failure` to stderr, including the chain of errors that caused it.
+fn report(failure: &Failure) {
+    match failure {
+        Failure::Check(message) => eprintln!("yaml-datastore: {message}"),
+        Failure::Usage(message) => {
+            eprintln!("yaml-datastore: {message}");
+            eprintln!("Run `yaml-datastore --help` for usage.");
+        }
+        Failure::Open(root, error) => {
+            eprintln!(
+                "yaml-datastore: can't open {}: {}",
+                root.display(),
+                chain(error)
+            );
+        }
+        Failure::Datastore(error) => eprintln!("yaml-datastore: {}", chain(error)),
+    }
+}
+
+/// Format `error` followed by the chain of errors that caused it.
+fn chain(error: &Error) -> String {
+    let mut message = error.to_string();
+    let mut source = std::error::Error::source(error);
+    while let Some(error) = source {
+        message = format!("{message}: {error}");
+        source = error.source();
+    }
+    message
+}
+
+fn main() -> ExitCode {
+    let result = parse_args(std::env::args().skip(1))
+        .and_then(|(options, command)| run(&options, command, std::io::stdout().lock()));
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            report(&failure);
+            ExitCode::from(failure.exit_code())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<(Options, Command), Failure> {
+        parse_args(args.iter().map(ToString::to_string))
+    }
+
+    fn run_args(root: &std::path::Path, list: &[&str]) -> Result<String, Failure> {
+        let (mut options, command) = args(list)?;
+        options.root = root.to_owned();
+        let mut out = Vec::new();
+        run(&options, command, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn parse_options() {
+        let (options, command) = args(&["--root=data", "get", "-f", "json", "a.b"]).unwrap();
+        assert_eq!(options.root, PathBuf::from("data"));
+        assert_eq!(options.format, Format::Json);
+        assert_eq!(command, Command::Get("a.b".to_owned()));
+
+        let (_, command) = args(&["set", "a", "--", "-1"]).unwrap();
+        assert_eq!(command, Command::Set("a".to_owned(), "-1".to_owned()));
+        let (_, command) = args(&["ls"]).unwrap();
+        assert_eq!(command, Command::List(None));
+    }
+
+    #[test]
+    fn parse_errors() {
+        for list in [
+            &["get"][..],
+            &["get", "a", "b"],
+            &["frobnicate"],
+            &["--root"],
+            &["-f", "toml", "ls"],
+            &["--verbose", "ls"],
+        ] {
+            let failure = args(list).unwrap_err();
+            assert_eq!(failure.exit_code(), EXIT_USAGE, "{list:?}");
+        }
+    }
+
+    #[test]
+    fn commands() {
+        let dir = tempfile::tempdir().unwrap();
+        run_args(dir.path(), &["set", "a.b", "{c: 1, d: [x]}"]).unwrap();
+        assert_eq!(run_args(dir.path(), &["get", "a.b.c"]).unwrap(), "1\n");
+        assert_eq!(
+            run_args(dir.path(), &["-f", "json", "get", "a.b.d"]).unwrap(),
+            "[\n  \"x\"\n]\n"
+        );
+        assert_eq!(run_args(dir.path(), &["ls", "a.b"]).unwrap(), "c\nd\n");
+        run_args(dir.path(), &["rm", "a.b.c"]).unwrap();
+
+        let failure = run_args(dir.path(), &["get", "a.b.c"]).unwrap_err();
+        assert_eq!(failure.exit_code(), EXIT_NOT_FOUND);
+        let failure = run_args(dir.path(), &["get", "a..b"]).unwrap_err();
+        assert_eq!(failure.exit_code(), EXIT_USAGE);
+        let failure = run_args(dir.path(), &["set", "a.b", "[unclosed"]).unwrap_err();
+        assert_eq!(failure.exit_code(), EXIT_PARSE);
+    }
+
+    #[test]
+    fn get_resolves_like_the_library() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.yaml"), "b: {c: 1}").unwrap();
+        std::fs::create_dir(dir.path().join("a")).unwrap();
+        std::fs::write(dir.path().join("a/b.yaml"), "d: 2").unwrap();
+        // A broken file that isn't a candidate for `a.b` doesn't get in the way.
+        std::fs::write(dir.path().join("other.yaml"), "[unclosed").unwrap();
+        // `a/b.yaml` is the whole of `a.b`, without `c` merged in from `a.yaml`.
+        assert_eq!(run_args(dir.path(), &["get", "a.b"]).unwrap(), "d: 2\n");
+    }
+
+    #[test]
+    fn lint() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.yaml"), "x: 1").unwrap();
+        assert_eq!(
+            run_args(dir.path(), &["-f", "json", "lint"]).unwrap(),
+            "[]\n"
+        );
+
+        std::fs::write(dir.path().join("empty.yaml"), "").unwrap();
+        let failure = run_args(dir.path(), &["lint"]).unwrap_err();
+        assert_eq!(failure.exit_code(), EXIT_CHECK);
+    }
+
+    #[test]
+    fn fmt() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.yml"), "{b: 1}").unwrap();
+        let failure = run_args(dir.path(), &["fmt", "--check"]).unwrap_err();
+        assert_eq!(failure.exit_code(), EXIT_CHECK);
+        assert_eq!(run_args(dir.path(), &["fmt"]).unwrap(), "a.yml\n");
+        assert_eq!(run_args(dir.path(), &["fmt", "--check"]).unwrap(), "");
+
+        // A file that may have comments can't be checked, so the check fails without listing it.
+        std::fs::write(dir.path().join("b.yaml"), "c:  1 # note").unwrap();
+        let failure = run_args(dir.path(), &["fmt", "--check"]).unwrap_err();
+        assert_eq!(failure.exit_code(), EXIT_CHECK);
+        let (mut options, command) = args(&["fmt", "--check", "--strip-comments"]).unwrap();
+        options.root = dir.path().to_owned();
+        let mut out = Vec::new();
+        assert!(run(&options, command, &mut out).is_err());
+        assert_eq!(out, b"b.yaml\n");
+    }
+
+    #[test]
+    fn diff_roots() {
+        let old = tempfile::tempdir().unwrap();
+        let new = tempfile::tempdir().unwrap();
+        std::fs::write(old.path().join("a.yaml"), "b: 1").unwrap();
+        std::fs::write(new.path().join("a.yaml"), "b: 2").unwrap();
+        let (old, new) = (old.path().to_str().unwrap(), new.path().to_str().unwrap());
+
+        let output = run_args(".".as_ref(), &["diff", old, new]).unwrap();
+        assert_eq!(
+            output,
+            "- keypath: a.b\n  change: changed\n  old: 1\n  new: 2\n"
+        );
+        let failure = run_args(".".as_ref(), &["--check", "diff", old, new]).unwrap_err();
+        assert_eq!(failure.exit_code(), EXIT_CHECK);
+        assert_eq!(run_args(".".as_ref(), &["diff", old, old]).unwrap(), "[]\n");
+    }
+
+    #[test]
+    fn parse_error_exit_code() {
+        let data = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data");
+        let failure = run_args(data.as_ref(), &["get", "duplicate"]).unwrap_err();
+        assert_eq!(failure.exit_code(), EXIT_PARSE);
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.yaml"), b"b: \xff").unwrap();
+        let failure = run_args(dir.path(), &["get", "a.b"]).unwrap_err();
+        assert_eq!(failure.exit_code(), EXIT_PARSE);
+    }
+
+    #[test]
+    fn missing_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing");
+        let failure = run_args(&missing, &["get", "a"]).unwrap_err();
+        assert!(matches!(failure, Failure::Open(..)));
+        assert_eq!(failure.exit_code(), EXIT_FAILURE);
+        assert!(run_args(&missing, &["--help"]).is_ok());
+
+        // `diff` only opens the roots it compares.
+        let root = dir.path().to_str().unwrap();
+        assert_eq!(run_args(&missing, &["diff", root, root]).unwrap(), "[]\n");
+        let failure = run_args(dir.path(), &["diff", root, missing.to_str().unwrap()]);
+        assert_eq!(failure.unwrap_err().exit_code(), EXIT_FAILURE);
+    }
+
+    #[test]
+    fn set_into_sequences_and_new_parents() {
+        let dir = tempfile::tempdir().unwrap();
+        run_args(dir.path(), &["set", "a.b.c.d", "1"]).unwrap();
+        assert_eq!(
+            run_args(dir.path(), &["get", "a"]).unwrap(),
+            "b:\n  c:\n    d: 1\n"
+        );
+        run_args(dir.path(), &["set", "a.l", "[x, y]"]).unwrap();
+        run_args(dir.path(), &["set", "a.l.0", "z"]).unwrap();
+        assert_eq!(run_args(dir.path(), &["get", "a.l"]).unwrap(), "- z\n- y\n");
+        run_args(dir.path(), &["rm", "a.l.0"]).unwrap();
+        assert_eq!(run_args(dir.path(), &["get", "a.l"]).unwrap(), "- y\n");
+        let failure = run_args(dir.path(), &["rm", "a.missing"]).unwrap_err();
+        assert_eq!(failure.exit_code(), EXIT_NOT_FOUND);
+    }
+}
@@ -0,0 +1,264 @@
+//! Per-root configuration read from a manifest file.
+//!
+//! A datastore can be configured by a YAML file named [`MANIFEST_PATH`] in its root, so every tool
+//! opening the same root behaves the same way. [`Datastore::open`] reads it automatically. Every
+//! setting is optional:
+//!
+//! ```yaml
+//! # Extensions of data files, highest precedence first. New files use the first one.
+//! extensions: [yaml, yml]
+//! # Whether deeper or shallower files win when several could hold a keypath.
+//! resolution: deepest_first
+//! # Gitignore-style patterns for files and directories that aren't data.
+//! ignore:
+//!   - scratch/
+//!   - "*.draft.yaml"
+//! # JSON Schemas for the records matched by each pattern, relative to the root.
+//! schemas:
+//!   users/*: schemas/user.json
+//! # Indexes to create, by name.
+//! indexes:
+//!   by_email:
+//!     pattern: users/*
+//!     field: email
+//! ```
+use crate::{Datastore, Error, keypath::DEFAULT_EXTENSIONS};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+/// Path of the manifest, relative to the root.
+pub const MANIFEST_PATH: &str = ".yaml-datastore.yaml";
+
+/// The order in which the files a keypath could resolve through are tried, set by a [`Manifest`].
+///
+/// Within each depth, files are tried in the order of their [extensions](Manifest::extensions).
+/// The same order decides which value wins wherever several files contribute to one, such as
+/// when [exporting](Datastore::export) or [validating](Datastore::validate).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolution {
+    /// Deeper files first, so a directory takes precedence over a file, and a file over a key:
+    /// `a.b` is read from `a/b.yaml` before the key `b` in `a.yaml`.
+    #[default]
+    DeepestFirst,
+    /// Shallower files first, so a key takes precedence over a file, and a file over a
+    /// directory: `a.b` is read from the key `b` in `a.yaml` before `a/b.yaml`.
+    ShallowestFirst,
+}
+
+/// An index declared in a [`Manifest`], as passed to [`Datastore::create_index`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IndexDefinition {
+    /// Pattern selecting the records to index.
+    pub pattern: String,
+    /// Keypath of the field within each record to index by.
+    pub field: String,
+}
+
+/// The contents of a datastore's manifest file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Manifest {
+    /// Extensions of data files, highest precedence first.
+    ///
+    /// When a keypath could resolve through files that differ only in extension, the file whose
+    /// extension comes first is used. Files the datastore creates are given the first extension.
+    pub extensions: Vec<String>,
+    /// Whether deeper or shallower files are tried first when resolving a keypath.
+    pub resolution: Resolution,
+    /// Gitignore-style patterns for files and directories under the root that aren't part of
+    /// the datastore.
+    pub ignore: Vec<String>,
+    /// Path of the JSON Schema for the records matched by each pattern, relative to the root.
+    ///
+    /// See [`Datastore::add_schema_file`].
+    pub schemas: BTreeMap<String, PathBuf>,
+    /// Indexes to create, by name.
+    pub indexes: BTreeMap<String, IndexDefinition>,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self {
+            extensions: DEFAULT_EXTENSIONS.iter().map(|&e| e.to_owned()).collect(),
+            resolution: Resolution::default(),
+            ignore: Vec::new(),
+            schemas: BTreeMap::new(),
+            indexes: BTreeMap::new(),
+        }
+    }
+}
+
+impl Manifest {
+    /// Read the manifest in `root`, returning `None` if there isn't one.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IOError`] if the manifest exists but can't be read, and
+    /// [`Error::DataParseError`] if it can't be parsed.
+    ///
+    /// Returns [`Error::InvalidManifest`] if it lists no extensions, or an extension that is empty
+    /// or contains a `.` or path separator.
+    pub fn load<P: AsRef<Path>>(root: P) -> Result<Option<Self>, Error> {
+        Datastore::builder(root.as_ref())
+            .unconfigured()
+            .read_manifest()
+    }
+
+    /// Parse and check the contents of a manifest.
+    fn parse(contents: &str) -> Result<Self, Error> {
+        let manifest: Self = match serde_yaml::from_str(contents) {
+            Ok(manifest) => manifest,
+            // An empty manifest is valid, and means every setting is the default.
+            Err(_) if contents.trim().is_empty() => Self::default(),
+            Err(e) => return Err(e.into()),
+        };
+        if manifest.extensions.is_empty() {
+            return Err(Error::InvalidManifest("no extensions listed".to_owned()));
+        }
+        if let Some(extension) = manifest
+            .extensions
+            .iter()
+            .find(|e| e.is_empty() || e.contains(['.', '/', '\\']))
+        {
+            return Err(Error::InvalidManifest(format!(
+                "invalid extension {extension:?}"
+            )));
+        }
+        Ok(manifest)
+    }
+}
+
+impl Datastore {
+    /// Read the manifest in the root, within the [sandbox](crate::builder::Builder::sandbox) and
+    /// [limits](crate::builder::Builder::limits), returning `None` if there isn't one.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`Manifest::load`], and the errors from
+    /// [`read_data_file()`](Self::read_data_file).
+    pub(crate) fn read_manifest(&self) -> Result<Option<Manifest>, Error> {
+        match self.read_data_file(Path::new(MANIFEST_PATH)) {
+            Ok(contents) => Manifest::parse(&contents).map(Some),
+            Err(Error::IOError(e)) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Datastore {
+    /// Attach the schemas and create the indexes declared in `manifest`, returning the errors
+    /// from [`add_schema_file()`](Self::add_schema_file) and
+    /// [`create_index()`](Self::create_index) for those that can't be added.
+    pub(crate) fn apply_manifest(&self, manifest: &Manifest) -> Vec<Error> {
+        let schemas = manifest
+            .schemas
+            .iter()
+            .map(|(pattern, path)| self.add_schema_file(pattern, path));
+        let indexes = manifest
+            .indexes
+            .iter()
+            .map(|(name, index)| self.create_index(name, &index.pattern, &index.field));
+        schemas.chain(indexes).filter_map(Result::err).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, path: &str, contents: &str) {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn open_with_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = "extensions: [yml, yaml]\nignore: [drafts/]\n\
+            schemas: {users/*: user.json}\n\
+            indexes: {by_email: {pattern: users/*, field: email}}";
+        write(dir.path(), MANIFEST_PATH, manifest);
+        write(dir.path(), "user.json", r#"{"required": ["email"]}"#);
+        write(dir.path(), "a.yaml", "x: 1");
+        write(dir.path(), "a.yml", "x: 2");
+        write(dir.path(), "drafts.yaml", "x: 1");
+        write(dir.path(), "drafts/b.yaml", "x: 1");
+        write(dir.path(), "users/alice.yaml", "email: a@example.com");
+        write(dir.path(), "users/bob.yaml", "name: bob");
+
+        let datastore = Datastore::open(dir.path());
+        assert_eq!(datastore.get::<i64>("a.x").unwrap(), 2);
+        assert!(datastore.get::<i64>("drafts.b.x").is_err());
+        assert_eq!(datastore.get::<i64>("drafts.x").unwrap(), 1);
+        let found = datastore.lookup("by_email", "a@example.com").unwrap();
+        assert_eq!(found[0].to_string(), "users.alice");
+        let violations = datastore.validate().unwrap();
+        assert_eq!(violations[0].keypath.to_string(), "users.bob");
+
+        datastore.set("c.x", &1).unwrap();
+        assert!(dir.path().join("c.yml").exists());
+    }
+
+    #[test]
+    fn shallowest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), MANIFEST_PATH, "resolution: shallowest_first");
+        write(dir.path(), "a.yaml", "b: {c: 1, d: 2}");
+        write(dir.path(), "a/b.yaml", "c: 3\ne: 4");
+        let datastore = Datastore::open(dir.path());
+        assert!(datastore.open_errors().is_empty());
+
+        // The key in `a.yaml` wins, and `a/b.yaml` still provides what it doesn't have.
+        assert_eq!(datastore.get::<i64>("a.b.c").unwrap(), 1);
+        assert_eq!(datastore.get::<i64>("a.b.e").unwrap(), 4);
+        let mut output = Vec::new();
+        datastore
+            .export_keypath("a.b", &mut output, crate::document::Format::Json)
+            .unwrap();
+        let exported: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(exported, serde_json::json!({"c": 1, "d": 2, "e": 4}));
+        let flat = datastore.flatten().unwrap();
+        let keypaths: Vec<&str> = flat.keys().map(String::as_str).collect();
+        assert_eq!(keypaths, ["a.b.c", "a.b.d", "a.b.e"]);
+        assert_eq!(flat["a.b.c"], serde_yaml::Value::from(1));
+
+        let issues = datastore.lint().unwrap();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].path, Path::new("a/b.yaml"));
+        assert_eq!(issues[0].message, "a/b.yaml is shadowed by a.b in a.yaml");
+
+        // Splitting and merging keep the same precedence.
+        datastore.split_file("a").unwrap();
+        assert_eq!(datastore.get::<i64>("a.b.c").unwrap(), 1);
+        datastore.merge_dir("a").unwrap();
+        assert_eq!(datastore.get::<i64>("a.b.c").unwrap(), 1);
+        assert_eq!(datastore.get::<i64>("a.b.e").unwrap(), 4);
+    }
+
+    #[test]
+    fn invalid_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(Manifest::load(dir.path()).unwrap(), None);
+        write(dir.path(), MANIFEST_PATH, "");
+        assert_eq!(
+            Manifest::load(dir.path()).unwrap(),
+            Some(Manifest::default())
+        );
+        write(dir.path(), MANIFEST_PATH, "extensions: [.yaml]");
+        assert!(matches!(
+            Manifest::load(dir.path()),
+            Err(Error::InvalidManifest(_))
+        ));
+        write(dir.path(), MANIFEST_PATH, "unknown: true");
+        assert!(matches!(
+            Manifest::load(dir.path()),
+            Err(Error::DataParseError(_))
+        ));
+    }
+}
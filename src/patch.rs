@@ -0,0 +1,469 @@
+//! Applying JSON Patch and JSON Merge Patch documents to a datastore.
+//!
+//! See [`Datastore::apply_patch`] for details.
+use crate::{Datastore, Error, index::compare_numbers, keypath::KeyPath, transaction::Transaction};
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+
+/// A single operation of a JSON Patch, as defined by [RFC 6902][00].
+///
+/// Paths may be JSON Pointers such as `/complete/tags/0`, or keypaths such as `complete.tags.0`.
+/// Operations deserialize from the RFC's JSON form, such as
+/// `{"op": "add", "path": "/a/b", "value": 1}`.
+///
+/// [00]: https://www.rfc-editor.org/rfc/rfc6902
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Operation {
+    /// Add a value, inserting it if the parent is a sequence and replacing it otherwise.
+    Add {
+        /// Where to add the value. A final `-` appends to a sequence.
+        path: String,
+        /// The value to add.
+        value: Value,
+    },
+    /// Remove the value at a path, which must exist.
+    Remove {
+        /// The path to remove.
+        path: String,
+    },
+    /// Replace the value at a path, which must exist.
+    Replace {
+        /// The path to replace.
+        path: String,
+        /// The new value.
+        value: Value,
+    },
+    /// Remove the value at one path and add it at another.
+    Move {
+        /// The path to move from.
+        from: String,
+        /// The path to move to.
+        path: String,
+    },
+    /// Add a copy of the value at one path at another.
+    Copy {
+        /// The path to copy from.
+        from: String,
+        /// The path to copy to.
+        path: String,
+    },
+    /// Check that the value at a path is equal to a given value.
+    Test {
+        /// The path to check.
+        path: String,
+        /// The expected value.
+        value: Value,
+    },
+}
+
+/// A patch to apply with [`Datastore::apply_patch`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Patch {
+    /// A JSON Patch as defined by [RFC 6902][00]: a list of operations applied in order.
+    ///
+    /// [00]: https://www.rfc-editor.org/rfc/rfc6902
+    Json(Vec<Operation>),
+
+    /// A JSON Merge Patch as defined by [RFC 7386][00]: a mapping merged into the datastore, with
+    /// `null` values removing keys.
+    ///
+    /// [00]: https://www.rfc-editor.org/rfc/rfc7386
+    Merge(Value),
+}
+
+/// Parse a patch path, which is either a JSON Pointer or a keypath.
+fn patch_keypath(path: &str) -> Result<KeyPath, Error> {
+    let Some(pointer) = path.strip_prefix('/') else {
+        return Ok(KeyPath::try_from(path)?);
+    };
+    let components: Vec<String> = pointer
+        .split('/')
+        .map(|c| c.replace("~1", "/").replace("~0", "~"))
+        .collect();
+    Ok(KeyPath::from_components(&components)?)
+}
+
+/// Apply `patch` to `target` as a merge patch, without reference to any files.
+fn merge_patch_value(target: &mut Value, patch: Value) {
+    let Value::Mapping(patch) = patch else {
+        *target = patch;
+        return;
+    };
+    if !target.is_mapping() {
+        *target = Value::Mapping(Mapping::new());
+    }
+    if let Value::Mapping(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.shift_remove(&key);
+            } else {
+                merge_patch_value(target.entry(key).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+/// Return whether two values are equal as a JSON Patch `test` operation compares them, treating
+/// numbers of equal value as equal, so `1` equals `1.0`.
+///
+/// Numbers are compared exactly, as [`IndexKey`](crate::index::IndexKey) orders them, so a large
+/// integer doesn't equal the float it rounds to.
+fn values_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => compare_numbers(a, b).is_eq(),
+        (Value::Sequence(a), Value::Sequence(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| values_equal(a, b))
+        }
+        (Value::Mapping(a), Value::Mapping(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.get(key).is_some_and(|b| values_equal(a, b)))
+        }
+        (Value::Tagged(a), Value::Tagged(b)) => a.tag == b.tag && values_equal(&a.value, &b.value),
+        _ => a == b,
+    }
+}
+
+impl Datastore {
+    /// Add `value` at `keypath` as a JSON Patch `add` operation.
+    fn patch_add(
+        &self,
+        transaction: &mut Transaction<'_>,
+        keypath: &KeyPath,
+        value: Value,
+    ) -> Result<(), Error> {
+        let components = keypath.components();
+        if let Some((last, parent)) = components.split_last()
+            && !parent.is_empty()
+        {
+            let parent = KeyPath::from_components(parent)?;
+            match self.materialize(Some(&parent))? {
+                Value::Sequence(mut sequence) => {
+                    let index = if *last == "-" {
+                        sequence.len()
+                    } else {
+                        last.parse::<usize>()
+                            .ok()
+                            .filter(|index| *index <= sequence.len())
+                            .ok_or(Error::KeyNotFound)?
+                    };
+                    sequence.insert(index, value);
+                    return self.replace_in(transaction, &parent, Value::Sequence(sequence));
+                }
+                Value::Mapping(_) => {}
+                // The RFC only allows adding to an object or array.
+                _ => return Err(Error::NotAMapping),
+            }
+        }
+        self.replace_in(transaction, keypath, value)
+    }
+
+    /// Apply a single JSON Patch operation.
+    fn apply_operation(
+        &self,
+        transaction: &mut Transaction<'_>,
+        operation: &Operation,
+    ) -> Result<(), Error> {
+        match operation {
+            Operation::Add { path, value } => {
+                self.patch_add(transaction, &patch_keypath(path)?, value.clone())
+            }
+            Operation::Remove { path } => {
+                if self.remove_in(transaction, &patch_keypath(path)?)? {
+                    Ok(())
+                } else {
+                    Err(Error::KeyNotFound)
+                }
+            }
+            Operation::Replace { path, value } => {
+                let keypath = patch_keypath(path)?;
+                self.materialize(Some(&keypath))?;
+                self.replace_in(transaction, &keypath, value.clone())
+            }
+            Operation::Move { from, path } => {
+                let (from, to) = (patch_keypath(from)?, patch_keypath(path)?);
+                let value = self.materialize(Some(&from))?;
+                if from == to {
+                    return Ok(());
+                }
+                if to.starts_with(&from) {
+                    return Err(Error::OverlappingKeyPaths(from.into(), to.into()));
+                }
+                self.remove_in(transaction, &from)?;
+                self.patch_add(transaction, &to, value)
+            }
+            Operation::Copy { from, path } => {
+                let value = self.materialize(Some(&patch_keypath(from)?))?;
+                self.patch_add(transaction, &patch_keypath(path)?, value)
+            }
+            Operation::Test { path, value } => {
+                match self.materialize(Some(&patch_keypath(path)?)) {
+                    Ok(actual) if values_equal(&actual, value) => Ok(()),
+                    Ok(_) | Err(Error::KeyNotFound) => Err(Error::PatchTestFailed(path.clone())),
+                    Err(e) => Err(e),
+                }
+            }
+        }
+    }
+
+    /// Merge `patch` into the value at `prefix`, or at the root if it is `None`.
+    ///
+    /// Keys are merged one at a time, so keys the patch doesn't mention stay in whatever file
+    /// they are in.
+    fn apply_merge(
+        &self,
+        transaction: &mut Transaction<'_>,
+        prefix: Option<&KeyPath>,
+        patch: Mapping,
+    ) -> Result<(), Error> {
+        for (key, value) in patch {
+            let key = key.as_str().ok_or(Error::NotAMapping)?;
+            let keypath = match prefix {
+                Some(prefix) => prefix.join(key)?,
+                None => KeyPath::try_from(key)?,
+            };
+            match value {
+                Value::Null => {
+                    self.remove_in(transaction, &keypath)?;
+                }
+                Value::Mapping(patch) => match self.materialize(Some(&keypath)) {
+                    Ok(Value::Mapping(_)) => {
+                        self.apply_merge(transaction, Some(&keypath), patch)?;
+                    }
+                    Ok(_) | Err(Error::KeyNotFound) => {
+                        let mut value = Value::Null;
+                        merge_patch_value(&mut value, Value::Mapping(patch));
+                        self.replace_in(transaction, &keypath, value)?;
+                    }
+                    Err(e) => return Err(e),
+                },
+                value => self.replace_in(transaction, &keypath, value)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply a JSON Patch or JSON Merge Patch to the datastore.
+    ///
+    /// The datastore is treated as a single document, as [exported](Datastore::export), so a patch
+    /// can change values in any number of files. Paths are keypaths, and may be written either as
+    /// JSON Pointers such as `/complete/nested/value` or as keypaths such as
+    /// `complete.nested.value`. Values are written into the file the keypath already resolves
+    /// through, creating files as needed, and anything else that contributed to the old value,
+    /// such as a shadowing file or directory, is removed so that [`get()`](Datastore::get) returns
+    /// the new value.
+    ///
+    /// For a [JSON Patch](Patch::Json), operations are applied in order, each seeing the result of
+    /// the ones before. A [merge patch](Patch::Merge) must be a mapping, and is merged key by key:
+    /// `null` removes a key, a mapping is merged recursively, and anything else replaces the value.
+    ///
+    /// Applying a patch is all-or-nothing: if any operation fails, including a `test` operation,
+    /// every file is restored to how it was before.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use yaml_datastore::{Datastore, patch::Patch};
+    ///
+    /// let datastore = Datastore::open("data");
+    /// let operations = serde_json::from_str(
+    ///     r#"[
+    ///         {"op": "test", "path": "/complete/id", "value": 1},
+    ///         {"op": "replace", "path": "/complete/nested/value", "value": false}
+    ///     ]"#,
+    /// )
+    /// .unwrap();
+    /// datastore.apply_patch(&Patch::Json(operations)).unwrap();
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::PatchTestFailed`] if a `test` operation fails.
+    ///
+    /// Returns [`Error::KeyPathError`] if a path is not a valid keypath or JSON Pointer, including
+    /// the empty path, since the datastore as a whole can't be replaced.
+    ///
+    /// Returns [`Error::KeyNotFound`] if an operation requires a value that doesn't exist, such as
+    /// removing a missing keypath or adding beneath one. As in the RFC, `add` only creates the
+    /// last component of its path, although top-level keys are always allowed.
+    ///
+    /// Returns [`Error::NotAMapping`] if an `add` operation's parent is neither a mapping nor a
+    /// sequence.
+    ///
+    /// Returns [`Error::OverlappingKeyPaths`] if a value would be moved beneath itself.
+    ///
+    /// Returns [`Error::NotAMapping`] if a merge patch, or a mapping within one, isn't a mapping
+    /// with string keys.
+    ///
+    /// Returns [`Error::Walk`], [`Error::DataParseError`] or [`Error::IOError`] if a file can't be
+    /// read, parsed or written.
+    ///
+    /// Returns [`Error::SchemaViolation`] as described in
+    /// [`enforce_schemas()`](Datastore::enforce_schemas).
+    pub fn apply_patch(&self, patch: &Patch) -> Result<(), Error> {
+        let mut transaction = Transaction::new(self);
+        match patch {
+            Patch::Json(operations) => {
+                for operation in operations {
+                    self.apply_operation(&mut transaction, operation)?;
+                }
+            }
+            Patch::Merge(Value::Mapping(patch)) => {
+                self.apply_merge(&mut transaction, None, patch.clone())?;
+            }
+            Patch::Merge(_) => return Err(Error::NotAMapping),
+        }
+        transaction.commit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn write(dir: &Path, path: &str, contents: &str) {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    fn operations(json: &str) -> Patch {
+        Patch::Json(serde_json::from_str(json).unwrap())
+    }
+
+    #[test]
+    fn json_patch() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.yaml", "tags: [x, z]\nold: 1");
+        write(dir.path(), "a/b.yaml", "c: 1");
+        let datastore = Datastore::open(dir.path());
+
+        let patch = operations(
+            r#"[
+                {"op": "test", "path": "/a/b/c", "value": 1},
+                {"op": "add", "path": "/a/tags/1", "value": "y"},
+                {"op": "add", "path": "a.tags.-", "value": "end"},
+                {"op": "move", "from": "/a/old", "path": "/other"},
+                {"op": "copy", "from": "/a/b", "path": "/a/b_copy"},
+                {"op": "replace", "path": "/a/b/c", "value": 2},
+                {"op": "remove", "path": "/a/tags/0"}
+            ]"#,
+        );
+        datastore.apply_patch(&patch).unwrap();
+        let tags: Vec<String> = datastore.get("a.tags").unwrap();
+        assert_eq!(tags, vec!["y", "z", "end"]);
+        assert!(!datastore.exists("a.old").unwrap());
+        assert_eq!(datastore.get::<u64>("other").unwrap(), 1);
+        assert_eq!(datastore.get::<u64>("a.b_copy.c").unwrap(), 1);
+        assert_eq!(datastore.get::<u64>("a.b.c").unwrap(), 2);
+    }
+
+    #[test]
+    fn failed_test_rolls_back() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.yaml", "b: 1");
+        let datastore = Datastore::open(dir.path());
+
+        let patch = operations(
+            r#"[
+                {"op": "replace", "path": "/a/b", "value": 2},
+                {"op": "add", "path": "/new", "value": 3},
+                {"op": "test", "path": "/a/b", "value": 1}
+            ]"#,
+        );
+        let result = datastore.apply_patch(&patch).unwrap_err();
+        assert!(matches!(result, Error::PatchTestFailed(_)));
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("a.yaml")).unwrap(),
+            "b: 1"
+        );
+        assert!(!dir.path().join("new.yaml").exists());
+    }
+
+    #[test]
+    fn add_to_scalar_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.yaml", "b: 1");
+        let datastore = Datastore::open(dir.path());
+
+        let patch = operations(r#"[{"op": "add", "path": "/a/b/c", "value": 2}]"#);
+        let result = datastore.apply_patch(&patch).unwrap_err();
+        assert!(matches!(result, Error::NotAMapping));
+        assert_eq!(datastore.get::<u64>("a.b").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_compares_numbers_by_value() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.yaml", "b: 1\nc: {d: [2.0]}");
+        let datastore = Datastore::open(dir.path());
+
+        let patch = operations(
+            r#"[
+                {"op": "test", "path": "/a/b", "value": 1.0},
+                {"op": "test", "path": "/a/c", "value": {"d": [2]}}
+            ]"#,
+        );
+        datastore.apply_patch(&patch).unwrap();
+        let patch = operations(r#"[{"op": "test", "path": "/a/b", "value": 1.5}]"#);
+        assert!(matches!(
+            datastore.apply_patch(&patch),
+            Err(Error::PatchTestFailed(_))
+        ));
+
+        // 2^53 + 1 rounds to the float 2^53, but isn't equal to it.
+        write(dir.path(), "big.yaml", "n: 9007199254740993");
+        let patch =
+            operations(r#"[{"op": "test", "path": "/big/n", "value": 9007199254740992.0}]"#);
+        assert!(matches!(
+            datastore.apply_patch(&patch),
+            Err(Error::PatchTestFailed(_))
+        ));
+        let patch = operations(r#"[{"op": "test", "path": "/big/n", "value": 9007199254740993}]"#);
+        datastore.apply_patch(&patch).unwrap();
+    }
+
+    #[test]
+    fn move_to_itself() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.yaml", "b: 1");
+        let datastore = Datastore::open(dir.path());
+
+        let patch = operations(r#"[{"op": "move", "from": "/a/b", "path": "/a/b"}]"#);
+        datastore.apply_patch(&patch).unwrap();
+        assert_eq!(datastore.get::<u64>("a.b").unwrap(), 1);
+        let patch = operations(r#"[{"op": "move", "from": "/a/c", "path": "/a/c"}]"#);
+        assert!(matches!(
+            datastore.apply_patch(&patch),
+            Err(Error::KeyNotFound)
+        ));
+    }
+
+    #[test]
+    fn merge_patch() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "a.yaml",
+            "keep: 1\nremove: 2\nnested: {x: 1, y: 2}",
+        );
+        write(dir.path(), "a/b.yaml", "c: 1");
+        let datastore = Datastore::open(dir.path());
+
+        let patch: Value = serde_json::from_str(
+            r#"{"a": {"remove": null, "nested": {"y": null, "z": 3}, "b": {"c": 2}},
+                "new": {"x": {"y": 1, "gone": null}}}"#,
+        )
+        .unwrap();
+        datastore.apply_patch(&Patch::Merge(patch)).unwrap();
+        assert_eq!(datastore.get::<u64>("a.keep").unwrap(), 1);
+        assert!(!datastore.exists("a.remove").unwrap());
+        assert!(!datastore.exists("a.nested.y").unwrap());
+        assert_eq!(datastore.get::<u64>("a.nested.z").unwrap(), 3);
+        assert_eq!(datastore.get::<u64>("a.b.c").unwrap(), 2);
+        assert!(dir.path().join("a/b.yaml").is_file());
+        assert!(!datastore.exists("new.x.gone").unwrap());
+    }
+}
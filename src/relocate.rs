@@ -0,0 +1,232 @@
+//! Moving and copying values between keypaths.
+//!
+//! See [`Datastore::rename`] and [`Datastore::copy`].
+use crate::{
+    Datastore, Error, flat::Layout, keypath::KeyPath, remove_value_at, set_value_at,
+    transaction::Transaction, value_at,
+};
+use serde_yaml::Value;
+use std::{collections::BTreeMap, path::PathBuf};
+
+impl Datastore {
+    /// Remove everything that contributes to the value at `keypath` as part of `transaction`.
+    ///
+    /// That is the key from every file that resolves it, the files named by the keypath itself,
+    /// and every data file in the directory named by the keypath. Returns whether anything was
+    /// removed.
+    pub(crate) fn remove_in(
+        &self,
+        transaction: &mut Transaction<'_>,
+        keypath: &KeyPath,
+    ) -> Result<bool, Error> {
+        let mut removed = false;
+        for (path, keys) in self.candidates(keypath) {
+            if !self.is_file(&path) {
+                continue;
+            }
+            if keys.is_empty() {
+                transaction.remove(&path)?;
+                removed = true;
+            } else {
+                let mut value = self.read_value(&path)?;
+                if remove_value_at(&mut value, &keys).is_some() {
+                    transaction.write(&path, &value)?;
+                    removed = true;
+                }
+            }
+        }
+        let directory: PathBuf = keypath.components().iter().collect();
+        if self.is_dir(&directory) {
+            let mut files = Vec::new();
+            self.collect_contributing_files(&directory, &mut files)?;
+            for path in &files {
+                transaction.remove(path)?;
+                removed = true;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Replace the value at `keypath` with `value` as part of `transaction`.
+    ///
+    /// The value is written into the file `keypath` currently resolves through, as with
+    /// [`Layout::Existing`], so a keypath that is a whole file stays one. A keypath that doesn't
+    /// resolve through any file is written beneath the file named by its first component. Anything
+    /// else contributing to the old value is removed so that `value` is what resolves afterwards.
+    pub(crate) fn replace_in(
+        &self,
+        transaction: &mut Transaction<'_>,
+        keypath: &KeyPath,
+        value: Value,
+    ) -> Result<(), Error> {
+        let (path, keys) = self.place(keypath, Layout::Existing(1), &BTreeMap::new());
+        // Removing an element of a sequence shifts the later ones down, so it goes back in at
+        // the same index rather than overwriting the next one.
+        let element = match keys.split_last() {
+            Some((last, parents)) => last.parse::<usize>().ok().filter(|index| {
+                matches!(
+                    value_at(self.read_value_for_update(&path).unwrap_or_default(), parents),
+                    Some(Value::Sequence(sequence)) if *index < sequence.len()
+                )
+            }),
+            None => None,
+        };
+        self.remove_in(transaction, keypath)?;
+        let mut file = self.read_value_for_update(&path)?;
+        match (element, keys.split_last()) {
+            (Some(index), Some((_, parents))) => {
+                if let Some(Value::Sequence(mut sequence)) = value_at(file.clone(), parents) {
+                    sequence.insert(index.min(sequence.len()), value);
+                    set_value_at(&mut file, parents, Value::Sequence(sequence));
+                } else {
+                    set_value_at(&mut file, &keys, value);
+                }
+            }
+            _ => set_value_at(&mut file, &keys, value),
+        }
+        transaction.write(&path, &file)
+    }
+
+    /// Parse `from` and `to`, and check that neither contains the other.
+    fn relocation(from: &str, to: &str) -> Result<(KeyPath, KeyPath), Error> {
+        let from = KeyPath::try_from(from)?;
+        let to = KeyPath::try_from(to)?;
+        if from.starts_with(&to) || to.starts_with(&from) {
+            return Err(Error::OverlappingKeyPaths(from.into(), to.into()));
+        }
+        Ok((from, to))
+    }
+
+    /// Move the value at `from` to `to`, removing it from `from`.
+    ///
+    /// The complete value at `from` is moved, even if it is spread across several files, such as
+    /// `a.yaml` and the directory `a/`. It is written as a single value into the file `to`
+    /// resolves through, creating the file and any mappings along the way if needed, and replacing
+    /// whatever was at `to` before. For example, renaming `complete.nested` to
+    /// `archive.complete_nested` removes the key `nested` from `complete.yaml` and adds the key
+    /// `complete_nested` to `archive.yaml`. Use [`split_file()`](Self::split_file) afterwards to
+    /// spread a moved value across files again.
+    ///
+    /// Files and directories left empty are removed. The move is atomic: if any file can't be
+    /// changed, every file is restored.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use yaml_datastore::Datastore;
+    ///
+    /// let datastore = Datastore::open("data");
+    /// datastore.rename("complete.nested", "archive.complete_nested").unwrap();
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::KeyPathError`] if either keypath is invalid.
+    ///
+    /// Returns [`Error::OverlappingKeyPaths`] if `to` is `from` or is beneath it, or the other
+    /// way around.
+    ///
+    /// Returns [`Error::KeyNotFound`] if nothing exists at `from`.
+    ///
+    /// Returns [`Error::Walk`], [`Error::DataParseError`] or [`Error::IOError`] if a file can't be
+    /// read, parsed or written.
+    ///
+    /// Returns [`Error::SchemaViolation`] as described in
+    /// [`enforce_schemas()`](Datastore::enforce_schemas).
+    pub fn rename(&self, from: &str, to: &str) -> Result<(), Error> {
+        let (from, to) = Self::relocation(from, to)?;
+        let value = self.materialize(Some(&from))?;
+        let mut transaction = Transaction::new(self);
+        self.remove_in(&mut transaction, &from)?;
+        self.replace_in(&mut transaction, &to, value)?;
+        transaction.commit()
+    }
+
+    /// Copy the value at `from` to `to`, leaving `from` unchanged.
+    ///
+    /// The value is written exactly as in [`rename()`](Self::rename).
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`rename()`](Self::rename).
+    pub fn copy(&self, from: &str, to: &str) -> Result<(), Error> {
+        let (from, to) = Self::relocation(from, to)?;
+        let value = self.materialize(Some(&from))?;
+        let mut transaction = Transaction::new(self);
+        self.replace_in(&mut transaction, &to, value)?;
+        transaction.commit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn write(dir: &Path, path: &str, contents: &str) {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn rename_between_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "complete.yaml",
+            "name: x\nnested: {value: true}",
+        );
+        write(dir.path(), "complete/nested.yaml", "extra: 1");
+        let datastore = Datastore::open(dir.path());
+
+        datastore
+            .rename("complete.nested", "archive.complete_nested")
+            .unwrap();
+        assert!(!datastore.exists("complete.nested").unwrap());
+        assert!(!dir.path().join("complete").exists());
+        assert!(
+            datastore
+                .get::<bool>("archive.complete_nested.value")
+                .unwrap()
+        );
+        assert_eq!(
+            datastore
+                .get::<u64>("archive.complete_nested.extra")
+                .unwrap(),
+            1
+        );
+        assert_eq!(datastore.get::<String>("complete.name").unwrap(), "x");
+    }
+
+    #[test]
+    fn copy_replaces_destination() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.yaml", "b: {x: 1}\nc: {old: true}");
+        write(dir.path(), "a/c.yaml", "also_old: true");
+        let datastore = Datastore::open(dir.path());
+
+        datastore.copy("a.b", "a.c").unwrap();
+        assert_eq!(datastore.get::<u64>("a.b.x").unwrap(), 1);
+        assert_eq!(datastore.get::<u64>("a.c.x").unwrap(), 1);
+        assert!(!datastore.exists("a.c.old").unwrap());
+        assert!(!datastore.exists("a.c.also_old").unwrap());
+        // The destination was a whole file, so it stays one.
+        assert!(dir.path().join("a/c.yaml").is_file());
+    }
+
+    #[test]
+    fn relocate_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.yaml", "b: 1");
+        let datastore = Datastore::open(dir.path());
+        assert!(matches!(
+            datastore.rename("a", "a.b").unwrap_err(),
+            Error::OverlappingKeyPaths(..)
+        ));
+        assert!(matches!(
+            datastore.copy("missing", "a.c").unwrap_err(),
+            Error::KeyNotFound
+        ));
+    }
+}
@@ -0,0 +1,368 @@
+//! Changing how data is split across files without changing what it resolves to.
+//!
+//! See [`Datastore::split_file`] and [`Datastore::merge_dir`].
+use crate::{
+    Datastore, Error,
+    cache::SIDECAR_DIR,
+    document::merge_into,
+    keypath::{KeyPath, KeyPathParseError, is_valid_component},
+    transaction::Transaction,
+    value_at,
+    walk::collect_leaves,
+};
+use serde_yaml::Value;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    path::{Path, PathBuf},
+};
+
+impl Datastore {
+    /// Recursively collect the data files under `dir` that contribute to its keypaths.
+    ///
+    /// These are the files [`Datastore::materialize_directory`] reads: ignored entries, the sidecar
+    /// directory and names that aren't valid keypath components are skipped.
+    pub(crate) fn collect_contributing_files(
+        &self,
+        dir: &Path,
+        files: &mut Vec<PathBuf>,
+    ) -> Result<(), Error> {
+        self.visit_contributing_files(dir, files, &mut |_, e| Err(e))
+    }
+
+    /// Collect the data files under `dir` as
+    /// [`collect_contributing_files()`](Self::collect_contributing_files) does, skipping any
+    /// directory or entry that can't be read and adding it to `unreadable` instead.
+    #[cfg(feature = "watch")]
+    pub(crate) fn collect_readable_files(
+        &self,
+        dir: &Path,
+        files: &mut Vec<PathBuf>,
+        unreadable: &mut Vec<PathBuf>,
+    ) {
+        let mut skip = |path: &Path, _| {
+            unreadable.push(path.to_owned());
+            Ok(())
+        };
+        // Every error is skipped, so there's none to return.
+        let _ = self.visit_contributing_files(dir, files, &mut skip);
+    }
+
+    /// Collect the data files under `dir`, calling `on_error` with any directory or entry that
+    /// can't be read. It's skipped if `on_error` returns `Ok`.
+    fn visit_contributing_files(
+        &self,
+        dir: &Path,
+        files: &mut Vec<PathBuf>,
+        on_error: &mut dyn FnMut(&Path, Error) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let entries = match self.read_directory(dir) {
+            Ok(entries) => entries,
+            Err(e) => return on_error(dir, e),
+        };
+        for entry in entries {
+            let path = dir.join(entry.file_name());
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            if path == Path::new(SIDECAR_DIR) {
+                continue;
+            }
+            let is_dir = match entry.file_type() {
+                Ok(file_type) => file_type.is_dir(),
+                Err(e) => {
+                    on_error(&path, e.into())?;
+                    continue;
+                }
+            };
+            if self.is_ignored(&path, is_dir) {
+                continue;
+            }
+            if is_dir {
+                if is_valid_component(name) {
+                    self.visit_contributing_files(&path, files, on_error)?;
+                }
+            } else if let Some(stem) = path.file_stem().and_then(|s| s.to_str())
+                && is_valid_component(stem)
+                && self.has_data_extension(&path)
+            {
+                files.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the existing data files whose whole contents are the value at `keypath`.
+    ///
+    /// Files are returned in increasing order of precedence, so `a.yml` comes before `a.yaml`.
+    fn files_for(&self, keypath: &KeyPath) -> Vec<PathBuf> {
+        self.candidates(keypath)
+            .rev()
+            .filter(|(path, keys)| keys.is_empty() && self.is_file(path))
+            .map(|(path, _)| path)
+            .collect()
+    }
+
+    /// Merge the contents of `files`, given in increasing order of precedence.
+    fn merge_files(&self, files: &[PathBuf]) -> Result<Option<Value>, Error> {
+        let mut value = None;
+        for path in files {
+            merge_into(&mut value, self.read_value(path)?);
+        }
+        Ok(value)
+    }
+
+    /// Resolve every leaf keypath at or beneath `prefix`, as [`get()`](Self::get) does.
+    ///
+    /// Every leaf of every file contributing to `prefix` is resolved, including those that are
+    /// hidden from [`export()`](Self::export) by a scalar in another file, since `get()` can still
+    /// reach them. Only those files are read, not the rest of the datastore, and files that can't
+    /// be read or parsed are skipped.
+    fn resolve_leaves(&self, prefix: &KeyPath) -> BTreeMap<KeyPath, Value> {
+        let mut leaves = VecDeque::new();
+        for (path, keys) in self.candidates(prefix) {
+            if let Ok(value) = self.read_value(&path)
+                && let Some(value) = value_at(value, &keys)
+            {
+                collect_leaves(prefix.clone(), value, &mut leaves);
+            }
+        }
+        let directory: PathBuf = prefix.components().iter().collect();
+        let mut files = Vec::new();
+        if self.is_dir(&directory) && !self.is_ignored(&directory, true) {
+            let mut unreadable = |_: &Path, _| Ok(());
+            // Every error is skipped, so there's none to return.
+            let _ = self.visit_contributing_files(&directory, &mut files, &mut unreadable);
+        }
+        for path in files {
+            if let Ok(keypath) = KeyPath::from_path(&path)
+                && let Ok(value) = self.read_value(&path)
+            {
+                collect_leaves(keypath, value, &mut leaves);
+            }
+        }
+        leaves
+            .into_iter()
+            .filter_map(|(keypath, _)| {
+                let value = self.resolve(&keypath.to_string(), Some).ok()?;
+                Some((keypath, value))
+            })
+            .collect()
+    }
+
+    /// Keep `transaction` if every leaf beneath `keypath` still resolves to the value in `before`,
+    /// and roll it back otherwise.
+    fn commit_if_unchanged(
+        &self,
+        transaction: Transaction<'_>,
+        keypath: &KeyPath,
+        before: &BTreeMap<KeyPath, Value>,
+    ) -> Result<(), Error> {
+        if self.resolve_leaves(keypath) == *before {
+            transaction.commit()
+        } else {
+            transaction.rollback()?;
+            Err(Error::ValueChanged(keypath.to_string()))
+        }
+    }
+
+    /// Split the file at `keypath` into a directory with one file per top-level key.
+    ///
+    /// A file `a.yaml` containing the keys `b` and `c` becomes `a/b.yaml` and `a/c.yaml`, and
+    /// `a.yaml` is removed. If `a/b.yaml` or `a/b.yml` already exists, the key `b` is merged into
+    /// it, with whichever took precedence before the split still taking precedence: the existing
+    /// file, unless the [manifest](crate::manifest) puts shallower files first. If both `a.yaml`
+    /// and `a.yml` exist, both are split and removed.
+    ///
+    /// The change is atomic: afterwards, every leaf keypath beneath `keypath` is checked to resolve
+    /// to the same value as it did before, and if any doesn't, every file is restored. Only the
+    /// files contributing to `keypath` are read for the check.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use yaml_datastore::Datastore;
+    ///
+    /// let datastore = Datastore::open("data");
+    /// datastore.split_file("users").unwrap();
+    /// // users.yaml is now users/alice.yaml, users/bob.yaml, ...
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::KeyPathError`] if `keypath` is invalid, or if a top-level key of the file
+    /// isn't a string that is a valid keypath component, since it couldn't name a file.
+    ///
+    /// Returns [`Error::KeyNotFound`] if there is no file at `keypath`.
+    ///
+    /// Returns [`Error::NotAMapping`] if the file doesn't contain a mapping.
+    ///
+    /// Returns [`Error::ValueChanged`] if splitting would change a resolved value, for example
+    /// because a scalar in `a/b.yaml` hid keys beneath `b` in `a.yaml` that would be lost.
+    ///
+    /// Returns [`Error::DataParseError`] or [`Error::IOError`] if a file can't be read, parsed or
+    /// written.
+    pub fn split_file(&self, keypath: &str) -> Result<(), Error> {
+        let keypath = KeyPath::try_from(keypath)?;
+        let files = self.files_for(&keypath);
+        let mapping = match self.merge_files(&files)? {
+            None => return Err(Error::KeyNotFound),
+            Some(Value::Null) => serde_yaml::Mapping::new(),
+            Some(Value::Mapping(mapping)) => mapping,
+            Some(_) => return Err(Error::NotAMapping),
+        };
+        let mut children = Vec::with_capacity(mapping.len());
+        for (key, value) in mapping {
+            match key.as_str() {
+                Some(key) if is_valid_component(key) => children.push((key.to_owned(), value)),
+                _ => return Err(KeyPathParseError::InvalidKeyPath.into()),
+            }
+        }
+
+        let before = self.resolve_leaves(&keypath);
+        let directory: PathBuf = keypath.components().iter().collect();
+        let mut transaction = Transaction::new(self);
+        for (key, value) in children {
+            let existing: Vec<PathBuf> = self
+                .extensions()
+                .iter()
+                .rev()
+                .map(|e| directory.join(&key).with_extension(e))
+                .filter(|path| self.is_file(path))
+                .collect();
+            let mut merged = None;
+            for path in &existing {
+                merge_into(&mut merged, self.read_value(path)?);
+                transaction.remove(path)?;
+            }
+            let merged = self.merge_by_depth(Some(value), merged);
+            let target = directory.join(&key).with_extension(self.write_extension());
+            transaction.write(&target, &merged.unwrap_or_default())?;
+        }
+        for path in &files {
+            transaction.remove(path)?;
+        }
+        self.commit_if_unchanged(transaction, &keypath, &before)
+    }
+
+    /// Merge the directory at `keypath` back into a single file.
+    ///
+    /// This is the inverse of [`split_file()`](Self::split_file). The directory `a/` is merged,
+    /// recursively, into `a.yaml`, or into `a.yml` if only that exists. Where a file and directory
+    /// contribute to the same keypath, whichever took precedence before still does.
+    /// Every data file that was merged is removed, along with any directories left empty.
+    /// Ignored files and files whose names can't be addressed by a keypath are left in place.
+    ///
+    /// The change is atomic and verified in the same way as [`split_file()`](Self::split_file).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::KeyPathError`] if `keypath` is invalid.
+    ///
+    /// Returns [`Error::KeyNotFound`] if there is no directory at `keypath`.
+    ///
+    /// Returns [`Error::ValueChanged`] if merging would change a resolved value.
+    ///
+    /// Returns [`Error::Walk`] if a file in the directory can't be read or parsed, and
+    /// [`Error::DataParseError`] or [`Error::IOError`] for the file being merged into.
+    pub fn merge_dir(&self, keypath: &str) -> Result<(), Error> {
+        let keypath = KeyPath::try_from(keypath)?;
+        let directory: PathBuf = keypath.components().iter().collect();
+        if !self.is_dir(&directory) {
+            return Err(Error::KeyNotFound);
+        }
+        let files = self.files_for(&keypath);
+        let value = self.merge_by_depth(
+            self.merge_files(&files)?,
+            Some(self.materialize_directory(&directory)?),
+        );
+        let mut merged = Vec::new();
+        self.collect_contributing_files(&directory, &mut merged)?;
+
+        let before = self.resolve_leaves(&keypath);
+        let target = files
+            .last()
+            .cloned()
+            .unwrap_or_else(|| directory.with_extension(self.write_extension()));
+        let mut transaction = Transaction::new(self);
+        transaction.write(&target, &value.unwrap_or_default())?;
+        for path in files.iter().chain(&merged).filter(|p| **p != target) {
+            transaction.remove(path)?;
+        }
+        self.commit_if_unchanged(transaction, &keypath, &before)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, path: &str, contents: &str) {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn split_and_merge_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.yaml", "b: {x: 1}\nc: [1, 2]\nd: 3");
+        write(dir.path(), "a/b.yml", "y: 2");
+        let datastore = Datastore::open(dir.path());
+        let a = KeyPath::try_from("a").unwrap();
+        let before = datastore.resolve_leaves(&a);
+        assert_eq!(before.len(), 5);
+
+        datastore.split_file("a").unwrap();
+        assert!(!dir.path().join("a.yaml").exists());
+        assert!(!dir.path().join("a/b.yml").exists());
+        assert!(dir.path().join("a/c.yaml").is_file());
+        assert_eq!(datastore.get::<u64>("a.b.y").unwrap(), 2);
+        assert_eq!(datastore.resolve_leaves(&a), before);
+
+        datastore.merge_dir("a").unwrap();
+        assert!(dir.path().join("a.yaml").is_file());
+        assert!(!dir.path().join("a").exists());
+        assert_eq!(datastore.resolve_leaves(&a), before);
+
+        // Only the files beneath `a` are checked, so a broken file elsewhere doesn't matter.
+        write(dir.path(), "z.yaml", "x: [");
+        datastore.split_file("a").unwrap();
+        datastore.merge_dir("a").unwrap();
+        assert_eq!(datastore.resolve_leaves(&a), before);
+    }
+
+    #[test]
+    fn split_rolls_back_on_change() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.yaml", "b: {hidden: 1}");
+        write(dir.path(), "a/b.yaml", "5");
+        let datastore = Datastore::open(dir.path());
+
+        let result = datastore.split_file("a").unwrap_err();
+        assert!(matches!(result, Error::ValueChanged(_)));
+        let a = std::fs::read_to_string(dir.path().join("a.yaml")).unwrap();
+        assert_eq!(a, "b: {hidden: 1}");
+        assert_eq!(datastore.get::<u64>("a.b.hidden").unwrap(), 1);
+    }
+
+    #[test]
+    fn split_rejects_non_mappings() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.yaml", "[1, 2]");
+        write(dir.path(), "b.yaml", "not.valid: 1");
+        let datastore = Datastore::open(dir.path());
+        assert!(matches!(
+            datastore.split_file("a").unwrap_err(),
+            Error::NotAMapping
+        ));
+        assert!(matches!(
+            datastore.split_file("b").unwrap_err(),
+            Error::KeyPathError(_)
+        ));
+        assert!(matches!(
+            datastore.merge_dir("a").unwrap_err(),
+            Error::KeyNotFound
+        ));
+    }
+}
@@ -0,0 +1,226 @@
+//! Confining file access to the datastore root.
+//!
+//! See [`Builder::sandbox`](crate::builder::Builder::sandbox) for details.
+use crate::{Datastore, Error};
+use std::{
+    fs::{DirEntry, Metadata},
+    path::{Component, Path, PathBuf},
+};
+
+impl Datastore {
+    /// Return the full path of `path`, relative to the root.
+    ///
+    /// When [sandboxed](crate::builder::Builder::sandbox), `path` must be relative without any
+    /// `..` components, and must not resolve through symlinks to anywhere outside the root.
+    /// Only the part of the path that exists is resolved, so a path that doesn't exist yet can
+    /// still be checked before it's written.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OutsideRoot`] if `path` escapes the root, and [`Error::IOError`] if the
+    /// root or an existing part of `path` can't be resolved.
+    pub(crate) fn confine(&self, path: &Path) -> Result<PathBuf, Error> {
+        let full_path = self.root.join(path);
+        if !self.settings.sandbox {
+            return Ok(full_path);
+        }
+        let outside = || Error::OutsideRoot(path.to_owned());
+        if !path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(outside());
+        }
+
+        let root = std::fs::canonicalize(&self.root)?;
+        let mut existing = full_path.as_path();
+        let resolved = loop {
+            match std::fs::canonicalize(existing) {
+                Ok(resolved) => break resolved,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound && existing != self.root => {
+                    existing = existing.parent().ok_or(e)?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        if resolved.starts_with(&root) {
+            Ok(full_path)
+        } else {
+            Err(outside())
+        }
+    }
+
+    /// Read the entries of the directory at `dir`, relative to the root, after
+    /// [confining](Self::confine) it.
+    pub(crate) fn read_directory(&self, dir: &Path) -> Result<Vec<DirEntry>, Error> {
+        Ok(std::fs::read_dir(self.confine(dir)?)?.collect::<Result<_, _>>()?)
+    }
+
+    /// Query the metadata of `path`, relative to the root, after [confining](Self::confine) it.
+    /// Symlinks are followed.
+    pub(crate) fn metadata(&self, path: &Path) -> Result<Metadata, Error> {
+        Ok(std::fs::metadata(self.confine(path)?)?)
+    }
+
+    /// Return whether the metadata of `path`, relative to the root, satisfies `predicate`.
+    ///
+    /// As with [`Path::exists`], other errors count as `false`. A path outside a sandboxed root
+    /// counts as `true`, so that whatever accesses it next reports [`Error::OutsideRoot`] rather
+    /// than it being silently skipped.
+    fn check_metadata(&self, path: &Path, predicate: fn(&Metadata) -> bool) -> bool {
+        match self.metadata(path) {
+            Ok(metadata) => predicate(&metadata),
+            Err(Error::OutsideRoot(_)) => true,
+            Err(_) => false,
+        }
+    }
+
+    /// Return whether `path`, relative to the root, exists.
+    pub(crate) fn path_exists(&self, path: &Path) -> bool {
+        self.check_metadata(path, |_| true)
+    }
+
+    /// Return whether `path`, relative to the root, is a file.
+    pub(crate) fn is_file(&self, path: &Path) -> bool {
+        self.check_metadata(path, Metadata::is_file)
+    }
+
+    /// Return whether `path`, relative to the root, is a directory.
+    pub(crate) fn is_dir(&self, path: &Path) -> bool {
+        self.check_metadata(path, Metadata::is_dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builder::IGNORE_PATH, cache::SIDECAR_DIR, manifest::MANIFEST_PATH};
+
+    fn write(dir: &Path, path: &str, contents: &str) {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn sandboxed_access() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "outside.yaml", "secret: 1");
+        write(dir.path(), "root/a.yaml", "x: 1");
+        let root = dir.path().join("root");
+        std::os::unix::fs::symlink(dir.path().join("outside.yaml"), root.join("link.yaml"))
+            .unwrap();
+        std::os::unix::fs::symlink(dir.path(), root.join("up")).unwrap();
+
+        let datastore = Datastore::builder(&root).sandbox(true).open();
+        assert_eq!(datastore.get_with_key::<_, i64>("a.yaml", "x").unwrap(), 1);
+        for path in [
+            PathBuf::from("../outside.yaml"),
+            dir.path().join("outside.yaml"),
+            PathBuf::from("link.yaml"),
+            PathBuf::from("up/outside.yaml"),
+        ] {
+            let result = datastore.get_with_key::<_, i64>(&path, "secret");
+            assert!(matches!(result, Err(Error::OutsideRoot(_))), "{path:?}");
+        }
+        assert!(matches!(
+            datastore.get::<i64>("link.secret"),
+            Err(Error::OutsideRoot(_))
+        ));
+        assert!(matches!(
+            datastore.set("up.outside.secret", &2),
+            Err(Error::OutsideRoot(_))
+        ));
+        let outside = std::fs::read_to_string(dir.path().join("outside.yaml")).unwrap();
+        assert_eq!(outside, "secret: 1");
+        datastore.set("b.c.x", &2).unwrap();
+        assert_eq!(datastore.get::<i64>("b.c.x").unwrap(), 2);
+
+        // Without the sandbox, paths are joined to the root as given.
+        let datastore = Datastore::open(&root);
+        assert_eq!(datastore.get::<i64>("link.secret").unwrap(), 1);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn sandboxed_listing_indexes_and_walks() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "secret/password.yaml", "password: hunter3");
+        write(dir.path(), "root/users/alice.yaml", "password: hunter2");
+        let root = dir.path().join("root");
+        std::os::unix::fs::symlink(dir.path().join("secret"), root.join("up")).unwrap();
+        std::os::unix::fs::symlink(
+            dir.path().join("secret/password.yaml"),
+            root.join("users/mallory.yaml"),
+        )
+        .unwrap();
+
+        let datastore = Datastore::builder(&root).sandbox(true).open();
+        assert!(matches!(datastore.list("up"), Err(Error::OutsideRoot(_))));
+        assert!(matches!(
+            datastore.create_index("pw", "users/*", "password"),
+            Err(Error::OutsideRoot(_))
+        ));
+        assert!(datastore.lookup("pw", "hunter3").unwrap().is_empty());
+        let errors: Vec<_> = datastore
+            .walk()
+            .filter_map(|item| item.err().map(|e| (e.path, e.error)))
+            .collect();
+        assert!(matches!(
+            errors.as_slice(),
+            [(up, Error::OutsideRoot(_)), (mallory, Error::OutsideRoot(_))]
+                if up == Path::new("up") && mallory == Path::new("users/mallory.yaml")
+        ));
+
+        // Without the sandbox, symlinks are followed.
+        let datastore = Datastore::open(&root);
+        assert_eq!(datastore.list("up").unwrap().len(), 1);
+        datastore.create_index("pw", "users/*", "password").unwrap();
+        assert_eq!(datastore.lookup("pw", "hunter3").unwrap().len(), 1);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn sandboxed_configuration() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "manifest.yaml", "extensions: [yml]");
+        write(dir.path(), "ignore", "a.yaml");
+        write(dir.path(), "root/a.yaml", "id: 1");
+        std::fs::create_dir(dir.path().join("sidecar")).unwrap();
+        let root = dir.path().join("root");
+        for (target, link) in [
+            ("manifest.yaml", MANIFEST_PATH),
+            ("ignore", IGNORE_PATH),
+            ("sidecar", SIDECAR_DIR),
+        ] {
+            std::os::unix::fs::symlink(dir.path().join(target), root.join(link)).unwrap();
+        }
+
+        let datastore = Datastore::builder(&root).sandbox(true).open();
+        assert!(matches!(
+            datastore.open_errors(),
+            [Error::OutsideRoot(_), Error::OutsideRoot(_)]
+        ));
+        assert_eq!(datastore.get::<i64>("a.id").unwrap(), 1);
+        datastore.create_index("by_id", "*", "id").unwrap();
+        assert!(matches!(
+            datastore.save_index_cache(),
+            Err(Error::OutsideRoot(_))
+        ));
+        let sidecar = std::fs::read_dir(dir.path().join("sidecar")).unwrap();
+        assert_eq!(sidecar.count(), 0);
+        assert!(matches!(
+            Datastore::builder(&root).sandbox(true).try_open(),
+            Err(Error::OutsideRoot(_))
+        ));
+
+        // Without the sandbox, the configuration is read and the cache written through symlinks.
+        let datastore = Datastore::open(&root);
+        assert!(datastore.open_errors().is_empty());
+        assert!(datastore.get::<i64>("a.id").is_err());
+        datastore.save_index_cache().unwrap();
+        let sidecar = std::fs::read_dir(dir.path().join("sidecar")).unwrap();
+        assert_eq!(sidecar.count(), 1);
+    }
+}
@@ -0,0 +1,1169 @@
+//! Validating data against [JSON Schema][00] documents attached to keypath patterns.
+//!
+//! Schemas are attached with [`Datastore::add_schema`] to a [pattern](KeyPathPattern) selecting
+//! the records they describe, in the same way as [indexes](crate::index). For example, a schema
+//! attached to `users/*` applies to each user, whether the users are stored as `users/alice.yaml`
+//! or as keys of `users.yaml`. [`Datastore::validate`] reports every violation, and writes can
+//! optionally be [checked](Datastore::enforce_schemas) so that invalid data is never written.
+//!
+//! A Rust type can be used as a schema instead, with [`Datastore::add_type_schema`]. Records then
+//! violate it if they can't be deserialized into the type, which is checked the same way. With
+//! the `schemars` feature, `Datastore::add_schema_for` instead generates a JSON Schema from a
+//! type deriving `schemars::JsonSchema`, so every violation within a record is reported rather
+//! than only the first.
+//!
+//! # Supported keywords
+//!
+//! Validation is built in and covers the commonly used keywords of draft 2020-12:
+//!
+//! * `type`, `enum` and `const`.
+//! * `properties`, `required`, `additionalProperties`, `minProperties` and `maxProperties`.
+//! * `items`, `prefixItems`, `minItems`, `maxItems` and `uniqueItems`.
+//! * `minimum`, `maximum`, `exclusiveMinimum`, `exclusiveMaximum` and `multipleOf`.
+//! * `minLength` and `maxLength`.
+//! * `allOf`, `anyOf`, `oneOf`, `not`, and `if` with `then` and `else`.
+//! * `$ref` to a location within the same schema, such as `#/$defs/address`.
+//!
+//! Annotations such as `title`, `description`, `default` and `$defs` are accepted but have no
+//! effect. [`Datastore::add_schema`] rejects schemas using any other keyword, such as `pattern`
+//! or `format`, rather than silently ignoring constraints it can't check.
+//!
+//! [00]: https://json-schema.org/
+use crate::{
+    Datastore, Error,
+    index::matching_records,
+    keypath::{KeyPath, KeyPathPattern},
+    value_at,
+};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{Map, Value as Json};
+use serde_yaml::{Value, value::from_value};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::{PoisonError, RwLockReadGuard},
+};
+
+/// How deeply `$ref`s may nest before a schema is assumed to be cyclic.
+const MAX_REF_DEPTH: usize = 64;
+
+/// A single way in which data doesn't match its schema, as returned by
+/// [`Datastore::validate`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Violation {
+    /// The file the invalid value is read from, relative to the root. For a value spread across a
+    /// directory, this is the directory.
+    pub path: PathBuf,
+    /// The keypath of the invalid value.
+    pub keypath: KeyPath,
+    /// A human-readable description of the violation.
+    pub message: String,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({}): {}",
+            self.keypath,
+            self.path.display(),
+            self.message
+        )
+    }
+}
+
+/// A schema that records can be validated against.
+#[derive(Debug)]
+enum Schema {
+    /// A JSON Schema document.
+    Json(Json),
+    /// A Rust type that records must deserialize into.
+    Type {
+        /// The name of the type, for messages.
+        name: &'static str,
+        /// Deserialize a record into the type, returning the error message if that fails.
+        check: fn(&Value) -> Result<(), String>,
+    },
+}
+
+/// Deserialize `value` into `T`, discarding the result.
+fn deserialize_as<T: DeserializeOwned>(value: &Value) -> Result<(), String> {
+    from_value::<T>(value.clone())
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// The schemas attached to a datastore.
+#[derive(Debug, Default)]
+pub(crate) struct Schemas {
+    /// Each pattern and the schema for the records it matches, in the order they were added.
+    rules: Vec<(KeyPathPattern, Schema)>,
+    /// Whether writes are checked against the schemas.
+    enforce: bool,
+}
+
+/// Keywords that validation checks.
+const VALIDATION_KEYWORDS: &[&str] = &[
+    "type",
+    "enum",
+    "const",
+    "properties",
+    "required",
+    "additionalProperties",
+    "minProperties",
+    "maxProperties",
+    "items",
+    "prefixItems",
+    "minItems",
+    "maxItems",
+    "uniqueItems",
+    "minimum",
+    "maximum",
+    "exclusiveMinimum",
+    "exclusiveMaximum",
+    "multipleOf",
+    "minLength",
+    "maxLength",
+    "allOf",
+    "anyOf",
+    "oneOf",
+    "not",
+    "if",
+    "then",
+    "else",
+    "$ref",
+];
+
+/// Keywords that don't affect validation, which are accepted and ignored.
+const ANNOTATION_KEYWORDS: &[&str] = &[
+    "$schema",
+    "$id",
+    "$comment",
+    "$defs",
+    "definitions",
+    "title",
+    "description",
+    "default",
+    "examples",
+    "deprecated",
+    "readOnly",
+    "writeOnly",
+];
+
+/// Escape `token` for use as a single reference token of a JSON Pointer.
+fn escape_pointer(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+/// Check that `schema`, found at the JSON Pointer `pointer` within its document, and every schema
+/// within it only use supported keywords, returning a message describing the first that doesn't.
+fn check_keywords(schema: &Json, pointer: &str) -> Result<(), String> {
+    let schema = match schema {
+        Json::Bool(_) => return Ok(()),
+        Json::Object(schema) => schema,
+        _ => {
+            let found = type_name(schema);
+            return Err(format!(
+                "expected an object or boolean at {pointer:?}, found {found}"
+            ));
+        }
+    };
+    for (keyword, value) in schema {
+        let pointer = format!("{pointer}/{}", escape_pointer(keyword));
+        if !VALIDATION_KEYWORDS.contains(&keyword.as_str())
+            && !ANNOTATION_KEYWORDS.contains(&keyword.as_str())
+        {
+            return Err(format!("unsupported keyword {keyword:?} at {pointer:?}"));
+        }
+        match (keyword.as_str(), value) {
+            ("additionalProperties" | "items" | "not" | "if" | "then" | "else", _) => {
+                check_keywords(value, &pointer)?;
+            }
+            ("properties" | "$defs" | "definitions", Json::Object(subschemas)) => {
+                for (name, subschema) in subschemas {
+                    check_keywords(subschema, &format!("{pointer}/{}", escape_pointer(name)))?;
+                }
+            }
+            ("prefixItems" | "allOf" | "anyOf" | "oneOf", Json::Array(subschemas)) => {
+                for (index, subschema) in subschemas.iter().enumerate() {
+                    check_keywords(subschema, &format!("{pointer}/{index}"))?;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Return the JSON Schema type name of `value`.
+fn type_name(value: &Json) -> &'static str {
+    match value {
+        Json::Null => "null",
+        Json::Bool(_) => "boolean",
+        Json::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Json::Number(_) => "number",
+        Json::String(_) => "string",
+        Json::Array(_) => "array",
+        Json::Object(_) => "object",
+    }
+}
+
+/// Return whether `value` is an instance of the JSON Schema type named `name`.
+fn has_type(value: &Json, name: &str) -> bool {
+    match (name, value) {
+        ("integer", Json::Number(n)) => n.as_f64().is_some_and(|n| n.fract() == 0.0),
+        ("number", Json::Number(_)) => true,
+        _ => type_name(value) == name,
+    }
+}
+
+/// Return whether two JSON values are equal, treating numbers of equal value as equal.
+fn json_equal(a: &Json, b: &Json) -> bool {
+    match (a, b) {
+        (Json::Number(a), Json::Number(b)) => a == b || a.as_f64() == b.as_f64(),
+        (Json::Array(a), Json::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| json_equal(a, b))
+        }
+        (Json::Object(a), Json::Object(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.get(key).is_some_and(|b| json_equal(a, b)))
+        }
+        _ => a == b,
+    }
+}
+
+/// A violation found within a record, located relative to the record.
+struct Failure {
+    /// Keys and sequence indexes leading from the record to the invalid value.
+    location: Vec<String>,
+    /// A human-readable description of the violation.
+    message: String,
+}
+
+/// Validates values against one schema document.
+struct Validator<'a> {
+    /// The whole schema document, which `$ref`s are resolved within.
+    document: &'a Json,
+}
+
+impl Validator<'_> {
+    /// Return whether `instance` is valid against `schema`.
+    fn is_valid(&self, schema: &Json, instance: &Json, depth: usize) -> bool {
+        let mut failures = Vec::new();
+        self.validate(schema, instance, &mut Vec::new(), depth, &mut failures);
+        failures.is_empty()
+    }
+
+    /// Validate `instance`, found at `location`, against `schema`, adding any violations to
+    /// `failures`.
+    fn validate(
+        &self,
+        schema: &Json,
+        instance: &Json,
+        location: &mut Vec<String>,
+        depth: usize,
+        failures: &mut Vec<Failure>,
+    ) {
+        let mut messages = Vec::new();
+        match schema {
+            Json::Bool(true) => {}
+            Json::Bool(false) => messages.push("no value is allowed here".to_owned()),
+            Json::Object(schema) => {
+                if let Some(Json::String(reference)) = schema.get("$ref") {
+                    let target = reference
+                        .strip_prefix('#')
+                        .and_then(|pointer| self.document.pointer(pointer));
+                    match target {
+                        _ if depth >= MAX_REF_DEPTH => {
+                            messages.push(format!("$ref {reference:?} is cyclic"));
+                        }
+                        Some(target) => {
+                            self.validate(target, instance, location, depth + 1, failures);
+                        }
+                        None => messages.push(format!("$ref {reference:?} can't be resolved")),
+                    }
+                }
+                Self::validate_value(schema, instance, &mut messages);
+                match instance {
+                    Json::Array(items) => {
+                        self.validate_items(schema, items, location, depth, failures);
+                    }
+                    Json::Object(object) => {
+                        self.validate_properties(schema, object, location, depth, failures);
+                    }
+                    _ => {}
+                }
+                self.validate_combinators(schema, instance, location, depth, failures);
+            }
+            _ => messages.push("schema is not an object or boolean".to_owned()),
+        }
+        failures.extend(messages.into_iter().map(|message| Failure {
+            location: location.clone(),
+            message,
+        }));
+    }
+
+    /// Check `instance` against the keywords of `schema` that don't involve other schemas,
+    /// adding a message for each violation to `messages`.
+    fn validate_value(schema: &Map<String, Json>, instance: &Json, messages: &mut Vec<String>) {
+        let mut fail = |message: String| messages.push(message);
+        match schema.get("type") {
+            Some(Json::String(name)) if !has_type(instance, name) => {
+                fail(format!("expected {name}, found {}", type_name(instance)));
+            }
+            Some(Json::Array(names))
+                if !names
+                    .iter()
+                    .any(|name| name.as_str().is_some_and(|name| has_type(instance, name))) =>
+            {
+                let names: Vec<&str> = names.iter().filter_map(Json::as_str).collect();
+                let expected = names.join(" or ");
+                fail(format!(
+                    "expected {expected}, found {}",
+                    type_name(instance)
+                ));
+            }
+            _ => {}
+        }
+        if let Some(Json::Array(allowed)) = schema.get("enum")
+            && !allowed.iter().any(|allowed| json_equal(allowed, instance))
+        {
+            fail("value is not one of the allowed values".to_owned());
+        }
+        if let Some(expected) = schema.get("const")
+            && !json_equal(expected, instance)
+        {
+            fail(format!("expected {expected}"));
+        }
+
+        let count = |keyword: &str| schema.get(keyword).and_then(Json::as_u64);
+        match instance {
+            Json::Number(number) => {
+                Self::validate_number(schema, number.as_f64().unwrap_or(f64::NAN), messages);
+            }
+            Json::String(string) => {
+                let length = string.chars().count() as u64;
+                if let Some(min) = count("minLength")
+                    && length < min
+                {
+                    fail(format!(
+                        "expected at least {min} characters, found {length}"
+                    ));
+                }
+                if let Some(max) = count("maxLength")
+                    && length > max
+                {
+                    fail(format!("expected at most {max} characters, found {length}"));
+                }
+            }
+            Json::Array(items) => {
+                let length = items.len() as u64;
+                if let Some(min) = count("minItems")
+                    && length < min
+                {
+                    fail(format!("expected at least {min} items, found {length}"));
+                }
+                if let Some(max) = count("maxItems")
+                    && length > max
+                {
+                    fail(format!("expected at most {max} items, found {length}"));
+                }
+                if schema.get("uniqueItems") == Some(&Json::Bool(true))
+                    && items
+                        .iter()
+                        .enumerate()
+                        .any(|(i, a)| items[i + 1..].iter().any(|b| json_equal(a, b)))
+                {
+                    fail("items must be unique".to_owned());
+                }
+            }
+            Json::Object(object) => {
+                let length = object.len() as u64;
+                if let Some(min) = count("minProperties")
+                    && length < min
+                {
+                    fail(format!(
+                        "expected at least {min} properties, found {length}"
+                    ));
+                }
+                if let Some(max) = count("maxProperties")
+                    && length > max
+                {
+                    fail(format!("expected at most {max} properties, found {length}"));
+                }
+                if let Some(Json::Array(required)) = schema.get("required") {
+                    for name in required.iter().filter_map(Json::as_str) {
+                        if !object.contains_key(name) {
+                            fail(format!("missing required property {name:?}"));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Check `number` against the numeric keywords of `schema`, adding a message for each
+    /// violation to `messages`.
+    fn validate_number(schema: &Map<String, Json>, number: f64, messages: &mut Vec<String>) {
+        let mut fail = |message: String| messages.push(message);
+        let bound = |keyword: &str| schema.get(keyword).and_then(Json::as_f64);
+        if let Some(minimum) = bound("minimum")
+            && number < minimum
+        {
+            fail(format!("value must be at least {minimum}"));
+        }
+        if let Some(maximum) = bound("maximum")
+            && number > maximum
+        {
+            fail(format!("value must be at most {maximum}"));
+        }
+        if let Some(minimum) = bound("exclusiveMinimum")
+            && number <= minimum
+        {
+            fail(format!("value must be greater than {minimum}"));
+        }
+        if let Some(maximum) = bound("exclusiveMaximum")
+            && number >= maximum
+        {
+            fail(format!("value must be less than {maximum}"));
+        }
+        if let Some(divisor) = bound("multipleOf")
+            && divisor > 0.0
+            && (number / divisor).fract() != 0.0
+        {
+            fail(format!("value must be a multiple of {divisor}"));
+        }
+    }
+
+    /// Validate each element of `items` against the schema `schema` gives for it.
+    fn validate_items(
+        &self,
+        schema: &Map<String, Json>,
+        items: &[Json],
+        location: &mut Vec<String>,
+        depth: usize,
+        failures: &mut Vec<Failure>,
+    ) {
+        let prefix = match schema.get("prefixItems") {
+            Some(Json::Array(prefix)) => prefix.as_slice(),
+            _ => &[],
+        };
+        for (index, item) in items.iter().enumerate() {
+            let Some(item_schema) = prefix.get(index).or_else(|| schema.get("items")) else {
+                continue;
+            };
+            location.push(index.to_string());
+            self.validate(item_schema, item, location, depth, failures);
+            location.pop();
+        }
+    }
+
+    /// Validate each property of `object` against the schema `schema` gives for it.
+    fn validate_properties(
+        &self,
+        schema: &Map<String, Json>,
+        object: &Map<String, Json>,
+        location: &mut Vec<String>,
+        depth: usize,
+        failures: &mut Vec<Failure>,
+    ) {
+        let properties = schema.get("properties").and_then(Json::as_object);
+        for (name, value) in object {
+            let property_schema = properties
+                .and_then(|properties| properties.get(name))
+                .or_else(|| schema.get("additionalProperties"));
+            let Some(property_schema) = property_schema else {
+                continue;
+            };
+            location.push(name.clone());
+            if *property_schema == Json::Bool(false) {
+                failures.push(Failure {
+                    location: location.clone(),
+                    message: format!("property {name:?} is not allowed"),
+                });
+            } else {
+                self.validate(property_schema, value, location, depth, failures);
+            }
+            location.pop();
+        }
+    }
+
+    /// Validate `instance` against the keywords of `schema` that combine other schemas.
+    fn validate_combinators(
+        &self,
+        schema: &Map<String, Json>,
+        instance: &Json,
+        location: &mut Vec<String>,
+        depth: usize,
+        failures: &mut Vec<Failure>,
+    ) {
+        let subschemas = |keyword: &str| match schema.get(keyword) {
+            Some(Json::Array(subschemas)) => subschemas.as_slice(),
+            _ => &[],
+        };
+        for subschema in subschemas("allOf") {
+            self.validate(subschema, instance, location, depth, failures);
+        }
+        if let Some(condition) = schema.get("if") {
+            let branch = if self.is_valid(condition, instance, depth) {
+                schema.get("then")
+            } else {
+                schema.get("else")
+            };
+            if let Some(branch) = branch {
+                self.validate(branch, instance, location, depth, failures);
+            }
+        }
+
+        let mut fail = |message: String| {
+            failures.push(Failure {
+                location: location.clone(),
+                message,
+            });
+        };
+        let any_of = subschemas("anyOf");
+        if !any_of.is_empty() && !any_of.iter().any(|s| self.is_valid(s, instance, depth)) {
+            fail("value doesn't match any of the schemas in anyOf".to_owned());
+        }
+        let one_of = subschemas("oneOf");
+        let matched = one_of
+            .iter()
+            .filter(|s| self.is_valid(s, instance, depth))
+            .count();
+        if !one_of.is_empty() && matched != 1 {
+            fail(format!(
+                "value matches {matched} of the schemas in oneOf, expected exactly one"
+            ));
+        }
+        if let Some(not) = schema.get("not")
+            && self.is_valid(not, instance, depth)
+        {
+            fail("value matches the schema in not".to_owned());
+        }
+    }
+}
+
+impl Datastore {
+    /// Acquire the schemas for reading. They are always left consistent, so poisoning is ignored.
+    fn read_schemas(&self) -> RwLockReadGuard<'_, Schemas> {
+        self.schemas.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Attach a JSON Schema to every record matched by `pattern`.
+    ///
+    /// Records are found as for [indexes](crate::index): `users/*` matches each user, wherever
+    /// it's stored. A record matched by several patterns must be valid against each of their
+    /// schemas. See the [module documentation](crate::schema) for the supported keywords.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use yaml_datastore::Datastore;
+    ///
+    /// let datastore = Datastore::open("tests/data");
+    /// let schema = serde_json::json!({"required": ["name", "id"]});
+    /// datastore.add_schema("complete", schema).unwrap();
+    /// assert!(datastore.validate().unwrap().is_empty());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::KeyPathError`] if `pattern` is invalid.
+    ///
+    /// Returns [`Error::InvalidSchema`] if `schema`, or a schema within it, is not an object or
+    /// boolean, or uses a keyword that isn't [supported](crate::schema#supported-keywords).
+    pub fn add_schema(&self, pattern: &str, schema: Json) -> Result<(), Error> {
+        let pattern = KeyPathPattern::try_from(pattern)?;
+        check_keywords(&schema, "").map_err(Error::InvalidSchema)?;
+        let mut schemas = self.schemas.write().unwrap_or_else(PoisonError::into_inner);
+        schemas.rules.push((pattern, Schema::Json(schema)));
+        Ok(())
+    }
+
+    /// Require every record matched by `pattern` to deserialize into the Rust type `T`.
+    ///
+    /// This treats the type callers [`get()`](Self::get) records as as their schema: a record
+    /// violates it if deserializing the record into `T` fails, such as when a field is missing or
+    /// has the wrong type. Like [JSON Schemas](Self::add_schema), type schemas are checked by
+    /// [`validate()`](Self::validate), which reports every record that doesn't deserialize
+    /// rather than stopping at the first, and can be [enforced](Self::enforce_schemas) on writes.
+    ///
+    /// Deserialization stops at the first problem within each record, so at most one violation
+    /// is reported per record, at the record's keypath.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use serde::Deserialize;
+    /// use yaml_datastore::Datastore;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Record {
+    ///     name: String,
+    ///     id: u32,
+    /// }
+    ///
+    /// let datastore = Datastore::open("tests/data");
+    /// datastore.add_type_schema::<Record>("complete").unwrap();
+    /// assert!(datastore.validate().unwrap().is_empty());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::KeyPathError`] if `pattern` is invalid.
+    pub fn add_type_schema<T: DeserializeOwned>(&self, pattern: &str) -> Result<(), Error> {
+        let pattern = KeyPathPattern::try_from(pattern)?;
+        let schema = Schema::Type {
+            name: std::any::type_name::<T>(),
+            check: deserialize_as::<T>,
+        };
+        let mut schemas = self.schemas.write().unwrap_or_else(PoisonError::into_inner);
+        schemas.rules.push((pattern, schema));
+        Ok(())
+    }
+
+    /// Attach a JSON Schema generated from the Rust type `T` to every record matched by `pattern`.
+    ///
+    /// The schema is generated by [`schemars`], describing what deserializes into `T`, and is
+    /// then used as with [`add_schema()`](Self::add_schema). Unlike
+    /// [`add_type_schema()`](Self::add_type_schema), which stops at the first field that fails to
+    /// deserialize, this reports every violation within a record, each at its own keypath.
+    ///
+    /// The `format` annotations `schemars` adds, such as `uint32`, are left out, since they aren't
+    /// [supported](crate::schema#supported-keywords). The ranges of integer types are still
+    /// checked through `minimum` and `maximum`. This requires the `schemars` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use schemars::JsonSchema;
+    /// use serde::Deserialize;
+    /// use yaml_datastore::Datastore;
+    ///
+    /// #[derive(Deserialize, JsonSchema)]
+    /// struct Record {
+    ///     name: String,
+    ///     id: u32,
+    /// }
+    ///
+    /// let datastore = Datastore::open("tests/data");
+    /// datastore.add_schema_for::<Record>("complete").unwrap();
+    /// assert!(datastore.validate().unwrap().is_empty());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::KeyPathError`] if `pattern` is invalid.
+    ///
+    /// Returns [`Error::InvalidSchema`] if the generated schema uses a keyword that isn't
+    /// supported, which can happen with custom [`JsonSchema`](schemars::JsonSchema)
+    /// implementations.
+    #[cfg(feature = "schemars")]
+    pub fn add_schema_for<T: schemars::JsonSchema>(&self, pattern: &str) -> Result<(), Error> {
+        let remove_format = |schema: &mut schemars::Schema| {
+            schema.remove("format");
+        };
+        let schema = schemars::generate::SchemaSettings::draft2020_12()
+            .for_deserialize()
+            .with_transform(schemars::transform::RecursiveTransform(remove_format))
+            .into_generator()
+            .into_root_schema_for::<T>();
+        self.add_schema(pattern, schema.to_value())
+    }
+
+    /// Attach the JSON Schema in the file at `path` to every record matched by `pattern`.
+    ///
+    /// A relative `path` is relative to the root. Files ending in `.json` are parsed as JSON, and
+    /// any others as YAML. See [`add_schema()`](Self::add_schema) for details.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IOError`] if the file can't be read, and [`Error::JsonError`] or
+    /// [`Error::DataParseError`] if it can't be parsed. Like a data file, it's read within the
+    /// datastore's [sandbox](crate::builder::Builder::sandbox) and
+    /// [limits](crate::builder::Builder::limits), returning [`Error::OutsideRoot`] or
+    /// [`Error::LimitExceeded`] otherwise.
+    ///
+    /// Returns the same errors as [`add_schema()`](Self::add_schema) otherwise.
+    pub fn add_schema_file<P: AsRef<Path>>(&self, pattern: &str, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let contents = self.read_data_file(path)?;
+        let schema = if path.extension().is_some_and(|e| e == "json") {
+            serde_json::from_str(&contents)?
+        } else {
+            serde_yaml::from_str(&contents)?
+        };
+        self.add_schema(pattern, schema)
+    }
+
+    /// Set whether writes are checked against the schemas. Off by default.
+    ///
+    /// When on, every method that changes files, such as [`apply_patch()`](Self::apply_patch),
+    /// [`import_flat()`](Self::import_flat) and [`import()`](Self::import), validates the
+    /// records its changes affect before finishing. If any is invalid, every change is undone and
+    /// [`Error::SchemaViolation`] is returned.
+    ///
+    /// A record is affected if a changed file contributes to it, so every record in a changed file
+    /// is checked, not only the values that changed. Records in other files aren't checked, so
+    /// existing violations there don't block writes.
+    pub fn enforce_schemas(&self, enforce: bool) {
+        self.schemas
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .enforce = enforce;
+    }
+
+    /// Return the file or directory that the value at `keypath` is read from.
+    fn source_of(&self, keypath: &KeyPath) -> PathBuf {
+        let directory: PathBuf = keypath.components().iter().collect();
+        if self.is_dir(&directory) {
+            return directory;
+        }
+        self.candidates(keypath)
+            .find(|(path, keys)| self.try_get(path, keys).is_ok_and(|value| value.is_some()))
+            .map_or(directory, |(path, _)| path)
+    }
+
+    /// Validate `value` against the JSON Schema `schema`.
+    fn validate_json(schema: &Json, value: &Value) -> Vec<Failure> {
+        match serde_json::to_value(value) {
+            Ok(instance) => {
+                let mut failures = Vec::new();
+                let validator = Validator { document: schema };
+                validator.validate(schema, &instance, &mut Vec::new(), 0, &mut failures);
+                failures
+            }
+            Err(e) => vec![Failure {
+                location: Vec::new(),
+                message: format!("value can't be represented as JSON: {e}"),
+            }],
+        }
+    }
+
+    /// Validate each record matched by `schemas` at or beneath `keypath`, whose value is `value`,
+    /// adding any violations to `violations`.
+    ///
+    /// Only records for which `affected` returns `true` are validated.
+    fn validate_within(
+        &self,
+        schemas: &Schemas,
+        keypath: &KeyPath,
+        value: &Value,
+        affected: &dyn Fn(&KeyPath) -> bool,
+        violations: &mut Vec<Violation>,
+    ) {
+        for (pattern, schema) in &schemas.rules {
+            let mut records = Vec::new();
+            matching_records(pattern, keypath, value, &mut records);
+            for (record, value) in records {
+                if !affected(&record) {
+                    continue;
+                }
+                let failures = match schema {
+                    Schema::Json(schema) => Self::validate_json(schema, value),
+                    Schema::Type { name, check } => match check(value) {
+                        Ok(()) => Vec::new(),
+                        Err(e) => vec![Failure {
+                            location: Vec::new(),
+                            message: format!("value doesn't deserialize as {name}: {e}"),
+                        }],
+                    },
+                };
+                for failure in failures {
+                    // Keys that aren't valid keypath components are reported at their parent.
+                    let mut keypath = record.clone();
+                    for component in &failure.location {
+                        match keypath.join(component) {
+                            Ok(joined) => keypath = joined,
+                            Err(_) => break,
+                        }
+                    }
+                    violations.push(Violation {
+                        path: self.source_of(&keypath),
+                        keypath,
+                        message: failure.message,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Validate every record matched by a [schema](Self::add_schema), returning every violation.
+    ///
+    /// Records are validated as [`get()`](Self::get) resolves them, with values from all the
+    /// files and directories that contribute to them merged together. Each violation names the
+    /// keypath of the invalid value and the file it's read from. Violations are sorted by keypath,
+    /// and [`Violation`] can be serialized for machine-readable output.
+    ///
+    /// A file that can't be read or parsed is reported as a violation at the top-level keypath
+    /// containing it, since none of the records beneath that keypath can be validated. Ignored
+    /// files and files whose names aren't valid keypath components are skipped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use yaml_datastore::Datastore;
+    ///
+    /// let datastore = Datastore::open("tests/data");
+    /// let schema = serde_json::json!({"properties": {"id": {"minimum": 2}}});
+    /// datastore.add_schema("complete", schema).unwrap();
+    /// let violations = datastore.validate().unwrap();
+    /// assert_eq!(violations[0].keypath.to_string(), "complete.id");
+    /// assert_eq!(violations[0].message, "value must be at least 2");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::IOError`] if the datastore's directories can't be listed.
+    pub fn validate(&self) -> Result<Vec<Violation>, Error> {
+        let schemas = self.read_schemas();
+        let mut files = Vec::new();
+        self.collect_contributing_files(Path::new(""), &mut files)?;
+        let tops: BTreeSet<KeyPath> = files
+            .iter()
+            .filter_map(|path| KeyPath::from_path(path).ok())
+            .filter_map(|keypath| KeyPath::try_from(keypath.components()[0]).ok())
+            .collect();
+
+        let mut violations = Vec::new();
+        for top in tops {
+            let applies = |(pattern, _): &(KeyPathPattern, Schema)| {
+                pattern.matches(&top) || pattern.could_match_within(&top)
+            };
+            if !schemas.rules.iter().any(applies) {
+                continue;
+            }
+            match self.materialize(Some(&top)) {
+                Ok(value) => {
+                    self.validate_within(&schemas, &top, &value, &|_| true, &mut violations);
+                }
+                Err(Error::Walk(e)) => {
+                    let message = match e.error {
+                        Error::DataParseError(e) => format!("file can't be parsed: {e}"),
+                        e => format!("file can't be read: {e}"),
+                    };
+                    violations.push(Violation {
+                        path: e.path,
+                        keypath: top,
+                        message,
+                    });
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        violations.sort_by(|a, b| a.keypath.cmp(&b.keypath));
+        Ok(violations)
+    }
+
+    /// Return whether the data file at `path`, whose keypath is `file`, contributes to `record`
+    /// now or did before it was changed from `original`.
+    fn contributes(
+        &self,
+        path: &Path,
+        file: &KeyPath,
+        original: Option<&str>,
+        record: &KeyPath,
+    ) -> bool {
+        if file.starts_with(record) {
+            return true;
+        }
+        if !record.starts_with(file) {
+            return false;
+        }
+        let keys = &record.components()[file.components().len()..];
+        self.try_get(path, keys).is_ok_and(|value| value.is_some())
+            || original
+                .and_then(|contents| serde_yaml::from_str(contents).ok())
+                .and_then(|value| value_at(value, keys))
+                .is_some()
+    }
+
+    /// Validate the records affected by changes to data files, if schemas are
+    /// [enforced](Self::enforce_schemas).
+    ///
+    /// `originals` maps each changed file to its contents before the change, or `None` if it
+    /// didn't exist. A record is affected if one of the files contributes to it, before or after
+    /// the change.
+    pub(crate) fn check_schemas(
+        &self,
+        originals: &BTreeMap<PathBuf, Option<String>>,
+    ) -> Result<(), Error> {
+        let schemas = self.read_schemas();
+        if !schemas.enforce || schemas.rules.is_empty() {
+            return Ok(());
+        }
+        let changed: Vec<(&PathBuf, KeyPath, Option<&str>)> = originals
+            .iter()
+            .filter_map(|(path, original)| {
+                let keypath = KeyPath::from_path(path).ok()?;
+                Some((path, keypath, original.as_deref()))
+            })
+            .collect();
+        let affected = |record: &KeyPath| {
+            changed
+                .iter()
+                .any(|(path, file, original)| self.contributes(path, file, *original, record))
+        };
+        let tops: BTreeSet<&str> = changed.iter().map(|(_, k, _)| k.components()[0]).collect();
+        let mut violations = Vec::new();
+        for top in tops {
+            let keypath = KeyPath::try_from(top)?;
+            let value = match self.materialize(Some(&keypath)) {
+                Ok(value) => value,
+                Err(Error::KeyNotFound) => continue,
+                Err(e) => return Err(e),
+            };
+            self.validate_within(&schemas, &keypath, &value, &affected, &mut violations);
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            violations.sort_by(|a, b| a.keypath.cmp(&b.keypath));
+            Err(Error::SchemaViolation(violations))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn write(dir: &Path, path: &str, contents: &str) {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    fn failures(schema: &Json, instance: &Json) -> Vec<String> {
+        let mut failures = Vec::new();
+        let validator = Validator { document: schema };
+        validator.validate(schema, instance, &mut Vec::new(), 0, &mut failures);
+        let mut failures: Vec<String> = failures
+            .into_iter()
+            .map(|f| format!("{}: {}", f.location.join("."), f.message))
+            .collect();
+        failures.sort();
+        failures
+    }
+
+    #[test]
+    fn validate_keywords() {
+        let schema = json!({
+            "$defs": {"port": {"type": "integer", "minimum": 1, "maximum": 65535}},
+            "type": "object",
+            "required": ["name", "port"],
+            "properties": {
+                "name": {"type": "string", "minLength": 1},
+                "port": {"$ref": "#/$defs/port"},
+                "tags": {"type": "array", "items": {"enum": ["a", "b"]}, "uniqueItems": true},
+                "mode": {"oneOf": [{"const": "fast"}, {"const": "safe"}]},
+            },
+            "additionalProperties": false,
+        });
+        let valid = json!({"name": "x", "port": 80, "tags": ["a", "b"], "mode": "fast"});
+        assert!(failures(&schema, &valid).is_empty());
+
+        let invalid = json!({"name": "", "port": 70000.0, "tags": ["a", "c", "a"], "extra": 1});
+        assert_eq!(
+            failures(&schema, &invalid),
+            vec![
+                "extra: property \"extra\" is not allowed",
+                "name: expected at least 1 characters, found 0",
+                "port: value must be at most 65535",
+                "tags.1: value is not one of the allowed values",
+                "tags: items must be unique",
+            ]
+        );
+        assert_eq!(
+            failures(&schema, &json!([])),
+            vec![": expected object, found array"]
+        );
+    }
+
+    #[test]
+    fn reject_unsupported_keywords() {
+        let datastore = Datastore::open("tests/data");
+        for (schema, message) in [
+            (
+                json!({"type": "string", "pattern": "^a"}),
+                r#"unsupported keyword "pattern" at "/pattern""#,
+            ),
+            (
+                json!({"properties": {"a/b": {"format": "email"}}}),
+                r#"unsupported keyword "format" at "/properties/a~1b/format""#,
+            ),
+            (
+                json!({"anyOf": [true, {"patternProperties": {}}]}),
+                r#"unsupported keyword "patternProperties" at "/anyOf/1/patternProperties""#,
+            ),
+            (
+                json!({"$defs": {"x": {"dependentRequired": {}}}}),
+                r#"unsupported keyword "dependentRequired" at "/$defs/x/dependentRequired""#,
+            ),
+            (
+                json!({"items": 1}),
+                r#"expected an object or boolean at "/items", found integer"#,
+            ),
+            (
+                json!([]),
+                r#"expected an object or boolean at "", found array"#,
+            ),
+        ] {
+            match datastore.add_schema("complete", schema) {
+                Err(Error::InvalidSchema(found)) => assert_eq!(found, message),
+                result => panic!("expected InvalidSchema, found {result:?}"),
+            }
+        }
+        let annotated =
+            json!({"$schema": "https://json-schema.org/draft/2020-12/schema", "title": "x"});
+        datastore.add_schema("complete", annotated).unwrap();
+    }
+
+    #[test]
+    fn validate_records() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "users.yaml",
+            "alice: {email: a@example.com}\nbob: {}",
+        );
+        write(dir.path(), "users/carol.yaml", "email: 3");
+        write(dir.path(), "broken.yaml", "key: [unclosed");
+        write(
+            dir.path(),
+            "schemas/user.json",
+            r#"{"required": ["email"], "properties": {"email": {"type": "string"}}}"#,
+        );
+        let datastore = Datastore::open(dir.path());
+        datastore
+            .add_schema_file("users/*", "schemas/user.json")
+            .unwrap();
+
+        let violations = datastore.validate().unwrap();
+        let found: Vec<(String, &Path)> = violations
+            .iter()
+            .map(|v| (v.keypath.to_string(), v.path.as_path()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("users.bob".to_owned(), Path::new("users.yaml")),
+                (
+                    "users.carol.email".to_owned(),
+                    Path::new("users/carol.yaml")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_types() {
+        #[derive(serde::Deserialize)]
+        #[allow(dead_code)]
+        struct User {
+            email: String,
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "users.yaml",
+            "alice: {email: a@example.com}\nbob: {name: b}",
+        );
+        write(dir.path(), "users/carol.yaml", "email: [c]");
+        let datastore = Datastore::open(dir.path());
+        datastore.add_type_schema::<User>("users/*").unwrap();
+
+        let violations = datastore.validate().unwrap();
+        let keypaths: Vec<String> = violations.iter().map(|v| v.keypath.to_string()).collect();
+        assert_eq!(keypaths, vec!["users.bob", "users.carol"]);
+        assert!(violations[0].message.ends_with("missing field `email`"));
+    }
+
+    #[cfg(feature = "schemars")]
+    #[test]
+    fn validate_generated_schema() {
+        #[derive(serde::Deserialize, schemars::JsonSchema)]
+        #[allow(dead_code)]
+        struct Address {
+            city: String,
+        }
+
+        #[derive(serde::Deserialize, schemars::JsonSchema)]
+        #[allow(dead_code)]
+        struct User {
+            age: u8,
+            email: Option<String>,
+            address: Address,
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "users/alice.yaml",
+            "age: 30\naddress: {city: Paris}",
+        );
+        write(
+            dir.path(),
+            "users/bob.yaml",
+            "age: 300\nemail: 1\naddress: {city: [x]}",
+        );
+        let datastore = Datastore::open(dir.path());
+        datastore.add_schema_for::<User>("users/*").unwrap();
+
+        // Every problem with Bob is reported, not only the first.
+        let found: Vec<String> = datastore
+            .validate()
+            .unwrap()
+            .iter()
+            .map(|v| format!("{}: {}", v.keypath, v.message))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                "users.bob.address.city: expected string, found array",
+                "users.bob.age: value must be at most 255",
+                "users.bob.email: expected string or null, found integer",
+            ]
+        );
+    }
+
+    #[test]
+    fn enforce_on_write() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "users.yaml", "alice: {age: 1}");
+        write(dir.path(), "users/bob.yaml", "age: -1");
+        let datastore = Datastore::open(dir.path());
+        let schema = json!({"properties": {"age": {"minimum": 0}}});
+        datastore.add_schema("users.*", schema).unwrap();
+        datastore.enforce_schemas(true);
+
+        // Bob's existing violation doesn't block changes to Alice.
+        datastore.set("users.alice.age", &2).unwrap();
+        let error = datastore.set("users.alice.age", &-2).unwrap_err();
+        assert!(matches!(error, Error::SchemaViolation(ref v) if v.len() == 1));
+        assert_eq!(datastore.get::<i64>("users.alice.age").unwrap(), 2);
+
+        // Writes that create new files are undone too.
+        assert!(datastore.set("users.carol", &json!({"age": -3})).is_err());
+        assert!(!datastore.exists("users.carol").unwrap());
+
+        // So are flat imports and documents imported into new directories.
+        let flat = [("users.dave.age".to_owned(), Value::from(-4))].into();
+        let error = datastore
+            .import_flat(&flat, crate::flat::Layout::Existing(1))
+            .unwrap_err();
+        assert!(matches!(error, Error::SchemaViolation(_)));
+        assert!(!datastore.exists("users.dave").unwrap());
+        let document = "users: {erin: {age: -5}}";
+        let error = datastore
+            .import(document.as_bytes(), crate::document::Format::Yaml, 3)
+            .unwrap_err();
+        assert!(matches!(error, Error::SchemaViolation(_)));
+        assert!(!dir.path().join("users/erin").exists());
+    }
+}
@@ -0,0 +1,103 @@
+//! Per-datastore settings that change how files are found and resolved.
+use crate::{
+    Datastore,
+    document::merge_into,
+    ignore::IgnoreRules,
+    keypath::{DEFAULT_EXTENSIONS, KeyPath},
+    limits::Limits,
+    manifest::{MANIFEST_PATH, Resolution},
+};
+use serde_yaml::Value;
+use std::{
+    cmp::Reverse,
+    path::{Path, PathBuf},
+};
+
+/// Settings that change which files make up a datastore, configured by a
+/// [manifest](crate::manifest).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Settings {
+    /// Extensions of data files, highest precedence first. New files use the first.
+    pub(crate) extensions: Vec<String>,
+    /// Whether deeper or shallower files are tried first.
+    pub(crate) resolution: Resolution,
+    /// Rules for files and directories that aren't part of the datastore.
+    pub(crate) ignore: IgnoreRules,
+    /// Whether file access is [confined](Datastore::confine) to the root.
+    pub(crate) sandbox: bool,
+    /// Limits on the data files that are parsed.
+    pub(crate) limits: Limits,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            extensions: DEFAULT_EXTENSIONS.iter().map(|&e| e.to_owned()).collect(),
+            resolution: Resolution::default(),
+            ignore: IgnoreRules::default(),
+            sandbox: false,
+            limits: Limits::default(),
+        }
+    }
+}
+
+impl Datastore {
+    /// The extensions of data files, highest precedence first.
+    pub(crate) fn extensions(&self) -> &[String] {
+        &self.settings.extensions
+    }
+
+    /// The extension given to data files the datastore creates.
+    pub(crate) fn write_extension(&self) -> &str {
+        &self.settings.extensions[0]
+    }
+
+    /// Return whether `path` has one of the datastore's extensions.
+    pub(crate) fn has_data_extension(&self, path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|e| self.extensions().iter().any(|d| e == d.as_str()))
+    }
+
+    /// Return whether `path`, relative to the root, is excluded from the datastore by its ignore
+    /// rules. The manifest itself is never part of the datastore.
+    pub(crate) fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        path == Path::new(MANIFEST_PATH) || self.settings.ignore.is_ignored(path, is_dir)
+    }
+
+    /// Return the candidate files and keys for resolving `keypath`, highest precedence first.
+    ///
+    /// This is [`KeyPath::iter`] with the datastore's extensions, skipping ignored files, and with
+    /// shallower files first if the [resolution order](Resolution) says so.
+    pub(crate) fn candidates<'a>(
+        &'a self,
+        keypath: &'a KeyPath,
+    ) -> impl DoubleEndedIterator<Item = (PathBuf, Vec<&'a str>)> + 'a {
+        let mut candidates: Vec<_> = keypath
+            .iter_extensions(self.extensions())
+            .filter(|(path, _)| !self.is_ignored(path, false))
+            .collect();
+        if self.settings.resolution == Resolution::ShallowestFirst {
+            // The sort is stable, so extensions keep their order within each depth.
+            candidates.sort_by_key(|(_, keys)| Reverse(keys.len()));
+        }
+        candidates.into_iter()
+    }
+
+    /// Merge the values that a file or directory and a deeper one beneath it contribute to the
+    /// same keypath, with the one the [resolution order](Resolution) tries first taking
+    /// precedence.
+    pub(crate) fn merge_by_depth(
+        &self,
+        shallower: Option<Value>,
+        deeper: Option<Value>,
+    ) -> Option<Value> {
+        let (mut base, overlay) = match self.settings.resolution {
+            Resolution::DeepestFirst => (shallower, deeper),
+            Resolution::ShallowestFirst => (deeper, shallower),
+        };
+        if let Some(overlay) = overlay {
+            merge_into(&mut base, overlay);
+        }
+        base
+    }
+}
@@ -0,0 +1,203 @@
+//! Receiving typed updates whenever the value at a keypath changes.
+//!
+//! See [`Datastore::subscribe`] for details. This module requires the `watch` feature.
+use crate::{
+    Datastore, Error,
+    keypath::KeyPath,
+    watch::{PollThread, Watch},
+};
+use serde::de::DeserializeOwned;
+use serde_yaml::{Value, value::from_value};
+use std::{
+    sync::{
+        Arc,
+        mpsc::{self, Receiver},
+    },
+    time::Duration,
+};
+
+/// What a keypath last resolved to, for deciding whether it changed.
+#[derive(PartialEq)]
+enum Resolved {
+    /// The keypath resolved to this value.
+    Value(Value),
+    /// Resolving failed with this error message.
+    Error(String),
+}
+
+impl From<&Result<Value, Error>> for Resolved {
+    fn from(result: &Result<Value, Error>) -> Self {
+        match result {
+            Ok(value) => Self::Value(value.clone()),
+            Err(e) => Self::Error(e.to_string()),
+        }
+    }
+}
+
+/// Receives the value at a keypath each time it changes, created by [`Datastore::subscribe`].
+///
+/// Watching stops when this is dropped.
+pub struct Subscription<T> {
+    /// Updates sent by the polling thread.
+    updates: Receiver<Result<T, Error>>,
+    /// The polling thread, stopped when dropped.
+    _thread: PollThread,
+}
+
+impl<T> std::fmt::Debug for Subscription<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Subscription").finish_non_exhaustive()
+    }
+}
+
+impl<T> Subscription<T> {
+    /// Wait for the next update.
+    ///
+    /// Returns `None` only if the polling thread has stopped, which happens if it panics.
+    #[must_use]
+    pub fn recv(&self) -> Option<Result<T, Error>> {
+        self.updates.recv().ok()
+    }
+
+    /// Wait up to `timeout` for the next update, returning `None` if there is none.
+    #[must_use]
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Result<T, Error>> {
+        self.updates.recv_timeout(timeout).ok()
+    }
+
+    /// Return the next update if one is ready, without waiting.
+    #[must_use]
+    pub fn try_recv(&self) -> Option<Result<T, Error>> {
+        self.updates.try_recv().ok()
+    }
+}
+
+impl Watch {
+    /// Start watching, returning a [`Subscription`] to the value at `keypath`.
+    ///
+    /// See [`Datastore::subscribe`] for details.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::KeyPathError`] if `keypath` is invalid.
+    pub fn subscribe<T>(self, keypath: &str) -> Result<Subscription<T>, Error>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        let keypath = KeyPath::try_from(keypath)?;
+        let datastore = Arc::clone(self.datastore());
+        let mut last = Resolved::from(&datastore.resolve_checked(&keypath));
+        let (sender, updates) = mpsc::channel();
+        let thread = PollThread::spawn(Arc::clone(&datastore), &self, move |event| {
+            if !event.affects(&keypath) {
+                return true;
+            }
+            let result = datastore.resolve_checked(&keypath);
+            let resolved = Resolved::from(&result);
+            if resolved == last {
+                return true;
+            }
+            last = resolved;
+            let update = result.and_then(|value| Ok(from_value(value)?));
+            sender.send(update).is_ok()
+        });
+        Ok(Subscription {
+            updates,
+            _thread: thread,
+        })
+    }
+}
+
+impl Datastore {
+    /// Subscribe to the value at `keypath`, receiving it each time it changes.
+    ///
+    /// The keypath is resolved as in [`get_value()`](Datastore::get_value) whenever a file that may
+    /// contribute to it [changes](Datastore::watch), and the new value is deserialized and sent
+    /// only if it differs from the last one. This includes the keypath resolving through a different file, such as when a
+    /// file shadowing the old value is created, but not a file being rewritten with the same value.
+    ///
+    /// An error is sent when the keypath stops resolving to a value of type `T`: if it no longer
+    /// exists, if the value can't be deserialized into `T`, or if a file it would be resolved
+    /// through can't be parsed. Unlike [`get_value()`](Datastore::get_value), such a file isn't
+    /// skipped, so a half-written file doesn't briefly resolve to a value elsewhere. The next successful update
+    /// is sent once the problem is fixed.
+    ///
+    /// Nothing is sent for the value at the time of subscribing. To get it without missing an
+    /// update, call [`get()`](Datastore::get) after subscribing. Files are polled with the default
+    /// settings; use [`watch()`](Datastore::watch) and [`Watch::subscribe`] to configure them.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::{collections::HashMap, sync::Arc};
+    /// use yaml_datastore::Datastore;
+    ///
+    /// let datastore = Arc::new(Datastore::open("config"));
+    /// let limits = datastore.subscribe::<HashMap<String, u64>>("service.limits").unwrap();
+    /// while let Some(update) = limits.recv() {
+    ///     match update {
+    ///         Ok(limits) => println!("new limits: {limits:?}"),
+    ///         Err(e) => eprintln!("limits are invalid: {e}"),
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::KeyPathError`] if `keypath` is invalid.
+    pub fn subscribe<T>(self: &Arc<Self>, keypath: &str) -> Result<Subscription<T>, Error>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        self.watch().subscribe(keypath)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn write(dir: &Path, path: &str, contents: &str) {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn subscribe_updates() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "service.yaml", "limits: {max: 1}\nname: a");
+        let datastore = Arc::new(Datastore::open(dir.path()));
+        let limits = datastore
+            .watch()
+            .poll_interval(Duration::from_millis(10))
+            .debounce(Duration::from_millis(30))
+            .subscribe::<u64>("service.limits.max")
+            .unwrap();
+
+        // Unrelated changes aren't sent.
+        write(dir.path(), "service.yaml", "limits: {max: 1}\nname: b");
+        assert!(limits.recv_timeout(Duration::from_millis(200)).is_none());
+
+        write(dir.path(), "service.yaml", "limits: {max: 2}\nname: b");
+        assert_eq!(limits.recv_timeout(TIMEOUT).unwrap().unwrap(), 2);
+
+        // Resolution moving to a shadowing file is a change.
+        write(dir.path(), "service/limits.yaml", "max: 3");
+        assert_eq!(limits.recv_timeout(TIMEOUT).unwrap().unwrap(), 3);
+
+        write(dir.path(), "service/limits.yaml", "max: [unclosed");
+        let error = limits.recv_timeout(TIMEOUT).unwrap().unwrap_err();
+        assert!(matches!(error, Error::Walk(e) if e.path == Path::new("service/limits.yaml")));
+
+        write(dir.path(), "service/limits.yaml", "max: 4");
+        assert_eq!(limits.recv_timeout(TIMEOUT).unwrap().unwrap(), 4);
+
+        write(dir.path(), "service/limits.yaml", "max: four");
+        let error = limits.recv_timeout(TIMEOUT).unwrap().unwrap_err();
+        assert!(matches!(error, Error::DataParseError(_)));
+    }
+}
@@ -0,0 +1,213 @@
+//! All-or-nothing changes spanning several files.
+//!
+//! A [`Transaction`] remembers the original contents of every file it touches, so that a change
+//! spanning several files can be undone if any step of it fails.
+use crate::{Datastore, Error};
+use serde_yaml::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+/// A set of file changes that can be rolled back.
+///
+/// Changes are applied to the filesystem immediately. If the transaction is dropped without being
+/// [committed](Transaction::commit), every touched file is restored to its original contents, and
+/// any directories that were created for new files are removed if they are empty again. When it
+/// is committed, directories left empty by removed files are removed instead.
+pub(crate) struct Transaction<'a> {
+    /// The datastore being changed.
+    datastore: &'a Datastore,
+    /// Original contents of each touched file, or `None` if it didn't exist.
+    originals: BTreeMap<PathBuf, Option<String>>,
+    /// Directories that didn't exist before the transaction, deepest last.
+    created_directories: Vec<PathBuf>,
+    /// Whether the transaction has been committed.
+    committed: bool,
+}
+
+impl<'a> Transaction<'a> {
+    /// Start a new transaction on `datastore`.
+    pub(crate) fn new(datastore: &'a Datastore) -> Self {
+        Self {
+            datastore,
+            originals: BTreeMap::new(),
+            created_directories: Vec::new(),
+            committed: false,
+        }
+    }
+
+    /// Record the original state of the file at `path`, if it isn't recorded already.
+    ///
+    /// A file too large for the datastore's [limits](crate::limits::Limits) is refused rather than
+    /// held in memory.
+    fn remember(&mut self, path: &Path) -> Result<(), Error> {
+        if self.originals.contains_key(path) {
+            return Ok(());
+        }
+        let original = match self.datastore.read_sized_file(path) {
+            Ok(contents) => Some(contents),
+            Err(Error::IOError(e)) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        self.originals.insert(path.to_owned(), original);
+        if let Some(parent) = path.parent() {
+            self.remember_directories(parent);
+        }
+        Ok(())
+    }
+
+    /// Record which of `directory` and its ancestors don't exist yet, so they're removed again if
+    /// the transaction is rolled back.
+    fn remember_directories(&mut self, directory: &Path) {
+        let mut missing: Vec<PathBuf> = directory
+            .ancestors()
+            .filter(|a| !a.as_os_str().is_empty() && !self.datastore.path_exists(a))
+            .map(Path::to_owned)
+            .collect();
+        missing.reverse();
+        self.created_directories.extend(missing);
+    }
+
+    /// Write `value` to the data file at `path`.
+    pub(crate) fn write(&mut self, path: &Path, value: &Value) -> Result<(), Error> {
+        self.remember(path)?;
+        self.datastore.write_value(path, value)
+    }
+
+    /// Write `contents` to the data file at `path`.
+    pub(crate) fn write_contents(&mut self, path: &Path, contents: &str) -> Result<(), Error> {
+        self.remember(path)?;
+        self.datastore.write_contents(path, contents)
+    }
+
+    /// Create the directory at `path`, along with any missing parents.
+    pub(crate) fn create_directory(&mut self, path: &Path) -> Result<(), Error> {
+        let full_path = self.datastore.confine(path)?;
+        self.remember_directories(path);
+        std::fs::create_dir_all(full_path)?;
+        Ok(())
+    }
+
+    /// Remove the data file at `path`.
+    pub(crate) fn remove(&mut self, path: &Path) -> Result<(), Error> {
+        self.remember(path)?;
+        self.datastore.remove_data_file(path)
+    }
+
+    /// Keep every change made in this transaction.
+    ///
+    /// If schemas are [enforced](Datastore::enforce_schemas), the records affected by the changes
+    /// are validated first, and every change is undone if any is invalid. Directories that
+    /// contained a removed file are removed if they are now empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::SchemaViolation`] if a changed record violates its schema, or the error
+    /// encountered while validating it.
+    pub(crate) fn commit(mut self) -> Result<(), Error> {
+        self.datastore.check_schemas(&self.originals)?;
+        self.committed = true;
+        let directories: BTreeSet<&Path> = self
+            .originals
+            .keys()
+            .filter(|path| !self.datastore.path_exists(path))
+            .flat_map(|path| path.ancestors().skip(1))
+            .filter(|directory| !directory.as_os_str().is_empty())
+            .collect();
+        // Deeper directories sort after their parents, so they are removed first.
+        for directory in directories.into_iter().rev() {
+            // Only empty directories are removed, so failure here is expected and harmless.
+            if let Ok(directory) = self.datastore.confine(directory) {
+                let _ = std::fs::remove_dir(directory);
+            }
+        }
+        Ok(())
+    }
+
+    /// Undo every change made in this transaction.
+    ///
+    /// This is best-effort: every file is attempted, and the first error encountered is returned.
+    pub(crate) fn rollback(mut self) -> Result<(), Error> {
+        self.committed = true;
+        self.undo()
+    }
+
+    /// Restore every touched file and remove created directories.
+    fn undo(&mut self) -> Result<(), Error> {
+        let mut result = Ok(());
+        for (path, original) in std::mem::take(&mut self.originals) {
+            let restored = match original {
+                Some(contents) => self.datastore.write_contents(&path, &contents),
+                None if self.datastore.path_exists(&path) => self.datastore.remove_data_file(&path),
+                None => Ok(()),
+            };
+            if result.is_ok() {
+                result = restored;
+            }
+        }
+        for directory in std::mem::take(&mut self.created_directories).iter().rev() {
+            // Only empty directories are removed, so failure here is expected and harmless.
+            if let Ok(directory) = self.datastore.confine(directory) {
+                let _ = std::fs::remove_dir(directory);
+            }
+        }
+        result
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            // There is no way to report failure from a drop.
+            let _ = self.undo();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rollback_restores_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.yaml"), "x: 1\n").unwrap();
+        std::fs::write(dir.path().join("b.yaml"), "y: 2\n").unwrap();
+        let datastore = Datastore::open(dir.path());
+
+        let mut transaction = Transaction::new(&datastore);
+        transaction
+            .write(Path::new("a.yaml"), &Value::from(3))
+            .unwrap();
+        transaction.remove(Path::new("b.yaml")).unwrap();
+        transaction
+            .write(Path::new("new/c.yaml"), &Value::from(4))
+            .unwrap();
+        transaction
+            .create_directory(Path::new("empty/nested"))
+            .unwrap();
+        drop(transaction);
+
+        let a = std::fs::read_to_string(dir.path().join("a.yaml")).unwrap();
+        assert_eq!(a, "x: 1\n");
+        assert!(dir.path().join("b.yaml").is_file());
+        assert!(!dir.path().join("new").exists());
+        assert!(!dir.path().join("empty").exists());
+    }
+
+    #[test]
+    fn commit_prunes_empty_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        std::fs::write(dir.path().join("a/b/c.yaml"), "x: 1\n").unwrap();
+        std::fs::write(dir.path().join("a/keep.txt"), "").unwrap();
+        let datastore = Datastore::open(dir.path());
+
+        let mut transaction = Transaction::new(&datastore);
+        transaction.remove(Path::new("a/b/c.yaml")).unwrap();
+        transaction.commit().unwrap();
+        assert!(!dir.path().join("a/b").exists());
+        assert!(dir.path().join("a/keep.txt").is_file());
+    }
+}
@@ -0,0 +1,281 @@
+//! Lazily walking every leaf value in a datastore.
+//!
+//! See [`Datastore::walk`] for details.
+use crate::{Datastore, Error, cache::SIDECAR_DIR, keypath::KeyPath};
+use serde_yaml::Value;
+use std::{
+    collections::{HashSet, VecDeque},
+    path::{Path, PathBuf},
+};
+
+/// Error for a single file or directory that could not be walked.
+///
+/// These are yielded by [`Walk`] in place of that file's leaves, and the walk continues.
+#[derive(thiserror::Error, Debug)]
+#[error("failed to walk {}", path.display())]
+pub struct WalkError {
+    /// Path of the file or directory, relative to the datastore root.
+    pub path: PathBuf,
+
+    /// The underlying error.
+    #[source]
+    pub error: Error,
+}
+
+/// Item yielded by [`Walk`].
+pub type WalkItem = Result<(KeyPath, Value), WalkError>;
+
+/// Return whether `name` is hidden, that is, starts with a `.`.
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.starts_with('.'))
+}
+
+/// Collect every leaf beneath `keypath` in `value` into `leaves`, in document order.
+///
+/// Leaves are scalars, empty mappings and sequences, and tagged values. Mapping keys that aren't
+/// valid keypath components can't be addressed, so they are skipped.
+pub(crate) fn collect_leaves(
+    keypath: KeyPath,
+    value: Value,
+    leaves: &mut VecDeque<(KeyPath, Value)>,
+) {
+    match value {
+        Value::Mapping(mapping) if !mapping.is_empty() => {
+            for (key, child) in mapping {
+                if let Some(key) = key.as_str()
+                    && let Ok(child_keypath) = keypath.join(key)
+                {
+                    collect_leaves(child_keypath, child, leaves);
+                }
+            }
+        }
+        Value::Sequence(sequence) if !sequence.is_empty() => {
+            for (index, child) in sequence.into_iter().enumerate() {
+                if let Ok(child_keypath) = keypath.join(&index.to_string()) {
+                    collect_leaves(child_keypath, child, leaves);
+                }
+            }
+        }
+        leaf => leaves.push_back((keypath, leaf)),
+    }
+}
+
+/// Lazy iterator over every leaf in a datastore, created by [`Datastore::walk`].
+#[derive(Debug)]
+pub struct Walk<'a> {
+    /// The datastore being walked.
+    datastore: &'a Datastore,
+    /// Whether hidden files and directories are visited.
+    include_hidden: bool,
+    /// Paths still to visit, relative to the root, in reverse order.
+    pending: Vec<PathBuf>,
+    /// Leaves of the most recently parsed file that haven't been yielded yet.
+    leaves: VecDeque<(KeyPath, Value)>,
+    /// The most recently parsed file, relative to the root.
+    source: Option<PathBuf>,
+    /// Canonical paths of the directories visited so far.
+    visited: HashSet<PathBuf>,
+}
+
+impl<'a> Walk<'a> {
+    /// Create a walk starting at the root of `datastore`.
+    fn new(datastore: &'a Datastore) -> Self {
+        Self {
+            datastore,
+            include_hidden: false,
+            pending: vec![PathBuf::new()],
+            leaves: VecDeque::new(),
+            source: None,
+            visited: HashSet::new(),
+        }
+    }
+
+    /// Return the file that the most recently yielded leaf came from, relative to the root.
+    #[must_use]
+    pub fn source(&self) -> Option<&Path> {
+        self.source.as_deref()
+    }
+
+    /// Set whether hidden files and directories, whose names start with `.`, are visited.
+    ///
+    /// They are skipped by default. The [default ignore rules](crate::builder::DEFAULT_IGNORES)
+    /// skip them too, so this only has an effect when those are disabled. The [sidecar
+    /// directory](SIDECAR_DIR) and [ignored](Datastore::builder) entries are always skipped.
+    #[must_use]
+    pub fn include_hidden(mut self, include_hidden: bool) -> Self {
+        self.include_hidden = include_hidden;
+        self
+    }
+
+    /// Queue the entries of the directory at `dir` to be visited in sorted order.
+    ///
+    /// Nothing is queued if the directory was already visited through a symlink.
+    fn push_directory(&mut self, dir: &Path) -> Result<(), Error> {
+        let full_path = self.datastore.confine(dir)?;
+        if !self.visited.insert(std::fs::canonicalize(&full_path)?) {
+            return Ok(());
+        }
+        let mut entries = std::fs::read_dir(full_path)?
+            .map(|entry| entry.map(|e| dir.join(e.file_name())))
+            .collect::<Result<Vec<_>, _>>()?;
+        let datastore = self.datastore;
+        entries.retain(|path| {
+            path != Path::new(SIDECAR_DIR)
+                && (self.include_hidden || !is_hidden(path))
+                && !datastore.is_ignored(path, datastore.is_dir(path))
+        });
+        entries.sort();
+        self.pending.extend(entries.into_iter().rev());
+        Ok(())
+    }
+
+    /// Parse the data file at `path` and queue its leaves.
+    fn push_file(&mut self, path: &Path) -> Result<(), Error> {
+        let keypath = KeyPath::from_path(path)?;
+        let file_string = self.datastore.read_data_file(path)?;
+        let value: Value = serde_yaml::from_str(&file_string)?;
+        collect_leaves(keypath, value, &mut self.leaves);
+        self.source = Some(path.to_owned());
+        Ok(())
+    }
+}
+
+impl Iterator for Walk<'_> {
+    type Item = WalkItem;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(leaf) = self.leaves.pop_front() {
+                return Some(Ok(leaf));
+            }
+            let path = self.pending.pop()?;
+            let result = match self.datastore.metadata(&path) {
+                Ok(metadata) if metadata.is_dir() => self.push_directory(&path),
+                Err(error @ Error::OutsideRoot(_)) => Err(error),
+                _ if self.datastore.has_data_extension(&path) => self.push_file(&path),
+                _ => Ok(()),
+            };
+            if let Err(error) = result {
+                return Some(Err(WalkError { path, error }));
+            }
+        }
+    }
+}
+
+impl Datastore {
+    /// Walk every leaf value in the datastore, along with the keypath addressing it.
+    ///
+    /// The walk is depth-first. Directory entries are visited sorted by name, and the contents of
+    /// each file in document order. Mappings and sequences are descended into, with sequence
+    /// elements addressed by their index, so the leaves are scalars, empty mappings and
+    /// sequences, and tagged values. An empty file is a single `null` leaf.
+    ///
+    /// The walk is lazy: files are parsed one at a time as the iterator advances. Files and
+    /// directories that can't be read or parsed are yielded as a [`WalkError`] and the walk
+    /// carries on. [Ignored](Datastore::builder) files and directories are skipped, as are hidden
+    /// ones unless [`include_hidden()`](Walk::include_hidden) is set.
+    ///
+    /// Every file is walked, so if a keypath can be resolved from more than one file (for example
+    /// both `a/b.yaml` and the key `b` in `a.yaml`), it is yielded once for each. Symlinks to
+    /// directories are followed, but each directory is walked only once, so a symlink loop ends
+    /// the first time it leads back to a directory already walked.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use yaml_datastore::Datastore;
+    ///
+    /// let datastore = Datastore::open("tests/data");
+    /// for item in datastore.walk() {
+    ///     match item {
+    ///         Ok((keypath, value)) => println!("{keypath}: {value:?}"),
+    ///         Err(e) => eprintln!("{e}"),
+    ///     }
+    /// }
+    /// ```
+    #[must_use]
+    pub fn walk(&self) -> Walk<'_> {
+        Walk::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walk_test_data() {
+        let datastore = Datastore::open(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data"));
+        let items: Vec<WalkItem> = datastore.walk().collect();
+        let errors: Vec<&PathBuf> = items
+            .iter()
+            .filter_map(|i| i.as_ref().err())
+            .map(|e| &e.path)
+            .collect();
+        assert_eq!(errors, vec![Path::new("duplicate.yaml")]);
+
+        let keypaths: Vec<String> = items
+            .iter()
+            .filter_map(|i| i.as_ref().ok())
+            .map(|(k, _)| k.to_string())
+            .collect();
+        assert_eq!(
+            keypaths,
+            vec![
+                "complete.name",
+                "complete.id",
+                "complete.rating",
+                "complete.complete",
+                "complete.tags.0",
+                "complete.tags.1",
+                "complete.tags.2",
+                "complete.nested.value",
+                "empty",
+                "no_tags.name",
+                "no_tags.id",
+                "no_tags.rating",
+                "no_tags.complete",
+            ]
+        );
+    }
+
+    #[test]
+    fn walk_hidden() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("sub/a.yaml"), "x: 1").unwrap();
+        std::fs::write(dir.path().join(".hidden.yaml"), "y: 2").unwrap();
+
+        let datastore = Datastore::builder(dir.path()).default_ignores(false).open();
+        let visible: Vec<WalkItem> = datastore.walk().collect();
+        assert_eq!(visible.len(), 1);
+        assert_eq!(visible[0].as_ref().unwrap().0.to_string(), "sub.a.x");
+
+        // A hidden file's name isn't a valid keypath, so it is reported rather than walked.
+        let all: Vec<WalkItem> = datastore.walk().include_hidden(true).collect();
+        assert_eq!(all.len(), 2);
+        assert!(matches!(
+            all[0].as_ref().unwrap_err().error,
+            Error::KeyPathError(_)
+        ));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn walk_symlink_loop() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("a")).unwrap();
+        std::fs::write(dir.path().join("a/b.yaml"), "x: 1").unwrap();
+        std::os::unix::fs::symlink("..", dir.path().join("a/up")).unwrap();
+        std::os::unix::fs::symlink("a", dir.path().join("c")).unwrap();
+
+        let datastore = Datastore::open(dir.path());
+        let keypaths: Vec<String> = datastore
+            .walk()
+            .map(|item| item.unwrap().0.to_string())
+            .collect();
+        assert_eq!(keypaths, ["a.b.x"]);
+    }
+}
@@ -0,0 +1,510 @@
+//! Watching the files under a datastore's root for changes.
+//!
+//! See [`Datastore::watch`] for details. This module requires the `watch` feature.
+use crate::{Datastore, Error, cache::FileStamp, keypath::KeyPath};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+/// How often the files are checked for changes, unless [configured](Watch::poll_interval).
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long the files must go unchanged before an event is sent, unless
+/// [configured](Watch::debounce).
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// A batch of changes to the files under a datastore's root, sent by a [`Watcher`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchEvent {
+    /// The data files that were created, modified or removed, relative to the root.
+    pub paths: BTreeSet<PathBuf>,
+    /// The keypath of each changed file. Any keypath starting with one of these, or that one of
+    /// these starts with, may now resolve differently.
+    pub keypaths: BTreeSet<KeyPath>,
+    /// The message of the error from [refreshing](Datastore::refresh_indexes) the indexes before
+    /// this event was sent, if it failed. Lookups may not reflect every changed file until a
+    /// later refresh succeeds.
+    pub index_error: Option<String>,
+}
+
+impl WatchEvent {
+    /// Return whether the value resolved for `keypath` may have changed.
+    ///
+    /// This is the case if a changed file's keypath is a prefix of `keypath`, so the file may
+    /// contain it, or if `keypath` is a prefix of a changed file's keypath, so the file may be
+    /// part of it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use yaml_datastore::{keypath::KeyPath, watch::WatchEvent};
+    ///
+    /// let event = WatchEvent {
+    ///     paths: ["a/b.yaml".into()].into(),
+    ///     keypaths: [KeyPath::try_from("a.b").unwrap()].into(),
+    ///     index_error: None,
+    /// };
+    /// assert!(event.affects(&KeyPath::try_from("a").unwrap()));
+    /// assert!(event.affects(&KeyPath::try_from("a.b.c").unwrap()));
+    /// assert!(!event.affects(&KeyPath::try_from("a.c").unwrap()));
+    /// ```
+    #[must_use]
+    pub fn affects(&self, keypath: &KeyPath) -> bool {
+        self.keypaths
+            .iter()
+            .any(|changed| keypath.starts_with(changed) || changed.starts_with(keypath))
+    }
+}
+
+/// Stamps of the data files under a datastore's root, for detecting which ones change.
+struct Snapshot {
+    /// A stamp for each data file, relative to the root.
+    stamps: BTreeMap<PathBuf, FileStamp>,
+}
+
+impl Snapshot {
+    /// Take a snapshot of the data files in `datastore`.
+    fn new(datastore: &Datastore) -> Self {
+        let mut snapshot = Self {
+            stamps: BTreeMap::new(),
+        };
+        snapshot.update(datastore);
+        snapshot
+    }
+
+    /// Stamp the file at `path` if it changed since `stamp` was taken, returning whether it did.
+    ///
+    /// A file too large for the datastore's [limits](crate::limits::Limits) is only compared as
+    /// far as the limit, as it is when indexed.
+    fn restamp(
+        datastore: &Datastore,
+        path: &Path,
+        stamp: &mut Option<FileStamp>,
+    ) -> Result<bool, Error> {
+        let full_path = datastore.confine(path)?;
+        let metadata = std::fs::metadata(&full_path)?;
+        if stamp
+            .as_ref()
+            .is_some_and(|s| s.matches_metadata(&metadata))
+        {
+            return Ok(false);
+        }
+        let contents = datastore.read_bounded(&full_path)?;
+        let changed = !stamp
+            .as_ref()
+            .is_some_and(|s| s.matches_contents(&contents));
+        *stamp = Some(FileStamp::new(&metadata, &contents)?);
+        Ok(changed)
+    }
+
+    /// Bring the snapshot up to date with the files in `datastore`, returning those that changed.
+    ///
+    /// If a directory can't be listed, perhaps because it's being removed, changes beneath it
+    /// aren't reported and the next update tries again; the rest of the files are still
+    /// compared. A missing root counts as having no files.
+    fn update(&mut self, datastore: &Datastore) -> BTreeSet<PathBuf> {
+        let root = &datastore.root;
+        let mut files = Vec::new();
+        let mut unreadable = Vec::new();
+        datastore.collect_readable_files(Path::new(""), &mut files, &mut unreadable);
+        if !root.exists() {
+            unreadable.clear();
+        }
+
+        let mut changed = BTreeSet::new();
+        // Keep the stamps beneath unreadable directories, so their files aren't reported removed.
+        let mut stamps: BTreeMap<_, _> = self
+            .stamps
+            .extract_if(.., |path, _| {
+                unreadable.iter().any(|dir| path.starts_with(dir))
+            })
+            .collect();
+        for path in files {
+            let mut stamp = self.stamps.remove(&path);
+            match Self::restamp(datastore, &path, &mut stamp) {
+                Ok(true) => {
+                    changed.insert(path.clone());
+                }
+                Ok(false) => {}
+                // The file was removed while being stamped, or leads outside a sandboxed root.
+                Err(_) => continue,
+            }
+            if let Some(stamp) = stamp {
+                stamps.insert(path, stamp);
+            }
+        }
+        // Whatever is left was removed.
+        changed.extend(std::mem::replace(&mut self.stamps, stamps).into_keys());
+        changed
+    }
+}
+
+/// Create the event for a batch of changed `paths`, first refreshing any indexes so lookups
+/// reflect the changed files.
+fn prepare_event(datastore: &Datastore, paths: BTreeSet<PathBuf>) -> WatchEvent {
+    let keypaths = paths
+        .iter()
+        .filter_map(|path| KeyPath::from_path(path).ok())
+        .collect();
+    let index_error = if datastore.read_indexes().indexes.is_empty() {
+        None
+    } else {
+        datastore.refresh_indexes().err().map(|e| e.to_string())
+    };
+    WatchEvent {
+        paths,
+        keypaths,
+        index_error,
+    }
+}
+
+/// A background thread polling a datastore for changes, stopped when dropped.
+pub(crate) struct PollThread {
+    /// Dropped to tell the thread to stop.
+    stop: Option<Sender<()>>,
+    /// The polling thread.
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PollThread {
+    /// Poll `datastore` from a new thread, calling `on_change` with each debounced event until it
+    /// returns `false` or the returned handle is dropped.
+    ///
+    /// The files are snapshotted before this returns, so any later change is reported. Indexes
+    /// are refreshed before `on_change` is called.
+    pub(crate) fn spawn<F>(datastore: Arc<Datastore>, settings: &Watch, mut on_change: F) -> Self
+    where
+        F: FnMut(&WatchEvent) -> bool + Send + 'static,
+    {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let poll_interval = settings.poll_interval;
+        let debounce = settings.debounce;
+        let mut snapshot = Snapshot::new(&datastore);
+        let thread = std::thread::spawn(move || {
+            let mut pending = BTreeSet::new();
+            let mut last_change = Instant::now();
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(poll_interval) {
+                let changed = snapshot.update(&datastore);
+                if !changed.is_empty() {
+                    pending.extend(changed);
+                    last_change = Instant::now();
+                }
+                if pending.is_empty() || last_change.elapsed() < debounce {
+                    continue;
+                }
+                let event = prepare_event(&datastore, std::mem::take(&mut pending));
+                if !on_change(&event) {
+                    break;
+                }
+            }
+        });
+        Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for PollThread {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            // A panic in the thread has already been reported, and there's nothing to recover.
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Settings for watching a datastore, created by [`Datastore::watch`].
+#[derive(Debug, Clone)]
+pub struct Watch {
+    /// The datastore to watch.
+    datastore: Arc<Datastore>,
+    /// How often the files are checked for changes.
+    poll_interval: Duration,
+    /// How long the files must go unchanged before an event is sent.
+    debounce: Duration,
+}
+
+impl Watch {
+    /// Set how often the files are checked for changes. Defaults to [`DEFAULT_POLL_INTERVAL`].
+    #[must_use]
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Set how long the files must go unchanged before an event is sent. Defaults to
+    /// [`DEFAULT_DEBOUNCE`].
+    ///
+    /// Changes are only noticed when the files are polled, so the effective delay is rounded up
+    /// to a multiple of the [poll interval](Self::poll_interval).
+    #[must_use]
+    pub fn debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// The datastore being watched.
+    pub(crate) fn datastore(&self) -> &Arc<Datastore> {
+        &self.datastore
+    }
+
+    /// Start watching, returning a [`Watcher`] that receives the change events.
+    #[must_use]
+    pub fn start(self) -> Watcher {
+        let (sender, events) = mpsc::channel();
+        let thread = PollThread::spawn(self.datastore.clone(), &self, move |event| {
+            sender.send(event.clone()).is_ok()
+        });
+        Watcher {
+            events,
+            _thread: thread,
+        }
+    }
+}
+
+/// Receives change events for a datastore, created by [`Watch::start`].
+///
+/// Watching stops when this is dropped.
+pub struct Watcher {
+    /// Events sent by the polling thread.
+    events: Receiver<WatchEvent>,
+    /// The polling thread, stopped when dropped.
+    _thread: PollThread,
+}
+
+impl std::fmt::Debug for Watcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Watcher").finish_non_exhaustive()
+    }
+}
+
+impl Watcher {
+    /// Wait for the next change event.
+    ///
+    /// Returns `None` only if the polling thread has stopped, which happens if it panics.
+    #[must_use]
+    pub fn recv(&self) -> Option<WatchEvent> {
+        self.events.recv().ok()
+    }
+
+    /// Wait up to `timeout` for the next change event, returning `None` if there is none.
+    #[must_use]
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<WatchEvent> {
+        self.events.recv_timeout(timeout).ok()
+    }
+
+    /// Return the next change event if one is ready, without waiting.
+    #[must_use]
+    pub fn try_recv(&self) -> Option<WatchEvent> {
+        self.events.try_recv().ok()
+    }
+}
+
+impl Datastore {
+    /// Watch the files under the root for changes.
+    ///
+    /// The returned [`Watch`] can be configured, then [started](Watch::start) to get a
+    /// [`Watcher`] that receives a [`WatchEvent`] for each batch of changes. Changes are
+    /// debounced, so a burst of writes produces a single event once the files have stopped
+    /// changing. Each event names the changed files and their keypaths; use
+    /// [`WatchEvent::affects`] to check whether a keypath may resolve differently.
+    ///
+    /// Before each event is sent, any [indexes](Datastore::create_index) are
+    /// [refreshed](Datastore::refresh_indexes), so lookups reflect the changed files. If that
+    /// fails, the error is in [`WatchEvent::index_error`]. Indexes are the only thing a datastore
+    /// caches: every other read parses the files it needs afresh, so nothing else can go stale.
+    ///
+    /// Files are detected by polling rather than by operating system notifications, so this
+    /// works on any filesystem, but changes are noticed only on the next
+    /// [poll](Watch::poll_interval). Only files that contribute to keypaths are watched: ignored
+    /// files and files whose names aren't valid keypath components are ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::{sync::Arc, time::Duration};
+    /// use yaml_datastore::Datastore;
+    ///
+    /// let datastore = Arc::new(Datastore::open("config"));
+    /// let watcher = datastore.watch().poll_interval(Duration::from_secs(1)).start();
+    /// while let Some(event) = watcher.recv() {
+    ///     for keypath in &event.keypaths {
+    ///         println!("{keypath} changed");
+    ///     }
+    /// }
+    /// ```
+    #[must_use]
+    pub fn watch(self: &Arc<Self>) -> Watch {
+        Watch {
+            datastore: Arc::clone(self),
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            debounce: DEFAULT_DEBOUNCE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, path: &str, contents: &str) {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    fn paths(changed: &BTreeSet<PathBuf>) -> Vec<&Path> {
+        changed.iter().map(PathBuf::as_path).collect()
+    }
+
+    #[test]
+    fn snapshot_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.yaml", "b: 1");
+        write(dir.path(), "c.yaml", "d: 1");
+        let datastore = Datastore::open(dir.path());
+        let mut snapshot = Snapshot::new(&datastore);
+
+        write(dir.path(), "a/b.yaml", "x: 1");
+        std::fs::remove_file(dir.path().join("c.yaml")).unwrap();
+        write(dir.path(), ".hidden.yaml", "x: 1");
+        let changed = snapshot.update(&datastore);
+        assert_eq!(
+            paths(&changed),
+            vec![Path::new("a/b.yaml"), Path::new("c.yaml")]
+        );
+        let event = prepare_event(&datastore, changed);
+        assert!(event.affects(&KeyPath::try_from("a").unwrap()));
+        assert!(!event.affects(&KeyPath::try_from("e").unwrap()));
+
+        // Touching a file without changing it isn't reported.
+        write(dir.path(), "a.yaml", "b: 1");
+        assert!(snapshot.update(&datastore).is_empty());
+        write(dir.path(), "a.yaml", "b: 2");
+        assert_eq!(
+            paths(&snapshot.update(&datastore)),
+            vec![Path::new("a.yaml")]
+        );
+
+        // A missing root has no files.
+        std::fs::remove_dir_all(dir.path()).unwrap();
+        assert_eq!(
+            paths(&snapshot.update(&datastore)),
+            vec![Path::new("a/b.yaml"), Path::new("a.yaml")]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sandboxed_snapshot_ignores_outside_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "outside.yaml", "secret: 1");
+        write(dir.path(), "root/a.yaml", "x: 1");
+        let root = dir.path().join("root");
+        std::os::unix::fs::symlink(dir.path().join("outside.yaml"), root.join("link.yaml"))
+            .unwrap();
+        let sandboxed = Datastore::builder(&root).sandbox(true).open();
+        let mut sandboxed_snapshot = Snapshot::new(&sandboxed);
+        let datastore = Datastore::open(&root);
+        let mut snapshot = Snapshot::new(&datastore);
+
+        write(dir.path(), "outside.yaml", "secret: 2");
+        assert!(sandboxed_snapshot.update(&sandboxed).is_empty());
+        assert_eq!(
+            paths(&snapshot.update(&datastore)),
+            vec![Path::new("link.yaml")]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn snapshot_skips_unreadable_directories() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a/b.yaml", "x: 1");
+        write(dir.path(), "c.yaml", "d: 1");
+        let datastore = Datastore::open(dir.path());
+        let mut snapshot = Snapshot::new(&datastore);
+
+        let locked = dir.path().join("a");
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+        let readable = std::fs::read_dir(&locked).is_ok();
+        write(dir.path(), "c.yaml", "d: 2");
+        let changed = snapshot.update(&datastore);
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+        if readable {
+            // Permissions aren't enforced, such as when running as root.
+            return;
+        }
+        // `a/b.yaml` isn't reported removed, and `c.yaml` is still reported changed.
+        assert_eq!(paths(&changed), vec![Path::new("c.yaml")]);
+        assert!(snapshot.update(&datastore).is_empty());
+    }
+
+    #[test]
+    fn event_refreshes_indexes() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "a.yaml", "id: 1");
+        let datastore = Datastore::open(dir.path());
+        let mut snapshot = Snapshot::new(&datastore);
+        datastore.create_index("by_id", "*", "id").unwrap();
+
+        write(dir.path(), "a.yaml", "id: 2");
+        prepare_event(&datastore, snapshot.update(&datastore));
+        let found: Vec<String> = datastore
+            .lookup("by_id", 2)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(found, vec!["a"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn event_reports_index_error() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "outside.yaml", "id: 2");
+        write(dir.path(), "root/a.yaml", "id: 1");
+        let root = dir.path().join("root");
+        let datastore = Datastore::builder(&root).sandbox(true).open();
+        let mut snapshot = Snapshot::new(&datastore);
+        datastore.create_index("by_id", "*", "id").unwrap();
+
+        write(&root, "a.yaml", "id: 3");
+        let event = prepare_event(&datastore, snapshot.update(&datastore));
+        assert!(event.index_error.is_none());
+
+        std::os::unix::fs::symlink(dir.path().join("outside.yaml"), root.join("link.yaml"))
+            .unwrap();
+        write(&root, "a.yaml", "id: 4");
+        let event = prepare_event(&datastore, snapshot.update(&datastore));
+        let error = event.index_error.unwrap();
+        assert!(error.contains("link.yaml"), "{error}");
+    }
+
+    #[test]
+    fn watcher_sends_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = Arc::new(Datastore::open(dir.path()));
+        let watcher = datastore
+            .watch()
+            .poll_interval(Duration::from_millis(10))
+            .debounce(Duration::ZERO)
+            .start();
+
+        write(dir.path(), "a.yaml", "b: 1");
+        let event = watcher.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(event.paths, [PathBuf::from("a.yaml")].into());
+    }
+}